@@ -0,0 +1,472 @@
+//! Archipelago patch files (`.apgbc`, `.aplttp`, …).
+//!
+//! These are zip files with an `archipelago.json` manifest, naming the MD5 of
+//! the base ROM, and the files of a procedure that turns the base ROM into the
+//! seed's ROM. The patched ROM is cached next to the patch, so its SRAM and
+//! hashes are the ones the Archipelago client expects. The MD5s of the patch,
+//! base ROM and patched ROM are stored next to it to tell whether it is still
+//! the one the patch produces.
+
+use std::fs::{self, File};
+use std::io::{self, Cursor, Read, Seek};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, ensure, Context, Result};
+use md5::{Digest, Md5};
+use serde::{Deserialize, Serialize};
+use zip::ZipArchive;
+
+use crate::{patch, util};
+
+const MANIFEST_NAME: &str = "archipelago.json";
+/// Appended to the patched ROM's file name for the file with its [`Cache`] entry.
+const CACHE_EXTENSION: &str = "apcache";
+/// Size of the copier header some SNES dumps start with.
+const COPIER_HEADER_LEN: usize = 512;
+
+#[derive(Deserialize)]
+struct Manifest {
+    #[serde(default)]
+    game: Option<String>,
+    base_checksum: Option<String>,
+    result_file_ending: String,
+    /// Patches from before procedures were introduced only contain a bsdiff4 delta.
+    #[serde(default = "default_procedure")]
+    procedure: Vec<(String, Vec<String>)>,
+}
+
+fn default_procedure() -> Vec<(String, Vec<String>)> {
+    vec![("apply_bsdiff4".into(), vec!["delta.bsdiff4".into()])]
+}
+
+/// What a patched ROM was made from.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct Cache {
+    patch_md5: String,
+    base_md5: String,
+    rom_md5: String,
+}
+
+/// Whether `path` looks like an Archipelago patch: an `.ap*` zip file.
+pub fn is_patch(path: &Path) -> bool {
+    let has_ap_extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| extension.starts_with("ap"));
+
+    has_ap_extension && is_zip(path)
+}
+
+fn is_zip(path: &Path) -> bool {
+    let mut magic = [0; 4];
+
+    File::open(path)
+        .and_then(|mut file| file.read_exact(&mut magic))
+        .is_ok_and(|()| magic == *b"PK\x03\x04")
+}
+
+/// Applies the patch at `patch_path` to the matching ROM in `base_rom_dir`
+/// and returns the path of the patched ROM, next to the patch.
+///
+/// The patched ROM is reused if it was made from the same patch and base ROM
+/// and hasn't been modified since.
+pub fn apply(patch_path: &Path, base_rom_dir: &Path) -> Result<PathBuf> {
+    let patch = fs::read(patch_path).with_context(|| format!("failed to read {patch_path:?}"))?;
+    let mut archive = ZipArchive::new(Cursor::new(&patch)).context("patch is not a zip file")?;
+    let manifest = read_file(&mut archive, MANIFEST_NAME)?;
+    let manifest = serde_json::from_slice::<Manifest>(&manifest)
+        .with_context(|| format!("invalid {MANIFEST_NAME}"))?;
+
+    let extension = manifest.result_file_ending.trim_start_matches('.');
+    let rom_path = patch_path.with_extension(extension);
+    let cache_path = cache_path(&rom_path);
+    let base_checksum = manifest
+        .base_checksum
+        .as_deref()
+        .context("patch doesn't name its base ROM")?;
+    let patch_md5 = md5_hex(&patch);
+    let base_md5 = base_checksum.to_ascii_lowercase();
+
+    if is_cached(&rom_path, &cache_path, &patch_md5, &base_md5) {
+        eprintln!("Using previously patched ROM {rom_path:?}");
+        return Ok(rom_path);
+    }
+
+    let mut rom = find_base_rom(base_rom_dir, base_checksum).with_context(|| {
+        let game = manifest.game.as_deref().unwrap_or("the game");
+        format!("no base ROM for {game} with MD5 {base_checksum} in {base_rom_dir:?}")
+    })?;
+
+    for (step, args) in &manifest.procedure {
+        rom = apply_step(&mut archive, step, args, rom)
+            .with_context(|| format!("failed to apply patch step `{step}`"))?;
+    }
+
+    fs::write(&rom_path, &rom).with_context(|| format!("failed to write {rom_path:?}"))?;
+
+    let cache = Cache {
+        patch_md5,
+        base_md5,
+        rom_md5: md5_hex(&rom),
+    };
+
+    util::write_atomically(&cache_path, &serde_json::to_vec_pretty(&cache)?)?;
+
+    eprintln!("Patched {patch_path:?} to {rom_path:?}");
+
+    Ok(rom_path)
+}
+
+fn apply_step<R: Read + Seek>(
+    archive: &mut ZipArchive<R>,
+    step: &str,
+    args: &[String],
+    mut rom: Vec<u8>,
+) -> Result<Vec<u8>> {
+    let file_arg = |archive: &mut ZipArchive<R>| -> Result<Vec<u8>> {
+        let name = args.first().context("missing file argument")?;
+
+        read_file(archive, name)
+    };
+
+    Ok(match step {
+        "apply_bsdiff4" => patch::bsdiff::apply(&rom, &file_arg(archive)?)?,
+        "apply_bps" => patch::bps::apply(&rom, &file_arg(archive)?)?,
+        "apply_tokens" => {
+            apply_tokens(&mut rom, &file_arg(archive)?)?;
+            rom
+        }
+        "calc_snes_crc" => {
+            calc_snes_crc(&mut rom);
+            rom
+        }
+        _ => bail!("unsupported patch step"),
+    })
+}
+
+/// Token types of `apply_tokens`.
+const TOKEN_WRITE: u8 = 0;
+const TOKEN_COPY: u8 = 1;
+const TOKEN_RLE: u8 = 2;
+const TOKEN_AND_8: u8 = 3;
+const TOKEN_OR_8: u8 = 4;
+const TOKEN_XOR_8: u8 = 5;
+
+/// Applies Archipelago's token format: a count, then `type, offset, size, data`
+/// entries, all numbers being little endian `u32`s.
+fn apply_tokens(rom: &mut Vec<u8>, tokens: &[u8]) -> Result<()> {
+    let mut pos = 0;
+    let read_u32 = |tokens: &[u8], pos: &mut usize| -> Result<usize> {
+        let bytes = tokens.get(*pos..*pos + 4).context("tokens are truncated")?;
+
+        *pos += 4;
+
+        Ok(u32::from_le_bytes(bytes.try_into().unwrap()) as usize)
+    };
+
+    let count = read_u32(tokens, &mut pos)?;
+
+    for _ in 0..count {
+        let kind = *tokens.get(pos).context("tokens are truncated")?;
+        pos += 1;
+        let offset = read_u32(tokens, &mut pos)?;
+        let size = read_u32(tokens, &mut pos)?;
+        let data = tokens
+            .get(pos..)
+            .and_then(|tokens| tokens.get(..size))
+            .context("tokens are truncated")?;
+        pos += size;
+
+        let arg_u32 = |index: usize| -> Result<usize> {
+            let bytes = data
+                .get(index * 4..index * 4 + 4)
+                .context("token is truncated")?;
+
+            Ok(u32::from_le_bytes(bytes.try_into().unwrap()) as usize)
+        };
+
+        match kind {
+            TOKEN_WRITE => patch::write(rom, offset, data)?,
+            TOKEN_COPY => {
+                let (len, source) = (arg_u32(0)?, arg_u32(1)?);
+                let bytes = source
+                    .checked_add(len)
+                    .and_then(|end| rom.get(source..end))
+                    .context("copy token reads out of bounds")?
+                    .to_vec();
+
+                patch::write(rom, offset, &bytes)?;
+            }
+            TOKEN_RLE => {
+                let (len, value) = (arg_u32(0)?, arg_u32(1)?);
+
+                patch::fill(rom, offset, len, value as u8)?;
+            }
+            TOKEN_AND_8 | TOKEN_OR_8 | TOKEN_XOR_8 => {
+                let arg = *data.first().context("token is truncated")?;
+                let byte = rom.get_mut(offset).context("token writes out of bounds")?;
+
+                match kind {
+                    TOKEN_AND_8 => *byte &= arg,
+                    TOKEN_OR_8 => *byte |= arg,
+                    _ => *byte ^= arg,
+                }
+            }
+            _ => bail!("unknown token type {kind}"),
+        }
+    }
+
+    Ok(())
+}
+
+/// Fixes the checksum in the header of a LoROM SNES ROM.
+fn calc_snes_crc(rom: &mut [u8]) {
+    const CHECKSUM_OFFSET: usize = 0x7FDC;
+
+    if rom.len() < CHECKSUM_OFFSET + 4 {
+        return;
+    }
+
+    let sum = rom[..CHECKSUM_OFFSET]
+        .iter()
+        .chain(&rom[CHECKSUM_OFFSET + 4..])
+        .fold(0u32, |sum, &byte| sum.wrapping_add(u32::from(byte)));
+    // the checksum and its complement always add 0x1FE
+    let checksum = (sum.wrapping_add(0x1FE) & 0xFFFF) as u16;
+    let complement = checksum ^ 0xFFFF;
+
+    rom[CHECKSUM_OFFSET..CHECKSUM_OFFSET + 2].copy_from_slice(&complement.to_le_bytes());
+    rom[CHECKSUM_OFFSET + 2..CHECKSUM_OFFSET + 4].copy_from_slice(&checksum.to_le_bytes());
+}
+
+/// Finds the file in `dir` with the given MD5, ignoring SNES copier headers.
+fn find_base_rom(dir: &Path, md5: &str) -> Result<Vec<u8>> {
+    let entries = fs::read_dir(dir).with_context(|| format!("failed to read {dir:?}"))?;
+
+    for entry in entries {
+        let path = entry?.path();
+
+        if !path.is_file() {
+            continue;
+        }
+
+        let Ok(rom) = fs::read(&path) else {
+            continue;
+        };
+
+        if md5_hex(&rom).eq_ignore_ascii_case(md5) {
+            eprintln!("Found base ROM {path:?}");
+            return Ok(rom);
+        }
+
+        if rom.len() % 1024 == COPIER_HEADER_LEN {
+            let rom = &rom[COPIER_HEADER_LEN..];
+
+            if md5_hex(rom).eq_ignore_ascii_case(md5) {
+                eprintln!("Found base ROM {path:?} with copier header");
+                return Ok(rom.to_vec());
+            }
+        }
+    }
+
+    bail!("base ROM not found")
+}
+
+fn read_file<R: Read + Seek>(archive: &mut ZipArchive<R>, name: &str) -> Result<Vec<u8>> {
+    let mut file = archive
+        .by_name(name)
+        .with_context(|| format!("patch contains no {name}"))?;
+    let mut data = Vec::new();
+
+    file.read_to_end(&mut data)
+        .with_context(|| format!("failed to read {name} from patch"))?;
+
+    Ok(data)
+}
+
+/// The file next to the patched ROM recording what it was made from, e.g. `seed.sfc.apcache`.
+fn cache_path(rom_path: &Path) -> PathBuf {
+    let mut file_name = rom_path.file_name().unwrap_or_default().to_owned();
+
+    file_name.push(".");
+    file_name.push(CACHE_EXTENSION);
+
+    rom_path.with_file_name(file_name)
+}
+
+/// Whether the ROM at `rom_path` was patched from the patch and base ROM with
+/// the given MD5s and is unmodified.
+fn is_cached(rom_path: &Path, cache_path: &Path, patch_md5: &str, base_md5: &str) -> bool {
+    let read_cache = || -> Result<Cache> { Ok(serde_json::from_slice(&fs::read(cache_path)?)?) };
+    let cache = match read_cache() {
+        Ok(cache) => cache,
+        Err(err) => {
+            let is_missing = err
+                .downcast_ref::<io::Error>()
+                .is_some_and(|err| err.kind() == io::ErrorKind::NotFound);
+
+            if !is_missing {
+                eprintln!("Ignoring invalid patch cache {cache_path:?}: {err:#}");
+            }
+
+            return false;
+        }
+    };
+
+    cache.patch_md5 == patch_md5
+        && cache.base_md5 == base_md5
+        && fs::read(rom_path).is_ok_and(|rom| md5_hex(&rom) == cache.rom_md5)
+}
+
+fn md5_hex(data: &[u8]) -> String {
+    hex::encode(Md5::digest(data))
+}
+
+/// Resolves `rom` to a loadable ROM, applying it first if it is an Archipelago patch.
+pub fn resolve_rom(rom: &Path, base_rom_dir: Option<&Path>) -> Result<PathBuf> {
+    if !is_patch(rom) {
+        return Ok(rom.to_owned());
+    }
+
+    let patch_dir = rom.parent().unwrap_or(Path::new("."));
+    let base_rom_dir = base_rom_dir.unwrap_or(patch_dir);
+
+    ensure!(
+        base_rom_dir.is_dir(),
+        "base ROM directory {base_rom_dir:?} does not exist"
+    );
+
+    apply(rom, base_rom_dir).with_context(|| format!("failed to apply Archipelago patch {rom:?}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/patch");
+
+    /// A fresh directory with the patch and its base ROM.
+    fn patch_directory() -> TempDir {
+        let temp = TempDir::new().unwrap();
+        let dir = temp.path();
+        let fixtures = Path::new(FIXTURES);
+
+        fs::create_dir_all(dir.join("base")).unwrap();
+        fs::copy(fixtures.join("seed.aptest"), dir.join("seed.aptest")).unwrap();
+        fs::copy(fixtures.join("source.bin"), dir.join("base/base.gb")).unwrap();
+
+        temp
+    }
+
+    #[test]
+    fn applies_procedure_and_reuses_the_result() {
+        let temp = patch_directory();
+        let dir = temp.path();
+        let expected = fs::read(Path::new(FIXTURES).join("seed-patched.gb")).unwrap();
+
+        let rom_path = apply(&dir.join("seed.aptest"), &dir.join("base")).unwrap();
+
+        assert_eq!(rom_path, dir.join("seed.gb"));
+        assert_eq!(fs::read(&rom_path).unwrap(), expected);
+        assert!(dir.join("seed.gb.apcache").is_file());
+
+        // the base ROM isn't needed while the cache is valid
+        fs::remove_file(dir.join("base/base.gb")).unwrap();
+        assert_eq!(
+            apply(&dir.join("seed.aptest"), &dir.join("base")).unwrap(),
+            rom_path
+        );
+
+        // a modified ROM is patched again, which now fails without the base ROM
+        fs::write(&rom_path, b"modified").unwrap();
+        assert!(apply(&dir.join("seed.aptest"), &dir.join("base")).is_err());
+    }
+
+    #[test]
+    fn finds_base_roms_with_copier_headers() {
+        let temp = patch_directory();
+        let dir = temp.path();
+        let source = fs::read(Path::new(FIXTURES).join("source.bin")).unwrap();
+        let mut headered = vec![0; COPIER_HEADER_LEN];
+
+        headered.extend_from_slice(&source);
+        headered.resize(1024 + COPIER_HEADER_LEN, 0xFF);
+        fs::write(dir.join("base/base.gb"), &headered).unwrap();
+
+        let rom = &headered[COPIER_HEADER_LEN..];
+
+        assert_eq!(
+            find_base_rom(&dir.join("base"), &md5_hex(rom)).unwrap(),
+            rom
+        );
+        assert!(find_base_rom(&dir.join("base"), &md5_hex(&source)).is_err());
+    }
+
+    #[test]
+    fn applies_tokens() {
+        let mut tokens = 4u32.to_le_bytes().to_vec();
+        let mut token = |kind: u8, offset: u32, data: &[u8]| {
+            tokens.push(kind);
+            tokens.extend_from_slice(&offset.to_le_bytes());
+            tokens.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            tokens.extend_from_slice(data);
+        };
+
+        token(TOKEN_WRITE, 1, b"AB");
+        token(TOKEN_COPY, 4, &[2, 0, 0, 0, 1, 0, 0, 0]);
+        token(TOKEN_AND_8, 0, &[0x0F]);
+        token(TOKEN_OR_8, 3, &[0x80]);
+
+        let mut rom = vec![0xFF, 0, 0, 0];
+
+        apply_tokens(&mut rom, &tokens).unwrap();
+
+        assert_eq!(rom, [0x0F, b'A', b'B', 0x80, b'A', b'B']);
+    }
+
+    #[test]
+    fn rejects_invalid_tokens() {
+        let mut rom = vec![0; 4];
+
+        // a copy from past the end of the ROM
+        let mut tokens = 1u32.to_le_bytes().to_vec();
+        tokens.push(TOKEN_COPY);
+        tokens.extend_from_slice(&0u32.to_le_bytes());
+        tokens.extend_from_slice(&8u32.to_le_bytes());
+        tokens.extend_from_slice(&[4, 0, 0, 0, 2, 0, 0, 0]);
+
+        assert!(apply_tokens(&mut rom, &tokens).is_err());
+
+        // a run of 4 GiB
+        let mut tokens = 1u32.to_le_bytes().to_vec();
+        tokens.push(TOKEN_RLE);
+        tokens.extend_from_slice(&0u32.to_le_bytes());
+        tokens.extend_from_slice(&8u32.to_le_bytes());
+        tokens.extend_from_slice(&[0xFF, 0xFF, 0xFF, 0xFF, 0x42, 0, 0, 0]);
+
+        let err = apply_tokens(&mut rom, &tokens).unwrap_err();
+
+        assert!(err.to_string().contains("maximum ROM size"), "{err}");
+        assert_eq!(rom.len(), 4);
+        assert!(apply_tokens(&mut rom, &[1, 0, 0, 0, 9]).is_err());
+        assert!(apply_tokens(&mut rom, &[1, 0]).is_err());
+    }
+
+    #[test]
+    fn calculates_snes_checksums() {
+        let mut rom = vec![0; 0x8000];
+
+        calc_snes_crc(&mut rom);
+
+        assert_eq!(rom[0x7FDC..0x7FE0], [0x01, 0xFE, 0xFE, 0x01]);
+
+        // the previous checksum doesn't count towards the new one
+        let mut rom = vec![1; 0x8000];
+
+        calc_snes_crc(&mut rom);
+
+        assert_eq!(rom[0x7FDC..0x7FE0], [0x05, 0x7E, 0xFA, 0x81]);
+    }
+}
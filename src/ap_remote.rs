@@ -1,19 +1,18 @@
+use std::collections::BTreeMap;
 use std::io::{BufRead, BufReader, Write};
-use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream, UdpSocket};
-use std::ops::{Range, RangeInclusive};
-use std::sync::Arc;
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
 use std::time::Duration;
 use std::{str, thread};
 
 use anyhow::{anyhow, Context, Error, Result};
 
 use base64::Engine;
-use itertools::Itertools;
-use parking_lot::Mutex;
 use serde::{de, Deserialize, Deserializer, Serializer};
-use sha1::{Digest, Sha1};
 
 use crate::core::{self, Core};
+use crate::memory_domain;
+use crate::osd::Notifications;
+use crate::system::System;
 
 mod request;
 use request::*;
@@ -24,16 +23,20 @@ use response::*;
 const VERSION: u8 = 1;
 const FIRST_PORT: u16 = 43055;
 const NUM_PORTS: u16 = 5;
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(60);
+/// How long a locked client may stay silent before the lock is released
+/// and the client is disconnected.
+const LOCK_TIMEOUT: Duration = Duration::from_secs(5);
 
-pub fn start(core_handle: core::Handle) {
+pub fn start(core_handle: core::Handle, notifications: Notifications) {
     thread::spawn(move || {
-        if let Err(err) = try_start(core_handle) {
+        if let Err(err) = try_start(core_handle, notifications) {
             eprintln!("ap remote interface stopped with error: {err:#?}");
         }
     });
 }
 
-fn try_start(core_handle: core::Handle) -> Result<()> {
+fn try_start(core_handle: core::Handle, notifications: Notifications) -> Result<()> {
     let socket = bind_socket().context("failed to create socket")?;
 
     loop {
@@ -46,21 +49,26 @@ fn try_start(core_handle: core::Handle) -> Result<()> {
         };
 
         let core_handle = core_handle.clone();
+        let notifications = notifications.clone();
 
-        thread::spawn(move || handle_client(stream, core_handle));
+        thread::spawn(move || handle_client(stream, core_handle, notifications));
     }
 }
 
-fn handle_client(stream: TcpStream, core_handle: core::Handle) {
-    if let Err(err) = try_handle_client(stream, core_handle) {
+fn handle_client(stream: TcpStream, core_handle: core::Handle, notifications: Notifications) {
+    if let Err(err) = try_handle_client(stream, core_handle, notifications) {
         eprintln!("Error handling ap remote client: {err:?}");
     }
 }
 
-fn try_handle_client(stream: TcpStream, core_handle: core::Handle) -> Result<()> {
+fn try_handle_client(
+    stream: TcpStream,
+    core_handle: core::Handle,
+    notifications: Notifications,
+) -> Result<()> {
     // TODO: move to tokio for proper message receive timeouts
-    stream.set_read_timeout(Some(Duration::from_secs(60)))?;
-    stream.set_write_timeout(Some(Duration::from_secs(60)))?;
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
 
     let mut stream = BufReader::new(stream);
 
@@ -72,10 +80,11 @@ fn try_handle_client(stream: TcpStream, core_handle: core::Handle) -> Result<()>
         };
 
         let responses;
+        let notifications = notifications.clone();
 
         (responses, stream) = core_handle
             .run(move |core| {
-                let responses = handle_requests(requests, core, &mut stream);
+                let responses = handle_requests(requests, core, &notifications, &mut stream);
                 (responses, stream)
             })
             .context("failed to run in core")?;
@@ -116,65 +125,96 @@ fn parse_requests(request: &str) -> Result<Vec<Request>> {
     Ok(request)
 }
 
+/// Handles a batch of requests on the core thread.
+///
+/// While a client holds the lock, the core thread stays parked in here and keeps
+/// exchanging batches with the client until it unlocks, disconnects or stays
+/// silent for longer than [`LOCK_TIMEOUT`].
 fn handle_requests(
     mut requests: Vec<Request>,
     core: &mut Core,
+    notifications: &Notifications,
     stream: &mut BufReader<TcpStream>,
 ) -> Result<Option<Vec<Response>>> {
     let mut is_locked = false;
-    let mut failed_guard: Option<Response> = None;
 
-    let responses = loop {
-        let mut responses = Vec::with_capacity(requests.len());
+    loop {
+        let responses = handle_request_batch(requests, core, notifications, &mut is_locked);
 
-        for request in requests {
-            if let Some(failed_guard) = &failed_guard {
-                responses.push(failed_guard.clone())
-            }
+        if !is_locked {
+            return Ok(Some(responses));
+        }
 
-            let response = handle_request(request, core)?;
+        send_responses(stream, responses).context("failed to send responses")?;
 
-            match response {
-                Response::Locked => is_locked = true,
-                Response::Unlocked => is_locked = false,
-                Response::GuardResponse { .. } => {
-                    failed_guard = Some(response);
-                    continue;
-                }
-                _ => {}
-            }
+        stream.get_ref().set_read_timeout(Some(LOCK_TIMEOUT))?;
+        let next_requests = receive_requests(stream);
+        stream.get_ref().set_read_timeout(Some(CLIENT_TIMEOUT))?;
 
-            responses.push(response);
+        requests = match next_requests.context("failed to receive requests while locked")? {
+            Some(requests) => requests,
+            None => return Ok(None),
         }
+    }
+}
 
-        if !is_locked {
-            break responses;
+/// Produces exactly one response per request.
+///
+/// Once a guard fails, every remaining request of the batch is answered with
+/// the failed guard's response instead of being executed.
+fn handle_request_batch(
+    requests: Vec<Request>,
+    core: &mut Core,
+    notifications: &Notifications,
+    is_locked: &mut bool,
+) -> Vec<Response> {
+    let mut responses = Vec::with_capacity(requests.len());
+    let mut failed_guard: Option<Response> = None;
+
+    for request in requests {
+        if let Some(failed_guard) = &failed_guard {
+            responses.push(failed_guard.clone());
+            continue;
         }
 
-        send_responses(stream, responses).context("failed to send responses")?;
+        let response =
+            handle_request(request, core, notifications, is_locked).unwrap_or_else(|err| {
+                Response::Error {
+                    err: format!("{err:#}"),
+                }
+            });
 
-        requests = match receive_requests(stream).context("failed to receive requests")? {
-            Some(requests) => requests,
-            None => return Ok(None),
+        if let Response::GuardResponse { value: false, .. } = response {
+            failed_guard = Some(response.clone());
         }
-    };
 
-    Ok(Some(responses))
+        responses.push(response);
+    }
+
+    responses
 }
 
-fn handle_request(request: Request, core: &mut Core) -> Result<Response> {
+fn handle_request(
+    request: Request,
+    core: &mut Core,
+    notifications: &Notifications,
+    is_locked: &mut bool,
+) -> Result<Response> {
     Ok(match request {
         Request::Version => Response::Version,
         Request::Ping => {
             eprintln!("Received ping from ap remote client");
             Response::Pong
         }
-        Request::System => Response::SystemResponse {
-            // TODO: report correct system
-            value: "GBA".into(),
-        },
-        Request::PreferredCores => Response::Error {
-            err: format!("TODO: unimplemented command: PreferredCores"),
+        Request::System => {
+            let system = System::detect(core).context("unknown system")?;
+
+            Response::SystemResponse {
+                value: system.bizhawk_id().into(),
+            }
+        }
+        Request::PreferredCores => Response::PreferredCoresResponse {
+            value: preferred_cores(core),
         },
         Request::Hash => Response::HashResponse {
             value: core.get_sha1_romhash(),
@@ -183,78 +223,65 @@ fn handle_request(request: Request, core: &mut Core) -> Result<Response> {
             address,
             expected_data,
             domain,
-        } => match &*domain {
-            "ROM" => core.rom(|rom| {
-                let start = address.min(rom.len());
-                let end = address.saturating_add(expected_data.len()).min(rom.len());
-                let data = &rom[start..end];
-                let is_match = data == expected_data;
-
-                if expected_data.len() != data.len() {
-                    eprintln!("WARNING: incomplete read");
-                }
+        } => {
+            let Some(domain) = memory_domain::find(core, &domain) else {
+                return Ok(unknown_domain(&domain));
+            };
 
-                Response::GuardResponse {
-                    value: is_match,
-                    address,
-                }
-            }),
-            "System Bus" => {
-                let max_len = expected_data.len();
-                let data = core.get_memory(address, max_len);
-                let is_match = data == expected_data;
-
-                if expected_data.len() != data.len() {
-                    eprintln!("WARNING: incomplete read");
-                }
+            let data = domain.read(core, address, expected_data.len());
+            let is_match = data == expected_data;
 
-                Response::GuardResponse {
-                    value: is_match,
-                    address,
-                }
+            if expected_data.len() != data.len() {
+                eprintln!("WARNING: incomplete read");
             }
-            _ => Response::Error {
-                err: format!("Unknown memory domain: {domain:?}"),
-            },
-        },
-        Request::Lock => Response::Error {
-            err: format!("TODO: unimplemented command: Lock"),
-        },
-        Request::Unlock => Response::Error {
-            err: format!("TODO: unimplemented command: Unlock"),
-        },
+
+            Response::GuardResponse {
+                value: is_match,
+                address,
+            }
+        }
+        Request::Lock => {
+            // Locking again is a no-op, like in BizHawk's connector
+            *is_locked = true;
+            Response::Locked
+        }
+        Request::Unlock => {
+            *is_locked = false;
+            Response::Unlocked
+        }
         Request::Read {
             address,
             size,
             domain,
-        } => match &*domain {
-            "ROM" => core.rom(|rom| {
-                let start = address.min(rom.len());
-                let end = address.saturating_add(size).min(rom.len());
-                let data = rom[start..end].to_vec();
-
-                if size != data.len() {
-                    eprintln!("WARNING: incomplete read");
-                }
+        } => {
+            let Some(domain) = memory_domain::find(core, &domain) else {
+                return Ok(unknown_domain(&domain));
+            };
 
-                Response::ReadResponse { value: data }
-            }),
-            "System Bus" => {
-                let max_len = size;
-                let data = core.get_memory(address, max_len);
+            let data = domain.read(core, address, size);
 
-                if size != data.len() {
-                    eprintln!("WARNING: incomplete read");
-                }
+            if size != data.len() {
+                eprintln!("WARNING: incomplete read");
+            }
 
-                Response::ReadResponse { value: data }
+            Response::ReadResponse { value: data }
+        }
+        Request::Write {
+            address,
+            value,
+            domain,
+        } => {
+            let Some(domain) = memory_domain::find(core, &domain) else {
+                return Ok(unknown_domain(&domain));
+            };
+
+            if !domain.is_writable() {
+                return Ok(Response::Error {
+                    err: format!("Memory domain {:?} is read-only", domain.name()),
+                });
             }
-            _ => Response::Error {
-                err: format!("Unknown memory domain: {domain:?}"),
-            },
-        },
-        Request::Write { address, value } => {
-            let bytes_written = core.write_memory(address, &value);
+
+            let bytes_written = domain.write(core, address, &value);
 
             if value.len() != bytes_written {
                 eprintln!("WARNING: incomplete write!");
@@ -262,15 +289,72 @@ fn handle_request(request: Request, core: &mut Core) -> Result<Response> {
 
             Response::WriteResponse
         }
-        Request::DisplayMessage { message } => Response::Error {
-            err: format!("TODO: unimplemented command: DisplayMessage"),
+        Request::MemorySize { domain } => {
+            let Some(domain) = memory_domain::find(core, &domain) else {
+                return Ok(unknown_domain(&domain));
+            };
+
+            Response::MemorySizeResponse {
+                value: domain.size(core),
+            }
+        }
+        Request::MemoryDomains => Response::MemoryDomainsResponse {
+            value: memory_domain::domains(core)
+                .iter()
+                .map(|domain| domain.name().to_owned())
+                .collect(),
         },
-        Request::SetMessageInterval { value } => Response::Error {
-            err: format!("TODO: unimplemented command: SetMessageInterval"),
+        Request::MemoryMapSource => {
+            let source: &str = core.memory_map_source().into();
+
+            Response::MemoryMapSourceResponse {
+                value: source.to_owned(),
+            }
+        }
+        Request::Screenshot => Response::ScreenshotResponse {
+            value: core.screenshot()?,
         },
+        Request::DisplayMessage { message } => {
+            notifications.info(message);
+            Response::DisplayMessageResponse
+        }
+        Request::SetMessageInterval { value } => {
+            let interval =
+                Duration::try_from_secs_f64(value).context("invalid message interval")?;
+
+            notifications.set_min_interval(interval);
+            Response::SetMessageIntervalResponse
+        }
     })
 }
 
+fn unknown_domain(domain: &str) -> Response {
+    Response::Error {
+        err: format!("Unknown memory domain: {domain:?}"),
+    }
+}
+
+/// Maps BizHawk system ids to the names of the cores ape uses for them,
+/// with the loaded core taking precedence for the current system.
+fn preferred_cores(core: &Core) -> BTreeMap<String, String> {
+    let mut preferred_cores = System::with_default_core()
+        .map(|(system, default_core)| {
+            let system_id = system.bizhawk_id().to_owned();
+            let library_name = default_core.library_name.to_owned();
+
+            (system_id, library_name)
+        })
+        .collect::<BTreeMap<_, _>>();
+
+    if let Some(system) = System::detect(core) {
+        let library_name = core.get_system_info().library_name.into_owned();
+
+        preferred_cores.insert(system.bizhawk_id().to_owned(), library_name);
+    }
+
+    preferred_cores
+}
+
 fn send_responses(stream: &mut BufReader<TcpStream>, responses: Vec<Response>) -> Result<()> {
     if let Some(Response::Version) = responses.first() {
         let version = format!("{VERSION}\n");
@@ -292,7 +376,7 @@ fn send_responses(stream: &mut BufReader<TcpStream>, responses: Vec<Response>) -
 
 fn bind_socket() -> Result<TcpListener, Error> {
     let mut errors = None::<Error>;
-    let port_range = FIRST_PORT..FIRST_PORT + 5;
+    let port_range = FIRST_PORT..FIRST_PORT + NUM_PORTS;
 
     for port in port_range {
         let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))
@@ -25,11 +25,25 @@ pub enum Request {
         address: usize,
         #[serde(deserialize_with = "super::deserialize_base64")]
         value: Vec<u8>,
+        #[serde(default = "system_bus")]
+        domain: String,
+    },
+    MemorySize {
+        domain: String,
     },
+    MemoryDomains,
+    MemoryMapSource,
+    /// The last frame at its native resolution as PNG.
+    Screenshot,
     DisplayMessage {
         message: String,
     },
     SetMessageInterval {
-        value: u64,
+        /// Seconds between two displayed messages
+        value: f64,
     },
 }
+
+fn system_bus() -> String {
+    "System Bus".into()
+}
@@ -28,6 +28,21 @@ pub enum Response {
         value: Vec<u8>,
     },
     WriteResponse,
+    MemorySizeResponse {
+        value: usize,
+    },
+    MemoryDomainsResponse {
+        value: Vec<String>,
+    },
+    MemoryMapSourceResponse {
+        /// `core`, `regions` or `none`
+        value: String,
+    },
+    ScreenshotResponse {
+        /// PNG
+        #[serde(serialize_with = "super::serialize_base64")]
+        value: Vec<u8>,
+    },
     DisplayMessageResponse,
     SetMessageIntervalResponse,
     Error {
@@ -0,0 +1,368 @@
+//! Loading ROMs from zip and 7z archives.
+//!
+//! A ROM inside an archive is either named explicitly, as in
+//! `archive.zip#inner.gb`, or picked by the extensions the core supports.
+
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::{env, process};
+
+use anyhow::{bail, ensure, Context, Result};
+use sevenz_rust::{Password, SevenZReader};
+use zip::ZipArchive;
+
+/// Separates the archive from the name of the file inside it.
+const ENTRY_SEPARATOR: char = '#';
+
+static NEXT_TEMP_FILE_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Splits `path` into an archive and the name of the entry to load, if any.
+///
+/// Returns `None` if `path` doesn't point into a zip or 7z archive.
+pub fn split(path: &Path) -> Option<(&Path, Option<&str>)> {
+    if is_archive(path) {
+        return Some((path, None));
+    }
+
+    let path_str = path.to_str()?;
+
+    path_str
+        .match_indices(ENTRY_SEPARATOR)
+        .map(|(index, _)| (Path::new(&path_str[..index]), &path_str[index + 1..]))
+        .find(|(archive, entry)| !entry.is_empty() && is_archive(archive))
+        .map(|(archive, entry)| (archive, Some(entry)))
+}
+
+/// The archive `path` points into, or `path` itself, e.g. `a.zip` for `a.zip#inner.gb`.
+///
+/// Files belonging to the ROM, like its SRAM, are named after this.
+pub fn strip_entry(path: &Path) -> &Path {
+    split(path).map_or(path, |(archive_path, _)| archive_path)
+}
+
+fn is_archive(path: &Path) -> bool {
+    Kind::of(path).is_some() && path.is_file()
+}
+
+/// The names of the files in the archive at `path`.
+pub fn entry_names(path: &Path) -> Result<Vec<String>> {
+    Ok(Archive::open(path)?.entry_names())
+}
+
+/// Reads the ROM at `path`, extracting it if it points into an archive.
+///
+/// `valid_extensions` are the extensions the core accepts, as reported in its
+/// system info. Archives are passed through unchanged if the core blocks
+/// extraction, or if it accepts the archive itself and no entry was named.
+pub fn read_rom(path: &Path, valid_extensions: &[&str], block_extract: bool) -> Result<Vec<u8>> {
+    read_rom_entry(path, valid_extensions, block_extract).map(|(_, rom)| rom)
+}
+
+/// Like [`read_rom`], but also returns the name of the entry the ROM was
+/// extracted from, `None` if a file was read as is.
+pub fn read_rom_entry(
+    path: &Path,
+    valid_extensions: &[&str],
+    block_extract: bool,
+) -> Result<(Option<String>, Vec<u8>)> {
+    let Some((archive_path, entry)) = split(path) else {
+        let rom = fs::read(path).with_context(|| format!("failed to read {path:?}"))?;
+
+        return Ok((None, rom));
+    };
+
+    if block_extract || (entry.is_none() && has_extension(archive_path, valid_extensions)) {
+        let rom =
+            fs::read(archive_path).with_context(|| format!("failed to read {archive_path:?}"))?;
+
+        return Ok((None, rom));
+    }
+
+    let mut archive = Archive::open(archive_path)?;
+    let name = match entry {
+        Some(entry) => entry.to_owned(),
+        None => select_entry(archive.entry_names(), valid_extensions)
+            .with_context(|| format!("no ROM to load in {archive_path:?}"))?,
+    };
+
+    eprintln!("Extracting {name:?} from {archive_path:?}");
+
+    let rom = archive
+        .read(&name)
+        .with_context(|| format!("failed to extract {name:?} from {archive_path:?}"))?;
+
+    Ok((Some(name), rom))
+}
+
+/// A ROM written to a temporary file, for cores that can only load from a path.
+///
+/// The file is deleted when dropped.
+pub struct TempFile {
+    path: PathBuf,
+}
+
+impl TempFile {
+    /// Writes `data` to a new temporary directory, as `file_name` so cores can
+    /// still tell the format by its extension.
+    pub fn create(file_name: &str, data: &[u8]) -> Result<Self> {
+        let file_name = Path::new(file_name)
+            .file_name()
+            .with_context(|| format!("invalid file name {file_name:?}"))?;
+        let directory = env::temp_dir().join("ape-content").join(format!(
+            "{}-{}",
+            process::id(),
+            NEXT_TEMP_FILE_ID.fetch_add(1, Ordering::Relaxed)
+        ));
+        let path = directory.join(file_name);
+
+        fs::create_dir_all(&directory)
+            .with_context(|| format!("failed to create temporary directory {directory:?}"))?;
+        fs::write(&path, data).with_context(|| format!("failed to write {path:?}"))?;
+
+        Ok(Self { path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let res = fs::remove_file(&self.path).and_then(|()| match self.path.parent() {
+            Some(directory) => fs::remove_dir(directory),
+            None => Ok(()),
+        });
+
+        if let Err(err) = res {
+            eprintln!("Failed to remove temporary file {:?}: {err}", self.path);
+        }
+    }
+}
+
+/// Picks the only entry with a valid extension, or the only entry at all.
+fn select_entry(names: Vec<String>, valid_extensions: &[&str]) -> Result<String> {
+    let candidates = names
+        .iter()
+        .filter(|name| has_extension(Path::new(name), valid_extensions))
+        .collect::<Vec<_>>();
+
+    match (candidates.as_slice(), names.as_slice()) {
+        ([name], _) => Ok(name.to_string()),
+        ([], [name]) => Ok(name.clone()),
+        ([], _) => bail!("no file with an extension the core supports ({valid_extensions:?})"),
+        (candidates, _) => bail!(
+            "multiple ROMs, pick one with `archive{ENTRY_SEPARATOR}name`: {}",
+            candidates
+                .iter()
+                .map(|name| name.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
+
+fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| {
+            extensions
+                .iter()
+                .any(|valid| valid.eq_ignore_ascii_case(extension))
+        })
+}
+
+enum Kind {
+    Zip,
+    SevenZ,
+}
+
+impl Kind {
+    fn of(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?;
+
+        if extension.eq_ignore_ascii_case("zip") {
+            Some(Self::Zip)
+        } else if extension.eq_ignore_ascii_case("7z") {
+            Some(Self::SevenZ)
+        } else {
+            None
+        }
+    }
+}
+
+enum Archive {
+    Zip(ZipArchive<File>),
+    // boxed, the reader keeps the whole archive index inline
+    SevenZ(Box<SevenZReader<File>>),
+}
+
+impl Archive {
+    fn open(path: &Path) -> Result<Self> {
+        let kind = Kind::of(path).with_context(|| format!("{path:?} is not an archive"))?;
+        let archive = match kind {
+            Kind::Zip => {
+                let file = File::open(path).with_context(|| format!("failed to open {path:?}"))?;
+
+                ZipArchive::new(file).map(Self::Zip)?
+            }
+            Kind::SevenZ => SevenZReader::open(path, Password::empty())
+                .map(|reader| Self::SevenZ(Box::new(reader)))?,
+        };
+
+        Ok(archive)
+    }
+
+    fn entry_names(&self) -> Vec<String> {
+        match self {
+            Self::Zip(zip) => zip
+                .file_names()
+                .filter(|name| !name.ends_with('/'))
+                .map(str::to_owned)
+                .collect(),
+            Self::SevenZ(seven_z) => seven_z
+                .archive()
+                .files
+                .iter()
+                .filter(|entry| !entry.is_directory())
+                .map(|entry| entry.name().to_owned())
+                .collect(),
+        }
+    }
+
+    fn read(&mut self, name: &str) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+
+        match self {
+            Self::Zip(zip) => {
+                zip.by_name(name)?.read_to_end(&mut data)?;
+            }
+            Self::SevenZ(seven_z) => {
+                let mut found = false;
+
+                seven_z.for_each_entries(|entry, reader| {
+                    if entry.name() == name {
+                        reader.read_to_end(&mut data)?;
+                        found = true;
+
+                        return Ok(false);
+                    }
+
+                    // entries of solid archives share a stream, skip by reading
+                    io::copy(reader, &mut io::sink())?;
+
+                    Ok(true)
+                })?;
+
+                ensure!(found, "no such file in archive");
+            }
+        }
+
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/archive");
+
+    fn fixture(path: &str) -> PathBuf {
+        Path::new(FIXTURES).join(path)
+    }
+
+    #[test]
+    fn splits_entries_off_archives() {
+        let roms = fixture("roms.zip");
+        let entry = fixture("roms.zip#dir/Other (World).gbc");
+
+        assert_eq!(split(&roms), Some((roms.as_path(), None)));
+        assert_eq!(
+            split(&entry),
+            Some((roms.as_path(), Some("dir/Other (World).gbc")))
+        );
+        assert_eq!(split(&fixture("missing.zip#rom.gb")), None);
+        assert_eq!(split(&fixture("roms.zip#")), None);
+        assert_eq!(strip_entry(&entry), roms);
+        assert_eq!(
+            strip_entry(Path::new("Game #1.gb")),
+            Path::new("Game #1.gb")
+        );
+    }
+
+    #[test]
+    fn lists_files_without_directories() {
+        let mut names = entry_names(&fixture("roms.zip")).unwrap();
+
+        names.sort();
+
+        assert_eq!(
+            names,
+            ["Game (World).gb", "dir/Other (World).gbc", "readme.txt"]
+        );
+    }
+
+    #[test]
+    fn selects_the_entry_with_a_valid_extension() {
+        let (entry, rom) = read_rom_entry(&fixture("roms.zip"), &["gb"], false).unwrap();
+
+        assert_eq!(entry.as_deref(), Some("Game (World).gb"));
+        assert_eq!(rom, (0..16).collect::<Vec<u8>>());
+
+        let err = read_rom(&fixture("roms.zip"), &["gb", "gbc"], false).unwrap_err();
+
+        assert!(format!("{err:#}").contains("multiple ROMs"), "{err:#}");
+        assert!(read_rom(&fixture("roms.zip"), &["sfc"], false).is_err());
+    }
+
+    #[test]
+    fn selects_the_only_entry() {
+        let (entry, rom) = read_rom_entry(&fixture("single.zip"), &["sfc"], false).unwrap();
+
+        assert_eq!(entry.as_deref(), Some("rom.bin"));
+        assert_eq!(rom, [1, 2, 3]);
+    }
+
+    #[test]
+    fn reads_named_entries() {
+        let path = fixture("roms.zip#dir/Other (World).gbc");
+        let (entry, rom) = read_rom_entry(&path, &["gb"], false).unwrap();
+
+        assert_eq!(entry.as_deref(), Some("dir/Other (World).gbc"));
+        assert_eq!(rom, b"GBC".repeat(4));
+        assert!(read_rom(&fixture("roms.zip#missing.gb"), &["gb"], false).is_err());
+    }
+
+    #[test]
+    fn passes_archives_through() {
+        let archive = fs::read(fixture("roms.zip")).unwrap();
+
+        // the core loads zips itself
+        let (entry, rom) = read_rom_entry(&fixture("roms.zip"), &["gb", "zip"], false).unwrap();
+
+        assert_eq!((entry, rom), (None, archive.clone()));
+
+        // the core blocks extraction, even of named entries
+        let path = fixture("roms.zip#Game (World).gb");
+        let (entry, rom) = read_rom_entry(&path, &["gb"], true).unwrap();
+
+        assert_eq!((entry, rom), (None, archive));
+    }
+
+    #[test]
+    fn temp_files_keep_the_file_name_and_are_removed() {
+        let file = TempFile::create("dir/Game (v1.1).gb", b"rom").unwrap();
+        let path = file.path().to_owned();
+
+        assert_eq!(path.file_name().unwrap(), "Game (v1.1).gb");
+        assert_eq!(fs::read(&path).unwrap(), b"rom");
+
+        drop(file);
+
+        assert!(!path.exists());
+        assert!(!path.parent().unwrap().exists());
+    }
+}
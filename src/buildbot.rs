@@ -1,15 +1,123 @@
 use std::env::consts::{ARCH, OS};
+use std::fmt::{self, Display};
 use std::fs;
-use std::io::{Cursor, Read};
-use std::path::Path;
+use std::io::{self, Cursor, Read};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use reqwest::Url;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 use zip::ZipArchive;
 
-fn buildbot_url_for_library(library_name: &str) -> Option<Url> {
-    let mut url = Url::parse("https://buildbot.libretro.com/nightly").unwrap();
-    let mut path = url.path_segments_mut().unwrap();
+const DEFAULT_URL: &str = "https://buildbot.libretro.com/nightly";
+
+/// Where cores are downloaded from.
+#[derive(Clone, Debug)]
+pub enum Source {
+    /// A buildbot laid out like the libretro one, with zipped cores under
+    /// `<os>/<arch>/latest/`.
+    Url(Url),
+    /// A local mirror with the zipped cores for the current platform, e.g.
+    /// `gambatte_libretro.so.zip`, directly in it.
+    Directory(PathBuf),
+}
+
+impl Default for Source {
+    fn default() -> Self {
+        Self::Url(Url::parse(DEFAULT_URL).unwrap())
+    }
+}
+
+impl FromStr for Source {
+    type Err = anyhow::Error;
+
+    /// Parses `http(s)://` and `file://` URLs, anything else is a directory.
+    fn from_str(source: &str) -> Result<Self> {
+        if !source.contains("://") {
+            return Ok(Self::Directory(source.into()));
+        }
+
+        let url = Url::parse(source).context("invalid buildbot url")?;
+
+        match url.scheme() {
+            "http" | "https" => Ok(Self::Url(url)),
+            "file" => url
+                .to_file_path()
+                .map(Self::Directory)
+                .map_err(|()| anyhow!("invalid file url")),
+            scheme => bail!("unsupported buildbot url scheme `{scheme}`"),
+        }
+    }
+}
+
+impl Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Url(url) => write!(f, "{url}"),
+            Source::Directory(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+impl Serialize for Source {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Source {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let source = String::deserialize(deserializer)?;
+
+        source.parse().map_err(de::Error::custom)
+    }
+}
+
+/// Progress of a download, shared with the thread running it.
+#[derive(Debug, Default)]
+pub struct Progress {
+    downloaded: AtomicU64,
+    /// 0 while unknown
+    total: AtomicU64,
+    cancelled: AtomicBool,
+}
+
+impl Progress {
+    /// Bytes downloaded so far.
+    pub fn downloaded(&self) -> u64 {
+        self.downloaded.load(Ordering::Relaxed)
+    }
+
+    /// Size of the download, if known.
+    pub fn total(&self) -> Option<u64> {
+        Some(self.total.load(Ordering::Relaxed)).filter(|&total| total > 0)
+    }
+
+    /// Makes the download fail as soon as possible.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+/// Whether cores can be downloaded from `source` on this platform.
+pub fn is_platform_supported(source: &Source) -> bool {
+    match source {
+        Source::Url(base_url) => buildbot_url_for_library(base_url, "").is_some(),
+        Source::Directory(_) => true,
+    }
+}
+
+fn buildbot_url_for_library(base_url: &Url, library_name: &str) -> Option<Url> {
+    let mut url = base_url.clone();
+    let mut path = url.path_segments_mut().ok()?;
+
+    path.pop_if_empty();
 
     match OS {
         "windows" => {
@@ -52,30 +160,75 @@ fn buildbot_url_for_library(library_name: &str) -> Option<Url> {
     Some(url)
 }
 
-fn download_library(library_name: &str) -> Result<Vec<u8>> {
-    let url = buildbot_url_for_library(library_name)
-        .context("Buildbot url for current platform is unknown")?;
+/// Downloads the zipped core library and extracts it, reporting to `progress`.
+///
+/// Zip entries are checked against their CRC while they are extracted.
+pub fn download_library(
+    source: &Source,
+    library_name: &str,
+    progress: &Progress,
+) -> Result<Vec<u8>> {
+    let zip = match source {
+        Source::Url(base_url) => {
+            let url = buildbot_url_for_library(base_url, library_name)
+                .context("Buildbot url for current platform is unknown")?;
+
+            eprintln!("Downloading core from {url}");
+
+            let response = reqwest::blocking::get(url)
+                .and_then(|request| request.error_for_status())
+                .context("Requesting core download failed")?;
+
+            progress
+                .total
+                .store(response.content_length().unwrap_or(0), Ordering::Relaxed);
+
+            read_with_progress(response, progress)?
+        }
+        Source::Directory(dir) => {
+            let path = dir.join(format!("{library_name}.zip"));
+
+            eprintln!("Copying core from {path:?}");
+
+            let file = fs::File::open(&path).with_context(|| format!("failed to open {path:?}"))?;
 
-    eprintln!("Downloading core from {url}");
+            progress
+                .total
+                .store(file.metadata()?.len(), Ordering::Relaxed);
 
-    let response = reqwest::blocking::get(url)
-        .and_then(|request| request.error_for_status())
-        .context("Requesting core download failed")?;
-    let zip = response.bytes()?;
-    let zip = Cursor::new(zip);
-    let mut zip = ZipArchive::new(zip)?;
+            read_with_progress(file, progress)
+                .with_context(|| format!("failed to read {path:?}"))?
+        }
+    };
+    let mut zip = ZipArchive::new(Cursor::new(zip)).context("core download is not a zip file")?;
     let mut file = zip.by_name(library_name).context("core not found in zip")?;
     let mut core = Vec::with_capacity(file.size() as usize);
 
-    file.read_to_end(&mut core)?;
+    file.read_to_end(&mut core)
+        .context("failed to extract core from zip")?;
 
     Ok(core)
 }
 
-pub fn download_core_to(library_name: &str, path: &Path) -> Result<()> {
-    let core = download_library(library_name)?;
+fn read_with_progress(mut reader: impl Read, progress: &Progress) -> Result<Vec<u8>> {
+    let mut data = Vec::with_capacity(progress.total().unwrap_or(0) as usize);
+    let mut chunk = vec![0; 64 * 1024];
+
+    loop {
+        if progress.is_cancelled() {
+            bail!("download cancelled");
+        }
 
-    fs::write(path, core).with_context(|| format!("failed to write core to `{path:?}`"))?;
+        let len = match reader.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(len) => len,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err).context("download failed"),
+        };
+
+        data.extend_from_slice(&chunk[..len]);
+        progress.downloaded.fetch_add(len as u64, Ordering::Relaxed);
+    }
 
-    Ok(())
+    Ok(data)
 }
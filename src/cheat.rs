@@ -0,0 +1,221 @@
+use std::collections::BTreeMap;
+use std::ffi::{CStr, CString};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use atomicwrites::{AtomicFile, OverwriteBehavior};
+
+use crate::system::System;
+
+mod cht;
+mod gb;
+mod gba;
+
+/// Cores that implement `retro_cheat_set`, by the library name they report.
+/// Cheats for all other cores are applied by ape itself.
+const CORES_WITH_CHEAT_SUPPORT: &[&str] = &[
+    "Gambatte",
+    "mGBA",
+    "VBA-M",
+    "VBA Next",
+    "Mesen",
+    "FCEUmm",
+    "Nestopia",
+    "Snes9x",
+    "Genesis Plus GX",
+    "bsnes",
+];
+
+pub struct Cheat {
+    pub description: String,
+    pub code: String,
+    pub enabled: bool,
+    kind: Result<Kind, String>,
+}
+
+enum Kind {
+    /// Passed to the core with `retro_cheat_set`.
+    Core(CString),
+    /// Written to memory by ape after every frame.
+    Ram(Vec<RamWrite>),
+}
+
+/// A write ape performs after every frame for cores without cheat support.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RamWrite {
+    pub target: Target,
+    pub data: Vec<u8>,
+}
+
+/// Where a [`RamWrite`] goes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Target {
+    /// An address on the system bus, as decoded codes give it.
+    SystemBus(usize),
+    /// An offset into the core's system RAM, `RETRO_MEMORY_SYSTEM_RAM` or the
+    /// system RAM descriptors of its memory map, as RetroArch's `.cht`
+    /// address/value cheats give it.
+    SystemRam(usize),
+}
+
+impl RamWrite {
+    fn new(address: usize, data: Vec<u8>) -> Self {
+        Self {
+            target: Target::SystemBus(address),
+            data,
+        }
+    }
+
+    fn system_ram(offset: usize, data: Vec<u8>) -> Self {
+        Self {
+            target: Target::SystemRam(offset),
+            data,
+        }
+    }
+}
+
+impl Cheat {
+    /// Why the cheat can't be used, if it can't.
+    pub fn error(&self) -> Option<&str> {
+        self.kind.as_ref().err().map(String::as_str)
+    }
+}
+
+/// The cheats of the loaded content, with their enable state persisted per ROM.
+#[derive(Default)]
+pub struct Cheats {
+    cheats: Vec<Cheat>,
+    state_path: Option<PathBuf>,
+}
+
+impl Cheats {
+    /// Loads a RetroArch `.cht` file.
+    ///
+    /// The enable state stored at `state_path` takes precedence over the one in the file.
+    pub fn load(
+        path: &Path,
+        state_path: PathBuf,
+        system: Option<System>,
+        library_name: &str,
+    ) -> Result<Self> {
+        let cht = fs::read_to_string(path)
+            .with_context(|| format!("failed to read cheat file {path:?}"))?;
+        let entries = cht::parse(&cht).context("failed to parse cheat file")?;
+        let core_has_cheat_support = CORES_WITH_CHEAT_SUPPORT.contains(&library_name);
+        let enable_state = load_enable_state(&state_path)?;
+
+        let cheats = entries
+            .into_iter()
+            .map(|entry| {
+                let code = entry.code();
+                let enabled = enable_state.get(&code).copied().unwrap_or(entry.enabled);
+                let kind = match entry.ram {
+                    Some(ram) => ram.writes().map(Kind::Ram),
+                    None if core_has_cheat_support => CString::new(code.clone())
+                        .map(Kind::Core)
+                        .map_err(|_| "code contains a NUL byte".to_owned()),
+                    None => decode(system, &code).map(Kind::Ram),
+                };
+
+                Cheat {
+                    description: entry.description,
+                    code,
+                    enabled: enabled && kind.is_ok(),
+                    kind,
+                }
+            })
+            .collect();
+
+        Ok(Self {
+            cheats,
+            state_path: Some(state_path),
+        })
+    }
+
+    pub fn as_slice(&self) -> &[Cheat] {
+        &self.cheats
+    }
+
+    pub fn set_enabled(&mut self, index: usize, enabled: bool) -> Result<()> {
+        let cheat = self.cheats.get_mut(index).context("no such cheat")?;
+
+        if let Err(err) = &cheat.kind {
+            bail!("cheat `{}` can't be used: {err}", cheat.description);
+        }
+
+        cheat.enabled = enabled;
+
+        self.save_enable_state()
+    }
+
+    /// Codes of the enabled cheats that are handled by the core.
+    pub fn core_codes(&self) -> impl Iterator<Item = &CStr> {
+        self.cheats
+            .iter()
+            .filter(|cheat| cheat.enabled)
+            .filter_map(|cheat| match &cheat.kind {
+                Ok(Kind::Core(code)) => Some(&**code),
+                _ => None,
+            })
+    }
+
+    /// Writes of the enabled cheats that are handled by ape.
+    pub fn ram_writes(&self) -> impl Iterator<Item = &RamWrite> {
+        self.cheats
+            .iter()
+            .filter(|cheat| cheat.enabled)
+            .filter_map(|cheat| match &cheat.kind {
+                Ok(Kind::Ram(writes)) => Some(writes.iter()),
+                _ => None,
+            })
+            .flatten()
+    }
+
+    fn save_enable_state(&self) -> Result<()> {
+        let Some(state_path) = &self.state_path else {
+            return Ok(());
+        };
+
+        let enable_state = self
+            .cheats
+            .iter()
+            .map(|cheat| (cheat.code.as_str(), cheat.enabled))
+            .collect::<BTreeMap<_, _>>();
+        let enable_state =
+            serde_json::to_vec_pretty(&enable_state).context("failed to serialize cheat state")?;
+
+        AtomicFile::new(state_path, OverwriteBehavior::AllowOverwrite)
+            .write(|file| file.write_all(&enable_state))
+            .with_context(|| format!("failed to save cheat state to {state_path:?}"))?;
+
+        Ok(())
+    }
+}
+
+fn load_enable_state(state_path: &Path) -> Result<BTreeMap<String, bool>> {
+    let enable_state = match fs::read(state_path) {
+        Ok(enable_state) => enable_state,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
+        Err(err) => {
+            return Err(err).with_context(|| format!("failed to read cheat state {state_path:?}"))
+        }
+    };
+
+    serde_json::from_slice(&enable_state)
+        .with_context(|| format!("failed to parse cheat state {state_path:?}"))
+}
+
+/// Decodes a code into RAM writes for cores without cheat support.
+fn decode(system: Option<System>, code: &str) -> Result<Vec<RamWrite>, String> {
+    match system {
+        Some(System::GameBoy | System::GameBoyColor) => code
+            .split(['+', ' ', '\n'])
+            .filter(|code| !code.is_empty())
+            .map(gb::decode)
+            .collect(),
+        Some(System::GameBoyAdvance) => gba::decode(code),
+        _ => Err("the core has no cheat support and codes for this system can't be decoded".into()),
+    }
+}
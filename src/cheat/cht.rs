@@ -0,0 +1,212 @@
+//! Parser for RetroArch `.cht` cheat files.
+
+use std::collections::HashMap;
+
+use anyhow::{bail, Context, Result};
+
+use super::RamWrite;
+
+/// `cheatN_handler` value of cheats RetroArch applies itself instead of the core.
+const HANDLER_RETRO: u32 = 1;
+/// `cheatN_cheat_type` value of cheats that set memory to a value.
+const CHEAT_TYPE_SET_TO_VALUE: u32 = 1;
+
+pub struct Entry {
+    pub description: String,
+    code: String,
+    pub enabled: bool,
+    /// Set for cheats that specify an address and value instead of a code.
+    pub ram: Option<RamCheat>,
+}
+
+impl Entry {
+    /// The code, or for address/value cheats a synthesized `ADDRESS:VALUE` code.
+    pub fn code(&self) -> String {
+        match &self.ram {
+            Some(ram) => format!("{:X}:{:X}", ram.address, ram.value),
+            None => self.code.clone(),
+        }
+    }
+}
+
+pub struct RamCheat {
+    /// Offset into the system RAM, not a bus address.
+    address: usize,
+    value: u32,
+    /// `cheatN_memory_search_size`, 3 to 5 for 8 to 32 bit values.
+    memory_search_size: u32,
+    cheat_type: u32,
+    big_endian: bool,
+    repeat_count: u32,
+    repeat_add_to_value: u32,
+    repeat_add_to_address: usize,
+}
+
+impl RamCheat {
+    pub fn writes(&self) -> Result<Vec<RamWrite>, String> {
+        if self.cheat_type != CHEAT_TYPE_SET_TO_VALUE {
+            return Err(format!("unsupported cheat type {}", self.cheat_type));
+        }
+
+        let size = match self.memory_search_size {
+            3 => 1,
+            4 => 2,
+            5 => 4,
+            size => return Err(format!("unsupported memory search size {size}")),
+        };
+
+        let writes = (0..self.repeat_count.max(1) as usize)
+            .map(|i| {
+                let address = self.address + i * self.repeat_add_to_address * size;
+                let value = self.value.wrapping_add(i as u32 * self.repeat_add_to_value);
+                let data = if self.big_endian {
+                    value.to_be_bytes()[4 - size..].to_vec()
+                } else {
+                    value.to_le_bytes()[..size].to_vec()
+                };
+
+                RamWrite::system_ram(address, data)
+            })
+            .collect();
+
+        Ok(writes)
+    }
+}
+
+pub fn parse(cht: &str) -> Result<Vec<Entry>> {
+    let values = cht
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let (key, value) = line
+                .split_once('=')
+                .with_context(|| format!("invalid line `{line}`"))?;
+            let value = value.trim();
+            let value = value
+                .strip_prefix('"')
+                .and_then(|value| value.strip_suffix('"'))
+                .unwrap_or(value);
+
+            Ok((key.trim(), value))
+        })
+        .collect::<Result<HashMap<_, _>>>()?;
+
+    let num_cheats = values
+        .get("cheats")
+        .context("missing `cheats` count")?
+        .parse::<usize>()
+        .context("invalid `cheats` count")?;
+
+    (0..num_cheats)
+        .map(|index| {
+            let get = |key: &str| values.get(&*format!("cheat{index}_{key}")).copied();
+            let get_number = |key: &str, default: u32| -> Result<u32> {
+                get(key).map_or(Ok(default), |value| {
+                    value
+                        .parse()
+                        .with_context(|| format!("invalid cheat{index}_{key} `{value}`"))
+                })
+            };
+            let get_bool = |key: &str| get(key) == Some("true");
+
+            let description = get("desc").unwrap_or_default().to_owned();
+            let code = get("code").unwrap_or_default().to_owned();
+            let ram = if get_number("handler", 0)? == HANDLER_RETRO {
+                Some(RamCheat {
+                    address: get_number("address", 0)? as usize,
+                    value: get_number("value", 0)?,
+                    memory_search_size: get_number("memory_search_size", 3)?,
+                    cheat_type: get_number("cheat_type", CHEAT_TYPE_SET_TO_VALUE)?,
+                    big_endian: get_bool("big_endian"),
+                    repeat_count: get_number("repeat_count", 1)?,
+                    repeat_add_to_value: get_number("repeat_add_to_value", 0)?,
+                    repeat_add_to_address: get_number("repeat_add_to_address", 1)? as usize,
+                })
+            } else {
+                None
+            };
+
+            if ram.is_none() && code.is_empty() {
+                bail!("cheat{index} has no code");
+            }
+
+            Ok(Entry {
+                description,
+                code,
+                enabled: get_bool("enable"),
+                ram,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cheat::Target;
+
+    const CHT: &str = r#"cheats = 3
+
+cheat0_desc = "Infinite Lives"
+cheat0_code = "00A-17B-C49"
+cheat0_enable = false
+
+cheat1_desc = "Max Money"
+cheat1_code = ""
+cheat1_enable = true
+cheat1_handler = "1"
+cheat1_address = "4660"
+cheat1_value = "39321"
+cheat1_memory_search_size = "4"
+cheat1_big_endian = "true"
+cheat1_repeat_count = "2"
+cheat1_repeat_add_to_value = "1"
+cheat1_repeat_add_to_address = "1"
+
+cheat2_desc = "Item Slot"
+cheat2_handler = "1"
+cheat2_address = "16"
+cheat2_value = "99"
+"#;
+
+    #[test]
+    fn parses_code_entries() {
+        let entries = parse(CHT).unwrap();
+
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].description, "Infinite Lives");
+        assert_eq!(entries[0].code(), "00A-17B-C49");
+        assert!(!entries[0].enabled);
+        assert!(entries[0].ram.is_none());
+    }
+
+    #[test]
+    fn address_cheats_write_system_ram_offsets() {
+        let entries = parse(CHT).unwrap();
+        let money = &entries[1];
+
+        assert!(money.enabled);
+        assert_eq!(money.code(), "1234:9999");
+
+        let writes = money.ram.as_ref().unwrap().writes().unwrap();
+
+        assert_eq!(writes.len(), 2);
+        assert_eq!(writes[0].target, Target::SystemRam(0x1234));
+        assert_eq!(writes[0].data, [0x99, 0x99]);
+        assert_eq!(writes[1].target, Target::SystemRam(0x1236));
+        assert_eq!(writes[1].data, [0x99, 0x9A]);
+
+        let writes = entries[2].ram.as_ref().unwrap().writes().unwrap();
+
+        assert_eq!(writes.len(), 1);
+        assert_eq!(writes[0].target, Target::SystemRam(16));
+        assert_eq!(writes[0].data, [99]);
+    }
+
+    #[test]
+    fn rejects_cheats_without_code() {
+        assert!(parse("cheats = 1\ncheat0_desc = \"Nothing\"").is_err());
+        assert!(parse("cheat0_code = \"00A-17B-C49\"").is_err());
+    }
+}
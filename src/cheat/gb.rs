@@ -0,0 +1,104 @@
+//! Game Boy (Color) GameShark and Game Genie codes.
+
+use super::RamWrite;
+
+/// Size of a WRAM bank, the GBC has banks 0 to 7 with bank 0 at 0xC000 and
+/// the selected bank at 0xD000.
+const WRAM_BANK_SIZE: usize = 0x1000;
+
+/// Decodes a GameShark (`01VVLLHH`) code.
+///
+/// Game Genie (`ABC-DEF` / `ABC-DEF-GHI`) codes patch ROM, which the core maps
+/// read-only, so they are rejected.
+pub fn decode(code: &str) -> Result<RamWrite, String> {
+    let code = code.trim();
+
+    if code.contains('-') {
+        Err(format!(
+            "Game Genie code `{code}` patches ROM, which only cores with cheat support apply"
+        ))
+    } else {
+        decode_game_shark(code)
+    }
+}
+
+/// GameShark codes are `TTVVLLHH`: type, value and little endian address.
+fn decode_game_shark(code: &str) -> Result<RamWrite, String> {
+    if code.len() != 8 {
+        return Err(format!("invalid GameShark code `{code}`"));
+    }
+
+    let code =
+        u32::from_str_radix(code, 16).map_err(|_| format!("invalid GameShark code `{code}`"))?;
+    let [ty, value, address_low, address_high] = code.to_be_bytes();
+    let address = usize::from(u16::from_le_bytes([address_low, address_high]));
+
+    match ty {
+        0x00 | 0x01 | 0x80 => Ok(RamWrite::new(address, vec![value])),
+        // 0x9X writes to WRAM bank X, bank 0 selects bank 1 like the hardware does
+        0x90..=0x97 => match address {
+            0xC000..=0xCFFF => Ok(RamWrite::system_ram(address - 0xC000, vec![value])),
+            0xD000..=0xDFFF => {
+                let bank = usize::from(ty & 0x07).max(1);
+
+                Ok(RamWrite::system_ram(
+                    bank * WRAM_BANK_SIZE + address - 0xD000,
+                    vec![value],
+                ))
+            }
+            _ => Ok(RamWrite::new(address, vec![value])),
+        },
+        _ => Err(format!("unsupported GameShark code type {ty:02X}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cheat::Target;
+
+    #[test]
+    fn decodes_game_shark_codes() {
+        // Pokémon Red, infinite money
+        let write = decode("019947D3").unwrap();
+
+        assert_eq!(write.target, Target::SystemBus(0xD347));
+        assert_eq!(write.data, [0x99]);
+    }
+
+    #[test]
+    fn decodes_game_shark_wram_banks() {
+        // Pokémon Crystal, walk through walls (bank 1)
+        let write = decode("910138D2").unwrap();
+
+        assert_eq!(write.target, Target::SystemRam(0x1238));
+        assert_eq!(write.data, [0x01]);
+
+        let write = decode("930555D6").unwrap();
+
+        assert_eq!(write.target, Target::SystemRam(0x3655));
+
+        let write = decode("90FF10C1").unwrap();
+
+        assert_eq!(write.target, Target::SystemRam(0x0110));
+
+        let write = decode("90FF00D0").unwrap();
+
+        assert_eq!(write.target, Target::SystemRam(0x1000));
+    }
+
+    #[test]
+    fn rejects_game_genie_codes() {
+        // Super Mario Land, infinite lives
+        let err = decode("00A-17B-C49").unwrap_err();
+
+        assert!(err.contains("patches ROM"), "{err}");
+    }
+
+    #[test]
+    fn rejects_invalid_codes() {
+        assert!(decode("01FF").is_err());
+        assert!(decode("01FFXXC0").is_err());
+        assert!(decode("A1FF00C0").is_err());
+    }
+}
@@ -0,0 +1,208 @@
+//! Game Boy Advance Action Replay / GameShark and CodeBreaker codes.
+
+use super::RamWrite;
+
+/// TEA keys of GameShark / Action Replay v1 and v2 codes.
+const GSA_V1_SEEDS: [u32; 4] = [0x09F4_FBBD, 0x9681_884A, 0x3520_27E9, 0xF3DE_E5A7];
+/// TEA keys of Action Replay v3 codes.
+const GSA_V3_SEEDS: [u32; 4] = [0x7AA9_648F, 0x7FAE_6994, 0xC0EF_AAD5, 0x4271_2C57];
+const TEA_DELTA: u32 = 0x9E37_79B9;
+const TEA_ROUNDS: u32 = 32;
+
+/// Decodes a list of codes, separated by whitespace or `+`.
+///
+/// `XXXXXXXX YYYYYYYY` pairs are Action Replay / GameShark codes, `XXXXXXXX YYYY`
+/// pairs are CodeBreaker codes.
+pub fn decode(codes: &str) -> Result<Vec<RamWrite>, String> {
+    let mut tokens = codes
+        .split(|c: char| c == '+' || c.is_whitespace())
+        .filter(|token| !token.is_empty());
+    let mut writes = Vec::new();
+
+    while let Some(token) = tokens.next() {
+        let (address, value) = match token.len() {
+            16 => token.split_at(8),
+            8 => (token, tokens.next().ok_or("incomplete code")?),
+            _ => return Err(format!("invalid code `{token}`")),
+        };
+
+        let parse =
+            |hex: &str| u32::from_str_radix(hex, 16).map_err(|_| format!("invalid code `{hex}`"));
+        let (address, code_value) = (parse(address)?, parse(value)?);
+
+        match value.len() {
+            4 => writes.extend(decode_code_breaker(address, code_value as u16)?),
+            8 => writes.extend(decode_action_replay(address, code_value)?),
+            _ => return Err(format!("invalid code `{token} {value}`")),
+        }
+    }
+
+    Ok(writes)
+}
+
+fn decode_action_replay(address: u32, value: u32) -> Result<Vec<RamWrite>, String> {
+    let (v1_address, v1_value) = decrypt(address, value, &GSA_V1_SEEDS);
+
+    if let Some(writes) = action_replay_v1_writes(v1_address, v1_value) {
+        return Ok(writes);
+    }
+
+    let (v3_address, v3_value) = decrypt(address, value, &GSA_V3_SEEDS);
+
+    action_replay_v3_writes(v3_address, v3_value)
+        .ok_or_else(|| format!("unsupported Action Replay code {address:08X} {value:08X}"))
+}
+
+/// Decrypted v1/v2 codes are `TAAAAAAA VVVVVVVV` with T selecting the write size.
+fn action_replay_v1_writes(address: u32, value: u32) -> Option<Vec<RamWrite>> {
+    let target = (address & 0x0FFF_FFFF) as usize;
+
+    if !is_ram(target) {
+        return None;
+    }
+
+    let data = match address >> 28 {
+        0 => vec![value as u8],
+        1 => (value as u16).to_le_bytes().to_vec(),
+        2 => value.to_le_bytes().to_vec(),
+        _ => return None,
+    };
+
+    Some(vec![RamWrite::new(target, data)])
+}
+
+/// Decrypted v3 codes are `TTAAAAAA VVVVVVVV`, the address `a0aaaaa` is stored as `aaaaaa`.
+fn action_replay_v3_writes(address: u32, value: u32) -> Option<Vec<RamWrite>> {
+    let target = (((address << 4) & 0x0F00_0000) | (address & 0x000F_FFFF)) as usize;
+
+    if !is_ram(target) {
+        return None;
+    }
+
+    let data = match address >> 24 {
+        // fills `count` bytes / halfwords with the low bits of the value
+        0x00 => vec![value as u8; (value >> 8) as usize + 1],
+        0x02 => (value as u16)
+            .to_le_bytes()
+            .repeat((value >> 16) as usize + 1),
+        0x04 => value.to_le_bytes().to_vec(),
+        _ => return None,
+    };
+
+    Some(vec![RamWrite::new(target, data)])
+}
+
+/// CodeBreaker codes are unencrypted `TAAAAAAA VVVV`.
+fn decode_code_breaker(address: u32, value: u16) -> Result<Vec<RamWrite>, String> {
+    let target = (address & 0x0FFF_FFFF) as usize;
+
+    Ok(match address >> 28 {
+        // master code, only needed by the real device
+        0x0 => Vec::new(),
+        0x3 => vec![RamWrite::new(target, vec![value as u8])],
+        0x8 => vec![RamWrite::new(target, value.to_le_bytes().to_vec())],
+        0x9 => return Err("encrypted CodeBreaker codes are not supported".into()),
+        ty => return Err(format!("unsupported CodeBreaker code type {ty:X}")),
+    })
+}
+
+fn decrypt(mut address: u32, mut value: u32, seeds: &[u32; 4]) -> (u32, u32) {
+    let mut sum = TEA_DELTA.wrapping_mul(TEA_ROUNDS);
+
+    for _ in 0..TEA_ROUNDS {
+        value = value.wrapping_sub(
+            (address << 4).wrapping_add(seeds[2])
+                ^ address.wrapping_add(sum)
+                ^ (address >> 5).wrapping_add(seeds[3]),
+        );
+        address = address.wrapping_sub(
+            (value << 4).wrapping_add(seeds[0])
+                ^ value.wrapping_add(sum)
+                ^ (value >> 5).wrapping_add(seeds[1]),
+        );
+        sum = sum.wrapping_sub(TEA_DELTA);
+    }
+
+    (address, value)
+}
+
+/// Whether the address is in EWRAM or IWRAM.
+fn is_ram(address: usize) -> bool {
+    (0x0200_0000..0x0204_0000).contains(&address) || (0x0300_0000..0x0300_8000).contains(&address)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cheat::Target;
+
+    /// The inverse of [`decrypt`], how the codes were created.
+    fn encrypt(mut address: u32, mut value: u32, seeds: &[u32; 4]) -> (u32, u32) {
+        let mut sum = 0u32;
+
+        for _ in 0..TEA_ROUNDS {
+            sum = sum.wrapping_add(TEA_DELTA);
+            address = address.wrapping_add(
+                (value << 4).wrapping_add(seeds[0])
+                    ^ value.wrapping_add(sum)
+                    ^ (value >> 5).wrapping_add(seeds[1]),
+            );
+            value = value.wrapping_add(
+                (address << 4).wrapping_add(seeds[2])
+                    ^ address.wrapping_add(sum)
+                    ^ (address >> 5).wrapping_add(seeds[3]),
+            );
+        }
+
+        (address, value)
+    }
+
+    fn bus_writes(writes: &[RamWrite]) -> Vec<(usize, &[u8])> {
+        writes
+            .iter()
+            .map(|write| match write.target {
+                Target::SystemBus(address) => (address, &*write.data),
+                Target::SystemRam(_) => panic!("unexpected system RAM write"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn decodes_code_breaker_codes() {
+        let writes = decode("00001234 000A\n32000000 0063 + 83007FF0 1234").unwrap();
+
+        assert_eq!(
+            bus_writes(&writes),
+            [(0x0200_0000, &[0x63][..]), (0x0300_7FF0, &[0x34, 0x12][..])]
+        );
+        assert!(decode("92000000 0063").is_err());
+        assert!(decode("32000000").is_err());
+    }
+
+    #[test]
+    fn decrypts_action_replay_v1_codes() {
+        let (address, value) = encrypt(0x1200_1000, 0xBEEF, &GSA_V1_SEEDS);
+        let writes = decode(&format!("{address:08X} {value:08X}")).unwrap();
+
+        assert_eq!(bus_writes(&writes), [(0x0200_1000, &[0xEF, 0xBE][..])]);
+    }
+
+    #[test]
+    fn decrypts_action_replay_v3_codes() {
+        // 8 bit fill of 3 bytes at 0x03001000
+        let (address, value) = encrypt(0x0030_1000, 0x0000_0242, &GSA_V3_SEEDS);
+        let writes = decode(&format!("{address:08X}{value:08X}")).unwrap();
+
+        assert_eq!(bus_writes(&writes), [(0x0300_1000, &[0x42; 3][..])]);
+    }
+
+    #[test]
+    fn tea_round_trips() {
+        let (address, value) = encrypt(0x0123_4567, 0x89AB_CDEF, &GSA_V1_SEEDS);
+
+        assert_eq!(
+            decrypt(address, value, &GSA_V1_SEEDS),
+            (0x0123_4567, 0x89AB_CDEF)
+        );
+    }
+}
@@ -0,0 +1,37 @@
+//! Subcommands that manage ape's files instead of running content.
+
+use anyhow::Result;
+use ape::config::Config;
+
+mod config;
+mod cores;
+mod firmware;
+
+#[derive(clap::Subcommand)]
+pub enum Command {
+    /// Show the effective settings and the config files they come from
+    Config {
+        #[clap(subcommand)]
+        command: config::Command,
+    },
+    /// List, install, update, pin and verify downloaded cores
+    Cores {
+        #[clap(subcommand)]
+        command: cores::Command,
+    },
+    /// List and check the firmware (BIOS) files cores expect
+    Firmware {
+        #[clap(subcommand)]
+        command: firmware::Command,
+    },
+}
+
+impl Command {
+    pub fn run(self, config: &Config) -> Result<()> {
+        match self {
+            Command::Config { command } => command.run(config),
+            Command::Cores { command } => command.run(&config.settings),
+            Command::Firmware { command } => command.run(&config.settings),
+        }
+    }
+}
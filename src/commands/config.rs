@@ -0,0 +1,35 @@
+use anyhow::Result;
+use ape::config::Config;
+
+#[derive(clap::Subcommand)]
+pub enum Command {
+    /// Print the effective settings as TOML, with where each value came from
+    ///
+    /// Pass `--rom` (and `--core`) to include the per-game and per-core config files.
+    Show,
+}
+
+impl Command {
+    pub fn run(self, config: &Config) -> Result<()> {
+        match self {
+            Command::Show => {
+                for (path, exists) in config.files() {
+                    let status = if *exists { "" } else { " (not found)" };
+
+                    println!("# config file: {}{status}", path.display());
+                }
+
+                println!();
+
+                for (key, value, origin) in config.entries() {
+                    match value {
+                        Some(value) => println!("{key} = {value} # {origin}"),
+                        None => println!("# {key} is not set"),
+                    }
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
@@ -0,0 +1,177 @@
+use anyhow::{bail, Result};
+use ape::buildbot::Progress;
+use ape::config::Settings;
+use ape::cores::{CoreStore, Outcome, Verification};
+
+#[derive(clap::Subcommand)]
+pub enum Command {
+    /// List installed cores with their checksum status
+    List,
+    /// Install cores, e.g. `gambatte`, replacing installed versions
+    Install {
+        #[clap(required = true)]
+        cores: Vec<String>,
+    },
+    /// Update cores, all unpinned ones if none are given
+    Update { cores: Vec<String> },
+    /// Remove cores
+    Remove {
+        #[clap(required = true)]
+        cores: Vec<String>,
+    },
+    /// Exclude cores from updates
+    Pin {
+        #[clap(required = true)]
+        cores: Vec<String>,
+    },
+    /// Include pinned cores in updates again
+    Unpin {
+        #[clap(required = true)]
+        cores: Vec<String>,
+    },
+    /// Restore the version a core had before its last update
+    Rollback { core: String },
+    /// Check installed cores against their recorded checksums
+    Verify { cores: Vec<String> },
+}
+
+impl Command {
+    pub fn run(self, settings: &Settings) -> Result<()> {
+        let mut store = CoreStore::open(&settings.cores_dir, settings.buildbot_url.clone())?;
+
+        match self {
+            Command::List => list(&store),
+            Command::Install { cores } => {
+                for core in cores {
+                    let outcome = store.install(&core, &Progress::default())?;
+
+                    print_outcome(&core, &outcome);
+                }
+
+                Ok(())
+            }
+            Command::Update { cores } => {
+                let cores = if cores.is_empty() {
+                    store.core_names()?
+                } else {
+                    cores
+                };
+                let mut num_failed = 0;
+
+                // keep updating the other cores if one fails
+                for core in cores {
+                    match store.update(&core) {
+                        Ok(outcome) => print_outcome(&core, &outcome),
+                        Err(err) => {
+                            eprintln!("{core}: {err:#}");
+                            num_failed += 1;
+                        }
+                    }
+                }
+
+                if num_failed > 0 {
+                    bail!("failed to update {num_failed} cores");
+                }
+
+                Ok(())
+            }
+            Command::Remove { cores } => {
+                for core in cores {
+                    store.remove(&core)?;
+                    println!("{core}: removed");
+                }
+
+                Ok(())
+            }
+            Command::Pin { cores } => {
+                for core in cores {
+                    store.set_pinned(&core, true)?;
+                    println!("{core}: pinned");
+                }
+
+                Ok(())
+            }
+            Command::Unpin { cores } => {
+                for core in cores {
+                    store.set_pinned(&core, false)?;
+                    println!("{core}: unpinned");
+                }
+
+                Ok(())
+            }
+            Command::Rollback { core } => {
+                store.rollback(&core)?;
+                println!("{core}: rolled back");
+
+                Ok(())
+            }
+            Command::Verify { cores } => {
+                let cores = if cores.is_empty() {
+                    store.core_names()?
+                } else {
+                    cores
+                };
+                let num_bad = cores
+                    .iter()
+                    .map(|core| -> Result<bool> {
+                        let verification = store.verify(core)?;
+
+                        println!("{core}: {}", describe(&verification));
+
+                        Ok(matches!(verification, Verification::Ok))
+                    })
+                    .collect::<Result<Vec<_>>>()?
+                    .into_iter()
+                    .filter(|is_ok| !is_ok)
+                    .count();
+
+                if num_bad > 0 {
+                    bail!("{num_bad} cores failed verification");
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
+
+fn list(store: &CoreStore) -> Result<()> {
+    for core in store.core_names()? {
+        let verification = store.verify(&core)?;
+        let Some(installed) = store.installed(&core) else {
+            println!("{core:<24} {}", describe(&verification));
+            continue;
+        };
+        let pinned = if installed.pinned { " pinned" } else { "" };
+
+        println!(
+            "{core:<24} {} {} from {}{pinned}: {}",
+            &installed.sha1[..installed.sha1.len().min(12)],
+            installed.installed_at,
+            installed.source,
+            describe(&verification),
+        );
+    }
+
+    Ok(())
+}
+
+fn print_outcome(core: &str, outcome: &Outcome) {
+    let outcome = match outcome {
+        Outcome::Installed => "installed",
+        Outcome::Updated => "updated",
+        Outcome::UpToDate => "up to date",
+        Outcome::Pinned => "pinned, skipped",
+    };
+
+    println!("{core}: {outcome}");
+}
+
+fn describe(verification: &Verification) -> String {
+    match verification {
+        Verification::Ok => "ok".into(),
+        Verification::Modified { actual_sha1 } => format!("modified (sha1 {actual_sha1})"),
+        Verification::Missing => "missing".into(),
+        Verification::Unrecorded => "not recorded".into(),
+    }
+}
@@ -0,0 +1,117 @@
+use anyhow::{bail, Result};
+use ape::config::Settings;
+use ape::core_info::{self, CoreInfo};
+use ape::cores::CoreStore;
+use ape::firmware;
+
+#[derive(clap::Subcommand)]
+pub enum Command {
+    /// List the firmware of cores with its hashes, of all installed cores if none are given
+    List { cores: Vec<String> },
+    /// Check the system directory for missing or bad firmware, of all installed cores if none are given
+    Check { cores: Vec<String> },
+}
+
+impl Command {
+    pub fn run(self, settings: &Settings) -> Result<()> {
+        let system_dir = &settings.system_dir;
+
+        match self {
+            Command::List { cores } => {
+                for core in core_infos(cores, settings)? {
+                    println!("{} ({}):", core.name, core.display_name);
+
+                    let firmware = firmware::for_core(core);
+
+                    if firmware.is_empty() {
+                        println!("  no firmware");
+                    }
+
+                    for firmware in firmware {
+                        println!(
+                            "  {:<24} {:<8} md5 {:<32} sha1 {:<40} {}",
+                            firmware.path,
+                            if firmware.optional {
+                                "optional"
+                            } else {
+                                "required"
+                            },
+                            firmware.md5.as_deref().unwrap_or("-"),
+                            firmware.sha1.unwrap_or("-"),
+                            firmware.description,
+                        );
+                    }
+                }
+
+                Ok(())
+            }
+            Command::Check { cores } => {
+                let cores = core_infos(cores, settings)?;
+                let mut num_problems = 0;
+
+                println!("Checking {}", system_dir.display());
+
+                for core in cores {
+                    println!("{} ({}):", core.name, core.display_name);
+
+                    let checks = firmware::check_core(core, system_dir)?;
+
+                    if checks.is_empty() {
+                        println!("  no firmware");
+                    }
+
+                    for check in checks {
+                        let marker = if check.is_problem() { "!" } else { " " };
+
+                        println!(
+                            "{marker} {:<24} {:<8} {}",
+                            check.firmware.path,
+                            if check.firmware.optional {
+                                "optional"
+                            } else {
+                                "required"
+                            },
+                            check.status,
+                        );
+
+                        if check.is_problem() {
+                            num_problems += 1;
+                        }
+                    }
+                }
+
+                if num_problems > 0 {
+                    bail!("{num_problems} firmware files are missing or bad");
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
+
+/// The core database entries of `cores`, or of the installed cores if none are given.
+fn core_infos(cores: Vec<String>, settings: &Settings) -> Result<Vec<&'static CoreInfo>> {
+    let cores = if cores.is_empty() {
+        CoreStore::open(&settings.cores_dir, settings.buildbot_url.clone())?.core_names()?
+    } else {
+        cores
+    };
+
+    if cores.is_empty() {
+        bail!("no cores installed, name the cores to check");
+    }
+
+    Ok(cores
+        .iter()
+        .filter_map(|core| {
+            let core_info = core_info::database().get(core);
+
+            if core_info.is_none() {
+                eprintln!("{core}: not in the core database, skipping");
+            }
+
+            core_info
+        })
+        .collect())
+}
@@ -0,0 +1,525 @@
+//! Layered settings.
+//!
+//! Each setting takes its value from the highest layer that sets it. From
+//! lowest to highest, the layers are:
+//!
+//! 1. the defaults, with directories following the XDG base directories
+//! 2. the user config, `config.toml` in [`config_directory`]
+//! 3. the per-core config, e.g. `cores/gambatte.toml` in [`config_directory`]
+//! 4. the per-game config, named after the ROM, e.g. `games/Tetris (World).toml`,
+//!    or after the archive for ROMs in archives
+//! 5. environment variables, including those from `.env`
+//! 6. command line arguments
+//!
+//! Config files are TOML with the same keys as [`Settings`].
+//!
+//! Earlier versions kept cores, firmware and the library in the working
+//! directory. Until they are moved to [`data_directory`], the defaults keep
+//! pointing there, see [`data_path`].
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Display};
+use std::fs;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use clap::parser::ValueSource;
+use clap::ArgMatches;
+use serde::Deserialize;
+
+use crate::{archive, buildbot, cores, firmware, library, recording, remote, replay, screenshot};
+
+const APP_NAME: &str = "ape";
+const USER_CONFIG_NAME: &str = "config.toml";
+const CORES_CONFIG_DIR: &str = "cores";
+const GAMES_CONFIG_DIR: &str = "games";
+
+/// Declares the settings, once resolved and once as a layer of optional values.
+///
+/// Settings in the second group are unset by default.
+macro_rules! settings {
+    (
+        $(
+            $(#[doc = $doc:literal])*
+            #[clap($($clap:tt)*)]
+            $name:ident: $ty:ty = $default:expr,
+        )*
+        ;
+        $(
+            $(#[doc = $optional_doc:literal])*
+            #[clap($($optional_clap:tt)*)]
+            $optional_name:ident: Option<$optional_ty:ty>,
+        )*
+    ) => {
+        /// The effective settings, after layering all sources.
+        #[derive(Clone, Debug)]
+        pub struct Settings {
+            $(
+                $(#[doc = $doc])*
+                pub $name: $ty,
+            )*
+            $(
+                $(#[doc = $optional_doc])*
+                pub $optional_name: Option<$optional_ty>,
+            )*
+        }
+
+        impl Default for Settings {
+            fn default() -> Self {
+                Self {
+                    $($name: $default,)*
+                    $($optional_name: None,)*
+                }
+            }
+        }
+
+        impl Settings {
+            /// Overrides the settings `layer` sets, recording that they came from `origin`.
+            fn apply(
+                &mut self,
+                layer: &Layer,
+                origin: &Origin,
+                origins: &mut BTreeMap<&'static str, Origin>,
+            ) {
+                $(
+                    if let Some(value) = &layer.$name {
+                        self.$name = value.clone();
+                        origins.insert(stringify!($name), origin.clone());
+                    }
+                )*
+                $(
+                    if let Some(value) = &layer.$optional_name {
+                        self.$optional_name = Some(value.clone());
+                        origins.insert(stringify!($optional_name), origin.clone());
+                    }
+                )*
+            }
+
+            /// The settings by key in declaration order, `None` if unset.
+            fn values(&self) -> Vec<(&'static str, Option<toml::Value>)> {
+                vec![
+                    $((stringify!($name), toml::Value::try_from(&self.$name).ok()),)*
+                    $((
+                        stringify!($optional_name),
+                        self.$optional_name
+                            .as_ref()
+                            .and_then(|value| toml::Value::try_from(value).ok()),
+                    ),)*
+                ]
+            }
+        }
+
+        /// The settings one layer sets, leaving the others to the layers below.
+        ///
+        /// Parsed from config files, and from the command line and environment as
+        /// part of the CLI.
+        #[derive(Clone, Debug, Default, clap::Args, Deserialize)]
+        #[serde(deny_unknown_fields)]
+        pub struct Layer {
+            $(
+                $(#[doc = $doc])*
+                #[clap($($clap)*)]
+                pub $name: Option<$ty>,
+            )*
+            $(
+                $(#[doc = $optional_doc])*
+                #[clap($($optional_clap)*)]
+                pub $optional_name: Option<$optional_ty>,
+            )*
+        }
+
+        impl Layer {
+            /// Moves the settings clap read from environment variables into their own layer,
+            /// leaving those from command line arguments.
+            pub fn split_env(&mut self, matches: &ArgMatches) -> Layer {
+                let mut env = Layer::default();
+                let is_env =
+                    |id: &str| matches.value_source(id) == Some(ValueSource::EnvVariable);
+
+                $(
+                    if is_env(stringify!($name)) {
+                        env.$name = self.$name.take();
+                    }
+                )*
+                $(
+                    if is_env(stringify!($optional_name)) {
+                        env.$optional_name = self.$optional_name.take();
+                    }
+                )*
+
+                env
+            }
+        }
+    };
+}
+
+settings! {
+    /// Directory downloaded cores are installed to
+    #[clap(long, env = "APE_CORES_DIR")]
+    cores_dir: PathBuf = cores::default_directory(),
+    /// Directory cores load firmware (BIOS) files from
+    #[clap(long, env = "APE_SYSTEM_DIR")]
+    system_dir: PathBuf = firmware::default_system_directory(),
+    /// Directory SRAM saves are kept in, named after the ROM
+    #[clap(long, env = "APE_SAVES_DIR")]
+    saves_dir: PathBuf = data_directory().join("saves"),
+    /// Directory save states are kept in, named after the ROM
+    #[clap(long, env = "APE_STATES_DIR")]
+    states_dir: PathBuf = data_directory().join("states"),
+    /// Directory screenshots are saved to
+    #[clap(long, env = "APE_SCREENSHOT_DIR")]
+    screenshot_dir: PathBuf = screenshot::default_directory(),
+    /// Directory recordings are saved to
+    #[clap(long, env = "APE_RECORDING_DIR")]
+    recording_dir: PathBuf = recording::default_directory(),
+    /// Library file with the scanned games and their play times
+    #[clap(long, env = "APE_LIBRARY_FILE")]
+    library_file: PathBuf = library::default_path(),
+    /// Directory scanned for the game library, including subdirectories
+    #[clap(long = "library-dir", env = "APE_LIBRARY_DIRS", value_delimiter = ',')]
+    library_dirs: Vec<PathBuf> = Vec::new(),
+    /// Buildbot URL or local mirror directory cores are downloaded from
+    #[clap(long, env = "APE_BUILDBOT_URL")]
+    buildbot_url: buildbot::Source = buildbot::Source::default(),
+    /// Address the UDP remote interface listens on (IPv4 or IPv6)
+    #[clap(long, env = "APE_REMOTE_ADDRESS")]
+    remote_address: IpAddr = Ipv4Addr::LOCALHOST.into(),
+    #[clap(long, env = "APE_REMOTE_PORT")]
+    remote_port: u16 = remote::DEFAULT_PORT,
+    /// Allow the UDP remote interface to listen on non-loopback addresses
+    #[clap(
+        long,
+        env = "APE_REMOTE_LAN",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        value_parser = clap::builder::BoolishValueParser::new(),
+    )]
+    remote_lan: bool = false,
+    /// Non-loopback peer allowed to use the UDP remote interface (requires remote_lan)
+    #[clap(long, env = "APE_REMOTE_ALLOW", value_delimiter = ',')]
+    remote_allow: Vec<IpAddr> = Vec::new(),
+    /// Seconds of recent frames kept for instant replays, 0 disables them
+    #[clap(long, env = "APE_REPLAY_SECONDS")]
+    replay_seconds: f64 = replay::Config::default().seconds,
+    /// Memory the instant replay buffer may use, in MiB
+    #[clap(long, env = "APE_REPLAY_MEMORY")]
+    replay_memory: usize = replay::Config::default().max_memory / 1024 / 1024,
+    ;
+    /// Directory of libretro `.info` files adding to and overriding the bundled core database
+    #[clap(long, env = "APE_CORE_INFO_DIR")]
+    core_info_dir: Option<PathBuf>,
+    /// Directory of No-Intro and Redump DAT files (Logiqx XML) identifying library games
+    #[clap(long, env = "APE_DAT_DIR")]
+    dat_dir: Option<PathBuf>,
+    /// Directory with the base ROMs of Archipelago patches, defaults to the patch's directory
+    #[clap(long, env = "APE_BASE_ROM_DIR")]
+    base_rom_dir: Option<PathBuf>,
+}
+
+/// ape's directory in the user's config directory, e.g. `~/.config/ape/`.
+pub fn config_directory() -> PathBuf {
+    app_directory(dirs::config_dir())
+}
+
+/// ape's directory in the user's data directory, e.g. `~/.local/share/ape/`.
+pub fn data_directory() -> PathBuf {
+    app_directory(dirs::data_dir())
+}
+
+/// `name` in [`data_directory`], unless only `./name` exists, where earlier
+/// versions kept it.
+pub fn data_path(name: &str) -> PathBuf {
+    let path = data_directory().join(name);
+    let legacy = Path::new(name);
+
+    if !path.exists() && legacy.exists() {
+        legacy.to_owned()
+    } else {
+        path
+    }
+}
+
+/// The working directory if there is no home directory to put files in.
+fn app_directory(base: Option<PathBuf>) -> PathBuf {
+    base.map(|base| base.join(APP_NAME))
+        .unwrap_or_else(|| PathBuf::from("."))
+}
+
+/// Where a setting's value came from.
+#[derive(Clone, Debug)]
+pub enum Origin {
+    Default,
+    File(PathBuf),
+    Env,
+    CommandLine,
+}
+
+impl Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Origin::Default => write!(f, "default"),
+            Origin::File(path) => write!(f, "{}", path.display()),
+            Origin::Env => write!(f, "environment"),
+            Origin::CommandLine => write!(f, "command line"),
+        }
+    }
+}
+
+/// The effective settings together with where they came from.
+pub struct Config {
+    pub settings: Settings,
+    origins: BTreeMap<&'static str, Origin>,
+    /// The config files looked for, whether they exist or not.
+    files: Vec<(PathBuf, bool)>,
+}
+
+impl Config {
+    /// Layers the config files over the defaults, then `env` and `cli`.
+    ///
+    /// The per-core and per-game files are only looked for if `core` (e.g.
+    /// `gambatte`) and `rom` are given.
+    pub fn load(env: &Layer, cli: &Layer, core: Option<&str>, rom: Option<&Path>) -> Result<Self> {
+        Self::load_from(&config_directory(), env, cli, core, rom)
+    }
+
+    fn load_from(
+        config_directory: &Path,
+        env: &Layer,
+        cli: &Layer,
+        core: Option<&str>,
+        rom: Option<&Path>,
+    ) -> Result<Self> {
+        let core_path = core.map(|core| {
+            config_directory
+                .join(CORES_CONFIG_DIR)
+                .join(format!("{core}.toml"))
+        });
+        // named after the archive a ROM is in, like its saves and states
+        let game_path = rom
+            .and_then(|rom| archive::strip_entry(rom).file_stem())
+            .map(|stem| {
+                let mut name = stem.to_owned();
+                name.push(".toml");
+
+                config_directory.join(GAMES_CONFIG_DIR).join(name)
+            });
+        let paths = [
+            Some(config_directory.join(USER_CONFIG_NAME)),
+            core_path,
+            game_path,
+        ];
+
+        let mut config = Self {
+            settings: Settings::default(),
+            origins: BTreeMap::new(),
+            files: Vec::new(),
+        };
+
+        for path in paths.into_iter().flatten() {
+            let layer = Layer::load(&path)?;
+
+            config.files.push((path.clone(), layer.is_some()));
+
+            if let Some(layer) = layer {
+                config.apply(&layer, Origin::File(path));
+            }
+        }
+
+        config.apply(env, Origin::Env);
+        config.apply(cli, Origin::CommandLine);
+
+        Ok(config)
+    }
+
+    fn apply(&mut self, layer: &Layer, origin: Origin) {
+        self.settings.apply(layer, &origin, &mut self.origins);
+    }
+
+    /// The settings by key with their values, `None` if unset, and where they came from.
+    pub fn entries(&self) -> impl Iterator<Item = (&'static str, Option<toml::Value>, &Origin)> {
+        self.settings.values().into_iter().map(|(key, value)| {
+            let origin = self.origins.get(key).unwrap_or(&Origin::Default);
+
+            (key, value, origin)
+        })
+    }
+
+    /// The config files looked for, in order of precedence, and whether they exist.
+    pub fn files(&self) -> &[(PathBuf, bool)] {
+        &self.files
+    }
+}
+
+impl Layer {
+    /// Loads the config file at `path`, `None` if there is none.
+    fn load(path: &Path) -> Result<Option<Self>> {
+        let layer = match fs::read_to_string(path) {
+            Ok(layer) => layer,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err).with_context(|| format!("failed to read {path:?}")),
+        };
+
+        toml::from_str(&layer)
+            .map(Some)
+            .with_context(|| format!("invalid config file {path:?}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    /// A config directory with empty per-core and per-game directories.
+    fn config_dir() -> TempDir {
+        let dir = TempDir::new().unwrap();
+
+        fs::create_dir_all(dir.path().join(CORES_CONFIG_DIR)).unwrap();
+        fs::create_dir_all(dir.path().join(GAMES_CONFIG_DIR)).unwrap();
+
+        dir
+    }
+
+    fn origin<'a>(config: &'a Config, key: &str) -> &'a Origin {
+        config
+            .entries()
+            .find_map(|(entry_key, _, origin)| (entry_key == key).then_some(origin))
+            .unwrap()
+    }
+
+    #[test]
+    fn layers_files_env_and_command_line() {
+        let temp = config_dir();
+        let dir = temp.path();
+        let user = dir.join(USER_CONFIG_NAME);
+        let core = dir.join("cores/gambatte.toml");
+        let game = dir.join("games/Tetris (World).toml");
+
+        fs::write(
+            &user,
+            "saves_dir = \"/user/saves\"\nstates_dir = \"/user/states\"\nremote_port = 1\n\
+             replay_seconds = 1.0\nremote_lan = true\n",
+        )
+        .unwrap();
+        fs::write(
+            &core,
+            "states_dir = \"/core/states\"\nremote_port = 2\nreplay_seconds = 2.0\n",
+        )
+        .unwrap();
+        fs::write(&game, "remote_port = 3\ndat_dir = \"/game/dats\"\n").unwrap();
+
+        let env = Layer {
+            replay_seconds: Some(4.),
+            system_dir: Some("/env/system".into()),
+            ..Layer::default()
+        };
+        let cli = Layer {
+            system_dir: Some("/cli/system".into()),
+            ..Layer::default()
+        };
+        let rom = Path::new("/roms/Tetris (World).gb");
+        let config = Config::load_from(dir, &env, &cli, Some("gambatte"), Some(rom)).unwrap();
+        let settings = &config.settings;
+
+        assert_eq!(settings.saves_dir, Path::new("/user/saves"));
+        assert_eq!(settings.states_dir, Path::new("/core/states"));
+        assert_eq!(settings.remote_port, 3);
+        assert_eq!(settings.dat_dir.as_deref(), Some(Path::new("/game/dats")));
+        assert_eq!(settings.replay_seconds, 4.);
+        assert_eq!(settings.system_dir, Path::new("/cli/system"));
+        assert!(settings.remote_lan);
+        assert_eq!(settings.core_info_dir, None);
+
+        assert!(matches!(origin(&config, "saves_dir"), Origin::File(path) if *path == user));
+        assert!(matches!(origin(&config, "states_dir"), Origin::File(path) if *path == core));
+        assert!(matches!(origin(&config, "remote_port"), Origin::File(path) if *path == game));
+        assert!(matches!(origin(&config, "replay_seconds"), Origin::Env));
+        assert!(matches!(origin(&config, "system_dir"), Origin::CommandLine));
+        assert!(matches!(origin(&config, "cores_dir"), Origin::Default));
+
+        let core_info_dir = config
+            .entries()
+            .find(|(key, ..)| *key == "core_info_dir")
+            .unwrap();
+
+        assert_eq!(core_info_dir.1, None);
+        assert_eq!(
+            config.files(),
+            [(user, true), (core, true), (game, true)].as_slice()
+        );
+    }
+
+    #[test]
+    fn looks_for_core_and_game_files_only_when_given() {
+        let temp = config_dir();
+        let dir = temp.path();
+        let config =
+            Config::load_from(dir, &Layer::default(), &Layer::default(), None, None).unwrap();
+
+        assert_eq!(
+            config.files(),
+            [(dir.join(USER_CONFIG_NAME), false)].as_slice()
+        );
+        assert!(config
+            .entries()
+            .all(|(_, _, origin)| matches!(origin, Origin::Default)));
+
+        let rom = Path::new("roms/game.zip");
+        let config = Config::load_from(
+            dir,
+            &Layer::default(),
+            &Layer::default(),
+            Some("mgba"),
+            Some(rom),
+        )
+        .unwrap();
+        let files = config.files().iter().map(|(path, _)| path);
+
+        assert!(files.eq([
+            dir.join(USER_CONFIG_NAME),
+            dir.join("cores/mgba.toml"),
+            dir.join("games/game.toml"),
+        ]
+        .iter()));
+    }
+
+    #[test]
+    fn names_game_files_after_the_archive() {
+        let temp = config_dir();
+        let dir = temp.path();
+        let archive = dir.join("Collection.zip");
+
+        fs::copy(
+            concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/tests/fixtures/archive/roms.zip"
+            ),
+            &archive,
+        )
+        .unwrap();
+
+        let rom = PathBuf::from(format!("{}#Game (World).gb", archive.display()));
+        let config =
+            Config::load_from(dir, &Layer::default(), &Layer::default(), None, Some(&rom)).unwrap();
+
+        assert_eq!(config.files()[1].0, dir.join("games/Collection.toml"));
+    }
+
+    #[test]
+    fn rejects_unknown_and_mistyped_keys() {
+        let temp = config_dir();
+        let dir = temp.path();
+        let user = dir.join(USER_CONFIG_NAME);
+        let load = || Config::load_from(dir, &Layer::default(), &Layer::default(), None, None);
+
+        fs::write(&user, "save_dir = \"/saves\"\n").unwrap();
+        assert!(format!("{:#}", load().err().unwrap()).contains("invalid config file"));
+
+        fs::write(&user, "remote_port = \"many\"\n").unwrap();
+        assert!(load().is_err());
+    }
+}
@@ -1,8 +1,8 @@
 use core::slice;
 use std::borrow::Cow;
-use std::ffi::CStr;
-use std::fs;
+use std::ffi::{c_uint, CStr, CString};
 use std::io::Write;
+use std::mem;
 use std::os::raw::c_void;
 use std::path::Path;
 use std::path::PathBuf;
@@ -16,73 +16,117 @@ use libretro_sys::GameGeometry;
 use libretro_sys::GameInfo;
 use libretro_sys::SystemAvInfo;
 use libretro_sys::SystemTiming;
+use libretro_sys::{MEMORY_RTC, MEMORY_SAVE_RAM, MEMORY_SYSTEM_RAM, MEMORY_VIDEO_RAM};
 use sha1::Digest;
 use sha1::Sha1;
 
 use self::api::Api;
+use self::context::Context as InstanceContext;
+use crate::archive;
+use crate::cheat::{self, Cheat, Cheats};
+use crate::core_info;
+use crate::patch;
+use crate::recording::{Recording, RecordingFiles};
+use crate::replay::{self, Replay};
+use crate::system::System;
 
 mod api;
 
 mod callbacks;
 pub use callbacks::*;
 
+mod context;
+
 mod memory_map;
 pub use memory_map::*;
 
 mod state;
-pub use state::*;
+pub(crate) use state::*;
 
 mod handle;
 pub use handle::*;
 
 const EXPECTED_LIB_RETRO_VERSION: u32 = 1;
 
+/// Regions used to synthesize a memory map, with the address space they are
+/// placed in if the system has no known bus location for them.
+const FALLBACK_REGIONS: [(c_uint, &str); 4] = [
+    (MEMORY_SYSTEM_RAM, "RAM"),
+    (MEMORY_SAVE_RAM, "SRAM"),
+    (MEMORY_VIDEO_RAM, "VRAM"),
+    (MEMORY_RTC, "RTC"),
+];
+
+/// A loaded libretro core with its content.
+///
+/// Dropping a `Core` unloads the content and deinitializes the core. Cores are
+/// not `Send`, all calls into them have to happen on the thread that loaded them.
+/// Other threads can use a [`Handle`] instead.
 pub struct Core {
     api: Api,
+    context: Box<InstanceContext>,
+    paused: bool,
+    cheats: Cheats,
+    /// Soft patches applied to the ROM whenever it is loaded.
+    patches: Vec<PathBuf>,
+    /// The extracted or patched ROM for cores that need a path to load it from.
+    content_file: Option<archive::TempFile>,
+    is_game_loaded: bool,
+    /// The save RAM of content that failed to reload, restored once a reload succeeds.
+    unloaded_save_ram: Option<Vec<u8>>,
 }
 
 impl Core {
-    pub fn load<F, R>(config: Config, f: F) -> Result<R>
-    where
-        F: FnOnce(&mut Core) -> R,
-    {
+    /// Loads the core library and the content described by `config`.
+    pub fn new(config: Config) -> Result<Self> {
         unsafe {
-            let is_core_loaded = STATE.with_borrow_mut(|state| state.is_core_loaded);
-
-            if is_core_loaded {
-                bail!("only one core per thread allowed")
-            }
-
-            // TODO: prevent the same core from being loaded more than once in the same process
-
             let api = Api::load(config.core)?;
 
-            let mut core = Core { api };
-
-            core.check_api_version_match()?;
-            core.register_callbacks(config.callbacks);
-            (core.api.retro_init)();
-
-            if let Err(err) = core.load_game(&config.rom) {
-                (core.api.retro_deinit)();
-
-                return Err(err.context("failed to load game"));
-            }
-
-            let res = f(&mut core);
-
-            (core.api.retro_unload_game)();
-            (core.api.retro_deinit)();
-
-            callbacks::drop();
+            Self::check_api_version_match(&api)?;
+
+            let system_directory = config
+                .system_directory
+                .as_deref()
+                .map(|system_directory| {
+                    let system_directory = system_directory
+                        .to_str()
+                        .context("system directory is not valid UTF-8")?;
+
+                    CString::new(system_directory).context("invalid system directory")
+                })
+                .transpose()?;
+            let mut core = Core {
+                api,
+                context: Box::new(InstanceContext::new(config.callbacks)),
+                paused: false,
+                cheats: Cheats::default(),
+                patches: config.patches,
+                content_file: None,
+                is_game_loaded: false,
+                unloaded_save_ram: None,
+            };
+
+            // cores may ask for it as early as `retro_set_environment`
+            core.with_state_mut(|state| state.system_directory = system_directory);
+            core.register_callbacks();
+            core.with_context(|core| (core.api.retro_init)());
+            core.load_game(&config.rom).context("failed to load game")?;
+
+            Ok(core)
+        }
+    }
 
-            STATE.set(State::new());
+    /// Loads a core, runs `f` with it and unloads it again.
+    pub fn load<F, R>(config: Config, f: F) -> Result<R>
+    where
+        F: FnOnce(&mut Core) -> R,
+    {
+        let mut core = Self::new(config)?;
 
-            Ok(res)
-        }
+        Ok(f(&mut core))
     }
 
-    pub fn get_system_info(&self) -> SystemInfo {
+    pub fn get_system_info(&self) -> SystemInfo<'_> {
         let mut system_info = libretro_sys::SystemInfo {
             library_name: null(),
             library_version: null(),
@@ -91,11 +135,11 @@ impl Core {
             block_extract: false,
         };
 
-        unsafe {
-            (self.api.retro_get_system_info)(&mut system_info);
+        self.with_context(|core| unsafe {
+            (core.api.retro_get_system_info)(&mut system_info);
 
             SystemInfo::from_raw(system_info)
-        }
+        })
     }
 
     pub fn get_system_av_info(&self) -> SystemAvInfo {
@@ -113,23 +157,80 @@ impl Core {
             },
         };
 
-        unsafe {
-            (self.api.retro_get_system_av_info)(&mut system_av_info);
-        }
+        self.with_context(|core| unsafe {
+            (core.api.retro_get_system_av_info)(&mut system_av_info);
+        });
 
         system_av_info
     }
 
+    /// Runs one frame. Does nothing if no content is loaded, e.g. after a
+    /// failed [`Core::reload_game`].
     pub fn run(&mut self) {
-        unsafe { (self.api.retro_run)() }
+        if !self.is_game_loaded {
+            return;
+        }
+
+        self.with_context(|core| unsafe { (core.api.retro_run)() });
+
+        self.apply_ram_cheats();
+    }
+
+    /// Resets the content like the console's reset button would.
+    pub fn reset(&mut self) {
+        if !self.is_game_loaded {
+            return;
+        }
+
+        self.with_context(|core| unsafe { (core.api.retro_reset)() });
+    }
+
+    /// Unloads the content and loads it again from disk, keeping the save RAM.
+    ///
+    /// If loading fails no content is loaded anymore and [`Core::run`] and
+    /// [`Core::reset`] do nothing, until reloading succeeds.
+    pub fn reload_game(&mut self) -> Result<()> {
+        let rom_path = self.rom_path().context("no content loaded")?;
+
+        if self.is_game_loaded {
+            self.unloaded_save_ram = Some(self.get_save_ram().to_vec());
+            self.with_context(|core| unsafe { (core.api.retro_unload_game)() });
+            self.is_game_loaded = false;
+            self.with_state_mut(|state| state.memory_map = MemoryMap::empty());
+        }
+
+        unsafe { self.load_game(&rom_path) }.context("failed to reload game")?;
+
+        if let Some(save_ram) = self.unloaded_save_ram.take() {
+            self.restore_save_ram(&save_ram);
+        }
+
+        self.apply_core_cheats();
+
+        Ok(())
+    }
+
+    /// Whether the core asked the frontend to exit, e.g. from an in-game menu.
+    pub fn is_shutdown_requested(&self) -> bool {
+        self.with_state(|state| state.shutdown_requested)
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
     }
 
     pub fn state(&mut self) -> Result<Vec<u8>> {
         unsafe {
-            let size = (self.api.retro_serialize_size)();
+            let size = self.with_context(|core| (core.api.retro_serialize_size)());
             let mut state = Vec::<u8>::with_capacity(size);
 
-            let success = (self.api.retro_serialize)(state.as_mut_ptr().cast::<c_void>(), size);
+            let success = self.with_context(|core| {
+                (core.api.retro_serialize)(state.as_mut_ptr().cast::<c_void>(), size)
+            });
 
             if !success {
                 bail!("state serialization failed");
@@ -143,8 +244,9 @@ impl Core {
 
     pub fn restore_state(&mut self, state: &[u8]) -> Result<()> {
         unsafe {
-            let success =
-                (self.api.retro_unserialize)(state.as_ptr().cast::<c_void>(), state.len());
+            let success = self.with_context(|core| {
+                (core.api.retro_unserialize)(state.as_ptr().cast::<c_void>(), state.len())
+            });
 
             if !success {
                 bail!("failed to restore state");
@@ -154,43 +256,219 @@ impl Core {
         }
     }
 
+    /// Encodes the last frame the core rendered as PNG, before any scaling.
+    pub fn screenshot(&self) -> Result<Vec<u8>> {
+        self.with_state(|state| {
+            state
+                .last_frame
+                .as_ref()
+                .context("no frame rendered yet")?
+                .to_png()
+        })
+    }
+
+    /// Starts recording video and audio to `directory`, see [`Recording`].
+    pub fn start_recording(&mut self, directory: &Path) -> Result<()> {
+        if self.is_recording() {
+            bail!("already recording");
+        }
+
+        let timing = self.get_system_av_info().timing;
+        let rom_path = self.rom_path();
+        let recording = Recording::start(
+            directory,
+            rom_path.as_deref(),
+            timing.fps,
+            timing.sample_rate,
+        )?;
+
+        self.with_state_mut(|state| state.recording = Some(recording));
+
+        Ok(())
+    }
+
+    /// Stops recording and returns the files written, or `None` if not recording.
+    pub fn stop_recording(&mut self) -> Result<Option<RecordingFiles>> {
+        self.with_state_mut(|state| state.recording.take())
+            .map(Recording::finish)
+            .transpose()
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.with_state(|state| state.recording.is_some())
+    }
+
+    /// Starts buffering recent frames for instant replays, replacing an existing buffer.
+    pub fn start_replay(&mut self, config: replay::Config) {
+        let fps = self.with_state(|state| state.fps);
+        let fps = if fps > 0. { fps } else { 60. };
+
+        self.with_state_mut(|state| state.replay = Some(Replay::start(config, fps)));
+    }
+
+    /// The replay buffer, if one was started.
+    pub fn replay(&self) -> Option<Replay> {
+        self.with_state(|state| state.replay.clone())
+    }
+
     pub fn rom<R>(&self, f: impl FnOnce(&[u8]) -> R) -> R {
-        STATE.with_borrow(|state| f(&state.rom))
+        self.with_state(|state| f(&state.rom))
     }
 
     pub fn get_sha1_romhash(&self) -> String {
-        STATE.with_borrow(|state| state.sha1_romhash.clone())
+        self.with_state(|state| state.sha1_romhash.clone())
+    }
+
+    pub fn get_crc32_romhash(&self) -> u32 {
+        self.with_state(|state| state.crc32_romhash)
+    }
+
+    /// Path of the loaded content, or `None` if no content is loaded.
+    pub fn rom_path(&self) -> Option<PathBuf> {
+        self.with_state(|state| state.rom_path.clone())
+    }
+
+    pub fn has_memory_map(&self) -> bool {
+        self.with_state(|state| !state.memory_map.is_empty())
     }
 
+    /// Loads cheats from a RetroArch `.cht` file and returns how many were loaded.
+    ///
+    /// Cheats that were enabled for the loaded ROM before are enabled again.
+    pub fn load_cheats(&mut self, path: &Path) -> Result<usize> {
+        let rom_path = self.rom_path().context("no content loaded")?;
+        let system = System::detect(self);
+        let library_name = self.get_system_info().library_name.into_owned();
+        let state_path = archive::strip_entry(&rom_path).with_extension("cheats.json");
+
+        self.cheats = Cheats::load(path, state_path, system, &library_name)?;
+        self.apply_core_cheats();
+
+        Ok(self.cheats.as_slice().len())
+    }
+
+    pub fn cheats(&self) -> &[Cheat] {
+        self.cheats.as_slice()
+    }
+
+    pub fn set_cheat_enabled(&mut self, index: usize, enabled: bool) -> Result<()> {
+        self.cheats.set_enabled(index, enabled)?;
+        self.apply_core_cheats();
+
+        Ok(())
+    }
+
+    /// Hands the enabled cheats the core handles itself to the core.
+    fn apply_core_cheats(&mut self) {
+        self.with_context(|core| unsafe {
+            (core.api.retro_cheat_reset)();
+
+            for (index, code) in core.cheats.core_codes().enumerate() {
+                (core.api.retro_cheat_set)(index as c_uint, true, code.as_ptr());
+            }
+        });
+    }
+
+    /// Writes the enabled cheats the core can't handle to memory.
+    fn apply_ram_cheats(&mut self) {
+        let cheats = mem::take(&mut self.cheats);
+
+        for write in cheats.ram_writes() {
+            match write.target {
+                cheat::Target::SystemBus(address) => self.write_memory(address, &write.data),
+                cheat::Target::SystemRam(offset) => self.write_system_ram(offset, &write.data),
+            };
+        }
+
+        self.cheats = cheats;
+    }
+
+    pub fn memory_map_source(&self) -> MemoryMapSource {
+        self.with_state(|state| state.memory_map.source())
+    }
+
+    pub fn system_bus_size(&self) -> usize {
+        self.with_state(|state| state.memory_map.end())
+    }
+
+    /// Reads up to `max_len` bytes from the system bus described by the core's memory map.
     pub fn get_memory(&self, address: usize, max_len: usize) -> Vec<u8> {
-        STATE.with_borrow(|state| unsafe {
-            state
-                .memory_map
-                .get_slice(address, max_len)
-                .unwrap_or_default()
-                .to_vec()
-        })
+        self.with_state(|state| unsafe { state.memory_map.read(address, max_len) })
     }
 
+    /// Writes `bytes` to the system bus and returns the number of bytes written.
+    /// Writing stops at unmapped addresses and read-only descriptors.
     pub fn write_memory(&mut self, address: usize, bytes: &[u8]) -> usize {
-        STATE.with_borrow(|state| unsafe {
-            let slice = state
+        self.with_state(|state| unsafe { state.memory_map.write(address, bytes) })
+    }
+
+    /// Writes `bytes` at `offset` into the system RAM and returns the number of
+    /// bytes written.
+    ///
+    /// The system RAM is `RETRO_MEMORY_SYSTEM_RAM`, or if the core doesn't expose
+    /// it, the memory map's descriptors flagged as system RAM, one after another,
+    /// like RetroArch's cheat manager sees it.
+    pub fn write_system_ram(&mut self, offset: usize, bytes: &[u8]) -> usize {
+        let system_ram = self.get_memory_region_mut(MEMORY_SYSTEM_RAM);
+
+        if system_ram.is_empty() {
+            return self
+                .with_state(|state| unsafe { state.memory_map.write_system_ram(offset, bytes) });
+        }
+
+        let Some(system_ram) = system_ram.get_mut(offset..) else {
+            return 0;
+        };
+        let len = system_ram.len().min(bytes.len());
+
+        system_ram[..len].copy_from_slice(&bytes[..len]);
+
+        len
+    }
+
+    pub fn is_memory_read_only(&self, address: usize) -> bool {
+        self.with_state(|state| state.memory_map.is_read_only(address))
+    }
+
+    /// Names of the address spaces of the memory map besides the system bus.
+    pub fn address_spaces(&self) -> Vec<String> {
+        self.with_state(|state| {
+            state
                 .memory_map
-                .get_slice_mut(address, bytes.len())
-                .unwrap_or_default();
+                .address_space_names()
+                .map(str::to_owned)
+                .collect()
+        })
+    }
 
-            let len = slice.len().min(bytes.len());
-            slice[..len].copy_from_slice(&bytes[..len]);
+    pub fn address_space_size(&self, address_space: &str) -> usize {
+        self.with_state(|state| state.memory_map.address_space_end(address_space))
+    }
 
-            len
+    pub fn get_address_space_memory(
+        &self,
+        address_space: &str,
+        address: usize,
+        max_len: usize,
+    ) -> Vec<u8> {
+        self.with_state(|state| unsafe {
+            state.memory_map.read_in(address_space, address, max_len)
         })
     }
 
-    pub fn get_save_ram(&self) -> &[u8] {
+    pub fn write_address_space_memory(
+        &mut self,
+        address_space: &str,
+        address: usize,
+        bytes: &[u8],
+    ) -> usize {
+        self.with_state(|state| unsafe { state.memory_map.write_in(address_space, address, bytes) })
+    }
+
+    /// Returns one of the `libretro_sys::MEMORY_*` regions of the core.
+    pub fn get_memory_region(&self, region: c_uint) -> &[u8] {
         unsafe {
-            let region = libretro_sys::MEMORY_SAVE_RAM;
-            let ptr = (self.api.retro_get_memory_data)(region);
-            let len = (self.api.retro_get_memory_size)(region);
+            let (ptr, len) = self.memory_region_raw(region);
 
             if ptr.is_null() || len == 0 {
                 return &[];
@@ -200,11 +478,9 @@ impl Core {
         }
     }
 
-    pub fn get_save_ram_mut(&mut self) -> &mut [u8] {
+    pub fn get_memory_region_mut(&mut self, region: c_uint) -> &mut [u8] {
         unsafe {
-            let region = libretro_sys::MEMORY_SAVE_RAM;
-            let ptr = (self.api.retro_get_memory_data)(region);
-            let len = (self.api.retro_get_memory_size)(region);
+            let (ptr, len) = self.memory_region_raw(region);
 
             if ptr.is_null() || len == 0 {
                 return &mut [];
@@ -214,6 +490,14 @@ impl Core {
         }
     }
 
+    pub fn get_save_ram(&self) -> &[u8] {
+        self.get_memory_region(libretro_sys::MEMORY_SAVE_RAM)
+    }
+
+    pub fn get_save_ram_mut(&mut self) -> &mut [u8] {
+        self.get_memory_region_mut(libretro_sys::MEMORY_SAVE_RAM)
+    }
+
     pub fn restore_save_ram(&mut self, data: &[u8]) {
         let save_ram = self.get_save_ram_mut();
         let len = save_ram.len().min(data.len());
@@ -221,9 +505,15 @@ impl Core {
         save_ram[..len].copy_from_slice(&data[..len]);
     }
 
+    /// Does nothing while no content is loaded, so that a failed reload doesn't
+    /// overwrite the save.
     pub fn save_sram_to(&self, sram_path: impl AsRef<Path>) -> Result<()> {
         let sram_path = sram_path.as_ref();
 
+        if !self.is_game_loaded {
+            return Ok(());
+        }
+
         AtomicFile::new(sram_path, OverwriteBehavior::AllowOverwrite)
             .write(|file| file.write_all(self.get_save_ram()))
             .with_context(|| format!("Failed to save SRAM to {sram_path:?}"))?;
@@ -233,8 +523,8 @@ impl Core {
 }
 
 impl Core {
-    unsafe fn check_api_version_match(&mut self) -> Result<()> {
-        let api_version = (self.api.retro_api_version)();
+    unsafe fn check_api_version_match(api: &Api) -> Result<()> {
+        let api_version = (api.retro_api_version)();
 
         if api_version != EXPECTED_LIB_RETRO_VERSION {
             bail!(
@@ -246,8 +536,34 @@ impl Core {
         Ok(())
     }
 
-    unsafe fn register_callbacks(&mut self, callbacks: Box<dyn Callbacks>) {
-        callbacks::register(callbacks);
+    /// The pointer and size of one of the `libretro_sys::MEMORY_*` regions.
+    fn memory_region_raw(&self, region: c_uint) -> (*mut c_void, usize) {
+        self.with_context(|core| unsafe {
+            (
+                (core.api.retro_get_memory_data)(region),
+                (core.api.retro_get_memory_size)(region),
+            )
+        })
+    }
+
+    /// Runs `f` with this instance's context entered, so that callbacks
+    /// the core makes during `f` are routed to this instance.
+    fn with_context<R>(&self, f: impl FnOnce(&Self) -> R) -> R {
+        let _context = self.context.enter();
+
+        f(self)
+    }
+
+    fn with_state<R>(&self, f: impl FnOnce(&State) -> R) -> R {
+        f(&self.context.state.borrow())
+    }
+
+    fn with_state_mut<R>(&self, f: impl FnOnce(&mut State) -> R) -> R {
+        f(&mut self.context.state.borrow_mut())
+    }
+
+    unsafe fn register_callbacks(&mut self) {
+        let _context = self.context.enter();
 
         (self.api.retro_set_environment)(callbacks::ffi::environment);
         (self.api.retro_set_video_refresh)(callbacks::ffi::video_refresh);
@@ -257,37 +573,154 @@ impl Core {
         (self.api.retro_set_input_state)(callbacks::ffi::input_state);
     }
 
-    unsafe fn load_game(&mut self, rom: impl AsRef<Path>) -> Result<()> {
-        let rom = fs::read(rom).context("Failed to read rom")?;
+    unsafe fn load_game(&mut self, rom_path: impl AsRef<Path>) -> Result<()> {
+        let rom_path = rom_path.as_ref();
+        let system_info = self.get_system_info();
+        let valid_extensions = system_info.valid_extensions.split('|').collect::<Vec<_>>();
+        let (entry, mut rom) =
+            archive::read_rom_entry(rom_path, &valid_extensions, system_info.block_extract)
+                .context("Failed to read rom")?;
+
+        for patch_path in &self.patches {
+            rom = patch::apply(patch_path, &rom)?;
+            eprintln!("Applied patch {patch_path:?}");
+        }
 
-        // TODO: ask core whether to provide path or data
-        let game_info = GameInfo {
-            path: null(),
-            data: rom.as_ptr().cast(),
-            size: rom.len(),
-            meta: null(),
+        // cores that need a path get the file, or a temporary one if the ROM
+        // on disk is not what they should load
+        let mut content_file = None;
+
+        let content_path = if !system_info.need_fullpath {
+            None
+        } else if entry.is_some() || !self.patches.is_empty() {
+            let file_name = entry
+                .as_deref()
+                .or_else(|| archive::strip_entry(rom_path).file_name()?.to_str())
+                .context("invalid ROM file name")?;
+            let file = archive::TempFile::create(file_name, &rom)
+                .context("failed to write the ROM for the core")?;
+
+            eprintln!("Core needs a path, loading from {:?}", file.path());
+
+            Some(content_file.insert(file).path().to_owned())
+        } else {
+            Some(archive::strip_entry(rom_path).to_owned())
         };
+        let content_path = content_path
+            .map(|path| {
+                let path = path.to_str().context("ROM path is not valid UTF-8")?;
+
+                CString::new(path).context("invalid ROM path")
+            })
+            .transpose()?;
+
+        let game_info = match &content_path {
+            Some(path) => GameInfo {
+                path: path.as_ptr(),
+                data: null(),
+                size: 0,
+                meta: null(),
+            },
+            None => GameInfo {
+                path: null(),
+                data: rom.as_ptr().cast(),
+                size: rom.len(),
+                meta: null(),
+            },
+        };
+
+        self.content_file = content_file;
 
-        let load_game_successful = (self.api.retro_load_game)(&game_info);
-        STATE.with_borrow_mut(|state| {
+        let load_game_successful = self.with_context(|core| (core.api.retro_load_game)(&game_info));
+        self.with_state_mut(|state| {
             let sha1_romhash = Sha1::digest(&rom);
             let sha1_romhash = hex::encode(sha1_romhash);
+            let crc32_romhash = crc32fast::hash(&rom);
 
             state.rom = rom;
+            state.rom_path = Some(rom_path.to_owned());
             state.sha1_romhash = sha1_romhash;
+            state.crc32_romhash = crc32_romhash;
         });
 
         if !load_game_successful {
             bail!("Failed to load game");
         }
 
+        self.is_game_loaded = true;
+
+        let fps = self.get_system_av_info().timing.fps;
+        self.with_state_mut(|state| state.fps = fps);
+
+        if !self.has_memory_map() {
+            self.load_fallback_memory_map();
+        }
+
         Ok(())
     }
+
+    /// Synthesizes a memory map from the `MEMORY_*` regions for cores that
+    /// don't call `SET_MEMORY_MAPS`.
+    fn load_fallback_memory_map(&mut self) {
+        let system = System::detect(self);
+
+        let descriptors = FALLBACK_REGIONS
+            .iter()
+            .filter_map(|&(region, address_space)| {
+                let (ptr, len) = self.memory_region_raw(region);
+                let ptr = ptr.cast::<u8>();
+
+                if ptr.is_null() || len == 0 {
+                    return None;
+                }
+
+                let location = system.and_then(|system| system.region_location(region));
+
+                Some(match location {
+                    Some(location) => {
+                        Descriptor::new(ptr, location.start, len.min(location.len), "")
+                    }
+                    // Without a known location system RAM is mapped at the start of the bus,
+                    // like RetroAchievements does for such systems.
+                    None if region == MEMORY_SYSTEM_RAM => Descriptor::new(ptr, 0, len, ""),
+                    None => Descriptor::new(ptr, 0, len, address_space),
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let memory_map = MemoryMap::from_regions(descriptors);
+
+        if !memory_map.is_empty() {
+            eprintln!("core provides no memory map, using memory regions instead");
+        }
+
+        self.with_state_mut(|state| state.memory_map = memory_map);
+    }
+}
+
+impl Drop for Core {
+    fn drop(&mut self) {
+        if let Err(err) = self.stop_recording() {
+            eprintln!("Failed to finish recording: {err:#}");
+        }
+
+        self.with_context(|core| unsafe {
+            if core.is_game_loaded {
+                (core.api.retro_unload_game)();
+            }
+
+            (core.api.retro_deinit)();
+        });
+    }
 }
 
 pub struct Config {
     pub core: PathBuf,
     pub rom: PathBuf,
+    /// IPS, UPS or BPS patches applied to the ROM in memory, in order.
+    pub patches: Vec<PathBuf>,
+    /// Directory cores load firmware from, `GET_SYSTEM_DIRECTORY` fails without one.
+    pub system_directory: Option<PathBuf>,
     pub callbacks: Box<dyn Callbacks>,
 }
 
@@ -319,7 +752,10 @@ impl SystemInfo<'_> {
             .unwrap_or_default();
 
         SystemInfo {
-            system_id: system_id_from_library_name(&library_name),
+            system_id: core_info::database()
+                .by_library_name(&library_name)
+                .and_then(|core| core.system_id.as_deref())
+                .or_else(|| system_id_from_library_name(&library_name)),
             library_name,
             library_version,
             valid_extensions,
@@ -340,9 +776,32 @@ impl SystemInfo<'_> {
     }
 }
 
+/// Maps the library names of cores missing from the core database to the
+/// system id RetroArch uses for them (the `systemid` field of their info file).
 fn system_id_from_library_name(library_name: &str) -> Option<&'static str> {
     Some(match library_name {
-        "Gambatte" | "SameBoy" => "game_boy",
+        "Gambatte" | "SameBoy" | "Gearboy" | "TGB Dual" | "DoubleCherryGB" => "game_boy",
+        "mGBA" | "VBA-M" | "VBA Next" | "gpSP" | "Beetle GBA" => "game_boy_advance",
+        "Nestopia" | "FCEUmm" | "QuickNES" | "Mesen" | "bnes" => "nes",
+        "Snes9x" | "Snes9x 2010" | "Snes9x 2005" | "Snes9x 2005 Plus" | "Snes9x 2002" | "bsnes"
+        | "bsnes-hd beta" | "bsnes-mercury" | "bsnes 2014" | "Beetle bsnes" | "Mesen-S" => {
+            "super_nes"
+        }
+        "Mupen64Plus-Next" | "ParaLLEl N64" => "n64",
+        "Genesis Plus GX" | "Genesis Plus GX Wide" | "PicoDrive" | "BlastEm" => "mega_drive",
+        "Gearsystem" | "SMS Plus GX" => "master_system",
+        "Beetle PSX" | "Beetle PSX HW" | "PCSX-ReARMed" | "SwanStation" => "playstation",
+        "melonDS" | "melonDS DS" | "DeSmuME" => "nds",
+        "Beetle PCE" | "Beetle PCE Fast" | "Beetle SuperGrafx" => "pc_engine",
+        "Beetle Saturn" | "Yabause" | "Kronos" => "sega_saturn",
+        "Beetle WonderSwan" => "wonderswan",
+        "Beetle NeoPop" | "RACE" => "neo_geo_pocket",
+        "Beetle VB" => "virtual_boy",
+        "Beetle Lynx" | "Handy" => "atari_lynx",
+        "Stella" | "Stella 2014" => "atari_2600",
+        "ProSystem" => "atari_7800",
+        "Flycast" => "dreamcast",
+        "PPSSPP" => "psp",
         _ => return None,
     })
 }
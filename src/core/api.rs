@@ -1,14 +1,24 @@
+use std::collections::HashSet;
 use std::ffi::c_void;
 use std::ops::Deref;
-use std::path::Path;
-use std::ptr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::LazyLock;
+use std::{env, fs, process, ptr};
 
 use anyhow::{Context, Result};
 use libloading::Library;
 use libretro_sys::CoreAPI;
+use parking_lot::Mutex;
+
+/// Core libraries that are loaded from their original path.
+static LOADED_LIBRARIES: LazyLock<Mutex<HashSet<PathBuf>>> = LazyLock::new(<_>::default);
+static NEXT_COPY_ID: AtomicUsize = AtomicUsize::new(0);
 
 pub(super) struct Api {
     _library: Library,
+    // dropped after the library is unloaded
+    _library_file: LibraryFile,
     core_api: CoreAPI,
     _opt_out_of_send_sync: *const (),
     pub retro_serialize: unsafe extern "C" fn(data: *mut c_void, size: usize) -> bool,
@@ -18,7 +28,8 @@ impl Api {}
 
 impl Api {
     pub unsafe fn load(path: impl AsRef<Path>) -> Result<Self> {
-        let library = Library::new(path.as_ref()).context("failed to load core library")?;
+        let library_file = LibraryFile::acquire(path.as_ref())?;
+        let library = Library::new(&library_file.path).context("failed to load core library")?;
         let core_api = CoreAPI {
             retro_set_environment: deref_symbol(&library, "retro_set_environment")?,
             retro_set_video_refresh: deref_symbol(&library, "retro_set_video_refresh")?,
@@ -62,6 +73,7 @@ impl Api {
 
         Ok(Self {
             _library: library,
+            _library_file: library_file,
             core_api,
             _opt_out_of_send_sync: ptr::null(),
             retro_serialize,
@@ -69,6 +81,64 @@ impl Api {
     }
 }
 
+/// The file a core library is loaded from.
+///
+/// A library's globals are shared by everyone who loads it, so when a core is
+/// already loaded by another instance it is copied to a unique temporary path first.
+struct LibraryFile {
+    path: PathBuf,
+    is_copy: bool,
+}
+
+impl LibraryFile {
+    fn acquire(path: &Path) -> Result<Self> {
+        let path = fs::canonicalize(path)
+            .with_context(|| format!("failed to resolve core path {path:?}"))?;
+
+        if LOADED_LIBRARIES.lock().insert(path.clone()) {
+            return Ok(Self {
+                path,
+                is_copy: false,
+            });
+        }
+
+        let copy_dir = env::temp_dir().join("ape-cores");
+        let file_name = format!(
+            "{}-{}-{}.{}",
+            path.file_stem().unwrap_or_default().to_string_lossy(),
+            process::id(),
+            NEXT_COPY_ID.fetch_add(1, Ordering::Relaxed),
+            path.extension().unwrap_or_default().to_string_lossy(),
+        );
+        let copy_path = copy_dir.join(file_name);
+
+        fs::create_dir_all(&copy_dir)
+            .with_context(|| format!("failed to create core copy directory {copy_dir:?}"))?;
+        fs::copy(&path, &copy_path)
+            .with_context(|| format!("failed to copy core library to {copy_path:?}"))?;
+
+        eprintln!("{path:?} is already loaded, loading a copy from {copy_path:?}");
+
+        Ok(Self {
+            path: copy_path,
+            is_copy: true,
+        })
+    }
+}
+
+impl Drop for LibraryFile {
+    fn drop(&mut self) {
+        if !self.is_copy {
+            LOADED_LIBRARIES.lock().remove(&self.path);
+            return;
+        }
+
+        if let Err(err) = fs::remove_file(&self.path) {
+            eprintln!("Failed to remove core copy {:?}: {err}", self.path);
+        }
+    }
+}
+
 unsafe fn deref_symbol<T: Copy>(library: &Library, symbol: &str) -> Result<T> {
     let item = library
         .get::<T>(symbol.as_bytes())
@@ -1,11 +1,14 @@
-use std::ffi::{c_uint, c_void};
+use std::ffi::{c_char, c_uint, c_void, CStr};
 use std::slice;
+use std::time::Duration;
 
-use libretro_sys::{PixelFormat, DEVICE_JOYPAD};
+use libretro_sys::{LogLevel, PixelFormat, DEVICE_JOYPAD};
 
-use crate::core::{MemoryMap, CALLBACKS, STATE};
-use crate::environment::Command;
+use crate::core::context::{self, Context};
+use crate::core::{Callbacks, MemoryMap, State};
+use crate::environment::{self, Command, MessageExt};
 use crate::input::Button;
+use crate::osd::{self, Notification};
 use crate::video::Frame;
 
 pub unsafe extern "C" fn video_refresh(
@@ -14,28 +17,58 @@ pub unsafe extern "C" fn video_refresh(
     height: c_uint,
     pitch: usize,
 ) {
-    let pixel_format = STATE.with_borrow(|state| state.pixel_format);
-    let frame = Frame::from_raw(data, width, height, pitch, pixel_format);
+    with_context(|context| {
+        let pixel_format = context.state.borrow().pixel_format;
+        let frame = Frame::from_raw(data, width, height, pitch, pixel_format);
 
-    CALLBACKS.with_borrow_mut(|callbacks| callbacks.video_refresh(frame));
+        {
+            let mut state = context.state.borrow_mut();
+
+            if let Some(recording) = &mut state.recording {
+                recording.video_frame(frame.as_ref());
+            }
+
+            if let Some(replay) = &state.replay {
+                replay.push_frame(frame.as_ref());
+            }
+
+            if let Some(frame) = &frame {
+                state.last_frame = Some(frame.clone());
+            }
+        }
+
+        context.callbacks.borrow_mut().video_refresh(frame);
+    })
 }
 
 pub unsafe extern "C" fn audio_sample(left: i16, right: i16) {
-    CALLBACKS.with_borrow_mut(|callbacks| callbacks.audio_sample(left, right))
+    with_context(|context| {
+        if let Some(recording) = &mut context.state.borrow_mut().recording {
+            recording.audio_samples(&[left, right]);
+        }
+
+        context.callbacks.borrow_mut().audio_sample(left, right);
+    })
 }
 
 pub unsafe extern "C" fn audio_sample_batch(samples: *const i16, num_frames: usize) -> usize {
     let num_channels = 2;
     let samples = slice::from_raw_parts(samples, num_channels * num_frames);
 
-    CALLBACKS.with_borrow_mut(|callbacks| callbacks.audio_samples(samples));
+    with_context(|context| {
+        if let Some(recording) = &mut context.state.borrow_mut().recording {
+            recording.audio_samples(samples);
+        }
+
+        context.callbacks.borrow_mut().audio_samples(samples);
+    });
 
     // TODO: allow high level API to control how many frames to consume
     num_frames
 }
 
 pub unsafe extern "C" fn input_poll() {
-    CALLBACKS.with_borrow_mut(|callbacks| callbacks.input_poll());
+    with_callbacks(|callbacks| callbacks.input_poll());
 }
 
 pub unsafe extern "C" fn input_state(
@@ -44,7 +77,7 @@ pub unsafe extern "C" fn input_state(
     index: c_uint,
     id: c_uint,
 ) -> i16 {
-    CALLBACKS.with_borrow_mut(|callbacks| {
+    with_callbacks(|callbacks| {
         if device != DEVICE_JOYPAD || port != 0 {
             return 0;
         }
@@ -76,25 +109,103 @@ pub unsafe extern "C" fn environment(command: u32, data: *mut c_void) -> bool {
                 return false;
             };
 
-            let supported = CALLBACKS
-                .with_borrow_mut(|callbacks| callbacks.supports_pixel_format(pixel_format));
+            let supported =
+                with_callbacks(|callbacks| callbacks.supports_pixel_format(pixel_format));
 
             if supported {
-                STATE.with_borrow_mut(|state| state.pixel_format = pixel_format);
+                with_state(|state| state.pixel_format = pixel_format);
             }
 
             supported
         }
+        Command::SHUTDOWN => {
+            eprintln!("Core requested shutdown");
+            with_state(|state| state.shutdown_requested = true);
+
+            true
+        }
+        Command::GET_SYSTEM_DIRECTORY => {
+            let Some(directory) = data.cast::<*const c_char>().as_mut() else {
+                return false;
+            };
+
+            with_state(|state| match &state.system_directory {
+                Some(system_directory) => {
+                    *directory = system_directory.as_ptr();
+                    true
+                }
+                None => false,
+            })
+        }
         Command::GET_CAN_DUPE => {
             if !data.is_null() {
-                let can_dupe = CALLBACKS.with_borrow_mut(|callbacks| callbacks.can_dupe_frames());
+                let can_dupe = with_callbacks(|callbacks| callbacks.can_dupe_frames());
 
                 *data.cast::<bool>() = can_dupe;
             }
 
             true
         }
-        Command::SET_MEMORY_MAPS => STATE.with_borrow_mut(|state| {
+        Command::SET_MESSAGE => {
+            let Some(message) = data.cast_const().cast::<libretro_sys::Message>().as_ref() else {
+                return false;
+            };
+            let Some(text) = message.msg.as_ref() else {
+                return false;
+            };
+
+            let text = CStr::from_ptr(text).to_string_lossy();
+            let fps = with_state(|state| state.fps);
+            let fps = if fps > 0. { fps } else { 60. };
+            let duration = Duration::from_secs_f64(f64::from(message.frames) / fps);
+            let notification = Notification::new(text).with_duration(duration);
+
+            with_callbacks(|callbacks| callbacks.show_notification(notification));
+
+            true
+        }
+        Command::GET_MESSAGE_INTERFACE_VERSION => {
+            if let Some(version) = data.cast::<c_uint>().as_mut() {
+                *version = 1;
+            }
+
+            true
+        }
+        Command::SET_MESSAGE_EXT => {
+            let Some(message) = data.cast_const().cast::<MessageExt>().as_ref() else {
+                return false;
+            };
+            let Some(text) = message.msg.as_ref() else {
+                return false;
+            };
+
+            let mut text = CStr::from_ptr(text).to_string_lossy().into_owned();
+
+            if message.target == environment::MESSAGE_TARGET_LOG {
+                eprintln!("[core] {text}");
+                return true;
+            }
+
+            if message.ty == environment::MESSAGE_TYPE_PROGRESS && message.progress >= 0 {
+                text = format!("{text} ({}%)", message.progress);
+            }
+
+            let level = match LogLevel::from_uint(message.level as c_uint) {
+                Some(LogLevel::Debug) => osd::Level::Debug,
+                Some(LogLevel::Info) | None => osd::Level::Info,
+                Some(LogLevel::Warn) => osd::Level::Warn,
+                Some(LogLevel::Error) => osd::Level::Error,
+            };
+            let notification = Notification::new(text)
+                .with_duration(Duration::from_millis(u64::from(message.duration)))
+                .with_priority(message.priority)
+                .with_level(level);
+
+            with_callbacks(|callbacks| callbacks.show_notification(notification));
+
+            true
+        }
+        Command::SET_MEMORY_MAPS => with_state(|state| {
             let memory_map = data.cast::<libretro_sys::MemoryMap>();
             let memory_map = MemoryMap::from_raw(memory_map);
 
@@ -158,3 +269,19 @@ pub unsafe extern "C" fn environment(command: u32, data: *mut c_void) -> bool {
         }
     }
 }
+
+/// Runs `f` with the context of the core instance that is calling back.
+fn with_context<R: Default>(f: impl FnOnce(&Context) -> R) -> R {
+    context::with_current(f).unwrap_or_else(|| {
+        eprintln!("WARNING: core called back outside of a call into the core, ignoring");
+        R::default()
+    })
+}
+
+fn with_state<R: Default>(f: impl FnOnce(&mut State) -> R) -> R {
+    with_context(|context| f(&mut context.state.borrow_mut()))
+}
+
+fn with_callbacks<R: Default>(f: impl FnOnce(&mut dyn Callbacks) -> R) -> R {
+    with_context(|context| f(&mut **context.callbacks.borrow_mut()))
+}
@@ -0,0 +1,57 @@
+use std::cell::{Cell, RefCell};
+use std::marker::PhantomData;
+use std::ptr;
+
+use crate::core::{Callbacks, State};
+
+thread_local! {
+    /// The context of the core instance that is currently being called into on this thread.
+    static CURRENT: Cell<*const Context> = const { Cell::new(ptr::null()) };
+}
+
+/// Everything the libretro callbacks of a single core instance operate on.
+///
+/// libretro callbacks carry no user data, so the instance a callback belongs to
+/// is tracked by entering its context around every call into the core.
+pub struct Context {
+    pub state: RefCell<State>,
+    pub callbacks: RefCell<Box<dyn Callbacks>>,
+}
+
+impl Context {
+    pub fn new(callbacks: Box<dyn Callbacks>) -> Self {
+        Self {
+            state: RefCell::new(State::new()),
+            callbacks: RefCell::new(callbacks),
+        }
+    }
+
+    /// Makes this the current context until the returned guard is dropped.
+    pub fn enter(&self) -> EnterGuard<'_> {
+        let previous = CURRENT.replace(self);
+
+        EnterGuard {
+            previous,
+            _context: PhantomData,
+        }
+    }
+}
+
+pub struct EnterGuard<'a> {
+    previous: *const Context,
+    _context: PhantomData<&'a Context>,
+}
+
+impl Drop for EnterGuard<'_> {
+    fn drop(&mut self) {
+        CURRENT.set(self.previous);
+    }
+}
+
+/// Runs `f` with the current context, or returns `None` if no core is being called into.
+pub fn with_current<R>(f: impl FnOnce(&Context) -> R) -> Option<R> {
+    let context = CURRENT.get();
+
+    // Safety: the pointer is only set while an `EnterGuard` borrows the context
+    unsafe { context.as_ref() }.map(f)
+}
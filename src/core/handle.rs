@@ -6,11 +6,18 @@ use crate::core::Core;
 
 type CoreRunFn = Box<dyn FnOnce(&mut Core) + Send>;
 
+/// Serves the closures sent by [`Handle`]s on the thread that owns a [`Core`].
 pub struct Host {
     rx: Receiver<CoreRunFn>,
     tx: SyncSender<CoreRunFn>,
 }
 
+impl Default for Host {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Host {
     pub fn new() -> Self {
         let (tx, rx) = sync_channel(0);
@@ -29,14 +36,27 @@ impl Host {
             run_fn(core);
         }
     }
+
+    /// Runs the functions handles are currently waiting on, without blocking.
+    pub fn run_pending(&self, core: &mut Core) {
+        while let Ok(run_fn) = self.rx.try_recv() {
+            run_fn(core);
+        }
+    }
 }
 
+/// Runs closures on the thread that owns a [`Core`].
+///
+/// Handles are cheap to clone and can be sent to other threads. Every call
+/// blocks until the owning thread serves it through its [`Host`].
 #[derive(Clone)]
 pub struct Handle {
     tx: SyncSender<CoreRunFn>,
 }
 
 impl Handle {
+    /// Runs `f` with the core and returns its result,
+    /// or an error if the core is gone.
     pub fn run<F, R>(&self, f: F) -> Result<R>
     where
         F: FnOnce(&mut Core) -> R + Send + 'static,
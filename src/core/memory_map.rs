@@ -2,38 +2,159 @@ use core::slice;
 use std::ffi::CStr;
 
 use itertools::Itertools;
+use libretro_sys::{MEMDESC_BIGENDIAN, MEMDESC_CONST};
+use strum::IntoStaticStr;
 
+/// Bits 16 and 17 of the descriptor flags encode the access alignment (`MEMDESC_ALIGN_*`).
+const ALIGN_SHIFT: u64 = 16;
+const ALIGN_MASK: u64 = 0b11;
+/// `RETRO_MEMDESC_SYSTEM_RAM`, missing from `libretro_sys`.
+const MEMDESC_SYSTEM_RAM: u64 = 1 << 2;
+
+/// The memory map a core describes with `SET_MEMORY_MAPS`.
+///
+/// Lookups follow the libretro mmap algorithm: descriptors are matched by their
+/// `select` mask, then `disconnect` bits are removed from the address, `len` is
+/// applied (mirroring regions that are not a power of two in size) and `offset`
+/// is added. Earlier descriptors take precedence over later ones.
 #[derive(Debug)]
 pub struct MemoryMap {
-    descriptors: Vec<Descriptor>,
+    source: MemoryMapSource,
+    address_spaces: Vec<AddressSpace>,
+}
+
+/// Where the memory map came from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, IntoStaticStr)]
+#[strum(serialize_all = "snake_case")]
+pub enum MemoryMapSource {
+    /// The core did not describe its memory and exposes no memory regions.
+    None,
+    /// The core described its memory with `SET_MEMORY_MAPS`.
+    Core,
+    /// Synthesized from the regions the core exposes through `retro_get_memory_data`.
+    Regions,
 }
 
 impl MemoryMap {
     pub fn empty() -> Self {
         Self {
-            descriptors: Vec::new(),
+            source: MemoryMapSource::None,
+            address_spaces: Vec::new(),
+        }
+    }
+
+    pub fn source(&self) -> MemoryMapSource {
+        self.source
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.address_spaces.is_empty()
+    }
+
+    /// One past the highest address of the system bus.
+    pub fn end(&self) -> usize {
+        self.system_bus()
+            .map(|address_space| address_space.top_address.saturating_add(1))
+            .unwrap_or(0)
+    }
+
+    /// Names of the address spaces besides the system bus.
+    pub fn address_space_names(&self) -> impl Iterator<Item = &str> {
+        self.address_spaces
+            .iter()
+            .skip(1)
+            .map(|address_space| &*address_space.name)
+    }
+
+    pub fn address_space_end(&self, name: &str) -> usize {
+        self.address_space(name)
+            .map(|address_space| address_space.top_address.saturating_add(1))
+            .unwrap_or(0)
+    }
+
+    /// Reads up to `max_len` bytes from the system bus, spanning descriptors.
+    /// Stops at the first unmapped address.
+    pub(crate) unsafe fn read(&self, addr: usize, max_len: usize) -> Vec<u8> {
+        match self.system_bus() {
+            Some(address_space) => address_space.read(addr, max_len),
+            None => Vec::new(),
+        }
+    }
+
+    /// Writes `data` to the system bus, spanning descriptors, and returns the
+    /// number of bytes written. Stops at the first unmapped or read-only address.
+    pub(crate) unsafe fn write(&self, addr: usize, data: &[u8]) -> usize {
+        match self.system_bus() {
+            Some(address_space) => address_space.write(addr, data),
+            None => 0,
         }
     }
 
-    pub(crate) unsafe fn get_slice(&self, addr: usize, max_len: usize) -> Option<&[u8]> {
-        let descriptor = self.find_descriptor(addr)?;
+    pub(crate) unsafe fn read_in(&self, name: &str, addr: usize, max_len: usize) -> Vec<u8> {
+        match self.address_space(name) {
+            Some(address_space) => address_space.read(addr, max_len),
+            None => Vec::new(),
+        }
+    }
+
+    pub(crate) unsafe fn write_in(&self, name: &str, addr: usize, data: &[u8]) -> usize {
+        match self.address_space(name) {
+            Some(address_space) => address_space.write(addr, data),
+            None => 0,
+        }
+    }
+
+    /// Writes `data` at `offset` into the descriptors flagged as system RAM,
+    /// laid out one after another, and returns the number of bytes written.
+    pub(crate) unsafe fn write_system_ram(&self, offset: usize, data: &[u8]) -> usize {
+        let mut buffers = self
+            .address_spaces
+            .iter()
+            .flat_map(|address_space| &address_space.descriptors)
+            .filter(|descriptor| descriptor.is_system_ram() && !descriptor.ptr.is_null())
+            .map(|descriptor| (descriptor.ptr.add(descriptor.offset), descriptor.len));
+        let mut offset = offset;
+        let mut num_written = 0;
+
+        for (ptr, len) in &mut buffers {
+            if offset < len {
+                let count = (len - offset).min(data.len() - num_written);
+
+                ptr.add(offset)
+                    .copy_from_nonoverlapping(data[num_written..].as_ptr(), count);
+                num_written += count;
+                offset = 0;
+
+                if num_written == data.len() {
+                    break;
+                }
+            } else {
+                offset -= len;
+            }
+        }
 
-        descriptor.get_slice(addr, max_len)
+        num_written
     }
 
-    pub(crate) unsafe fn get_slice_mut(&self, addr: usize, max_len: usize) -> Option<&mut [u8]> {
-        let descriptor = self.find_descriptor(addr)?;
+    pub fn is_read_only(&self, addr: usize) -> bool {
+        self.system_bus()
+            .and_then(|address_space| address_space.lookup(addr))
+            .is_some_and(|(descriptor, _)| descriptor.is_const())
+    }
 
-        descriptor.get_slice_mut(addr, max_len)
+    /// The unnamed address space, or if the core named all of its
+    /// descriptors, the address space of the first descriptor.
+    fn system_bus(&self) -> Option<&AddressSpace> {
+        self.address_spaces.first()
     }
 
-    fn find_descriptor(&self, addr: usize) -> Option<&Descriptor> {
-        self.descriptors
+    fn address_space(&self, name: &str) -> Option<&AddressSpace> {
+        self.address_spaces
             .iter()
-            .find(|descriptor| descriptor.contains_address(addr))
+            .find(|address_space| address_space.name == name)
     }
 
-    pub unsafe fn from_raw(map: *const libretro_sys::MemoryMap) -> Self {
+    pub(crate) unsafe fn from_raw(map: *const libretro_sys::MemoryMap) -> Self {
         if map.is_null() {
             return MemoryMap::empty();
         }
@@ -44,12 +165,227 @@ impl MemoryMap {
                 .map(|descriptor| Descriptor::from_raw_ref(descriptor))
                 .collect_vec();
 
-        Self { descriptors }
+        Self::from_descriptors(MemoryMapSource::Core, descriptors)
+    }
+
+    /// Builds a memory map from the `MEMORY_*` regions of a core that doesn't provide one.
+    pub(crate) fn from_regions(descriptors: Vec<Descriptor>) -> Self {
+        if descriptors.is_empty() {
+            return MemoryMap::empty();
+        }
+
+        Self::from_descriptors(MemoryMapSource::Regions, descriptors)
+    }
+
+    fn from_descriptors(source: MemoryMapSource, descriptors: Vec<Descriptor>) -> Self {
+        let mut names = descriptors
+            .iter()
+            .map(|descriptor| descriptor.address_space.clone())
+            .unique()
+            .collect_vec();
+
+        if let Some(position) = names.iter().position(|name| name.is_empty()) {
+            let unnamed = names.remove(position);
+            names.insert(0, unnamed);
+        }
+
+        let address_spaces = names
+            .into_iter()
+            .map(|name| {
+                let descriptors = descriptors
+                    .iter()
+                    .filter(|descriptor| descriptor.address_space == name)
+                    .cloned()
+                    .collect_vec();
+
+                AddressSpace::new(name, descriptors)
+            })
+            .collect_vec();
+
+        Self {
+            source,
+            address_spaces,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct AddressSpace {
+    name: String,
+    descriptors: Vec<Descriptor>,
+    top_address: usize,
+    /// Sorted, disjoint address ranges, each owned by the descriptor that
+    /// takes precedence within it.
+    intervals: Vec<Interval>,
+    /// Descriptors that map a scattered set of addresses and therefore can't
+    /// be part of `intervals`, in order of precedence.
+    scattered: Vec<usize>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Interval {
+    first: usize,
+    last: usize,
+    descriptor: usize,
+}
+
+impl AddressSpace {
+    fn new(name: String, mut descriptors: Vec<Descriptor>) -> Self {
+        let top_address = descriptors
+            .iter()
+            .map(|descriptor| {
+                if descriptor.select != 0 {
+                    descriptor.select
+                } else {
+                    (descriptor.start + descriptor.len).saturating_sub(1)
+                }
+            })
+            .fold(1, |top_address, address| top_address | address);
+        let top_address = add_bits_down(top_address);
+
+        descriptors.retain_mut(|descriptor| match descriptor.preprocess(top_address) {
+            Ok(()) => true,
+            Err(err) => {
+                eprintln!("Ignoring invalid memory descriptor ({err}): {descriptor:?}");
+                false
+            }
+        });
+
+        let mut this = Self {
+            name,
+            descriptors,
+            top_address,
+            intervals: Vec::new(),
+            scattered: Vec::new(),
+        };
+
+        this.build_index();
+        this
+    }
+
+    fn build_index(&mut self) {
+        let mut ranges = Vec::new();
+
+        for (index, descriptor) in self.descriptors.iter().enumerate() {
+            if descriptor.ptr.is_null() {
+                continue;
+            }
+
+            let free_bits = self.top_address & !descriptor.select;
+            let is_contiguous = free_bits & free_bits.wrapping_add(1) == 0;
+
+            if !is_contiguous {
+                self.scattered.push(index);
+                continue;
+            }
+
+            let last = if descriptor.is_mirrored {
+                descriptor.start + free_bits
+            } else {
+                descriptor.start + descriptor.len - 1
+            };
+
+            ranges.push(Interval {
+                first: descriptor.start,
+                last,
+                descriptor: index,
+            });
+        }
+
+        let boundaries = ranges
+            .iter()
+            .flat_map(|range| [range.first, range.last.saturating_add(1)])
+            .sorted()
+            .dedup()
+            .collect_vec();
+
+        for (&first, &next) in boundaries.iter().tuple_windows() {
+            let last = next - 1;
+            let owner = ranges
+                .iter()
+                .filter(|range| range.first <= first && last <= range.last)
+                .map(|range| range.descriptor)
+                .min();
+
+            let Some(owner) = owner else {
+                continue;
+            };
+
+            match self.intervals.last_mut() {
+                Some(previous) if previous.descriptor == owner && previous.last + 1 == first => {
+                    previous.last = last;
+                }
+                _ => self.intervals.push(Interval {
+                    first,
+                    last,
+                    descriptor: owner,
+                }),
+            }
+        }
+    }
+
+    /// Finds the descriptor responsible for `addr` and the offset into its buffer.
+    fn lookup(&self, addr: usize) -> Option<(&Descriptor, usize)> {
+        if addr > self.top_address {
+            return None;
+        }
+
+        let position = self
+            .intervals
+            .partition_point(|interval| interval.last < addr);
+        let interval_owner = self
+            .intervals
+            .get(position)
+            .filter(|interval| interval.first <= addr)
+            .map(|interval| interval.descriptor);
+
+        let scattered_owner = self
+            .scattered
+            .iter()
+            .copied()
+            .take_while(|&index| interval_owner.is_none_or(|owner| index < owner))
+            .find(|&index| self.descriptors[index].translate(addr).is_some());
+
+        let descriptor = &self.descriptors[scattered_owner.or(interval_owner)?];
+        let offset = descriptor.translate(addr)?;
+
+        Some((descriptor, offset))
+    }
+
+    unsafe fn read(&self, addr: usize, max_len: usize) -> Vec<u8> {
+        let mut data = Vec::with_capacity(max_len.min(0x1_0000));
+
+        for addr in (addr..).take(max_len) {
+            let Some((descriptor, offset)) = self.lookup(addr) else {
+                break;
+            };
+
+            data.push(descriptor.ptr.add(offset).read());
+        }
+
+        data
+    }
+
+    unsafe fn write(&self, addr: usize, data: &[u8]) -> usize {
+        for (addr, (num_written, &byte)) in (addr..).zip(data.iter().enumerate()) {
+            let Some((descriptor, offset)) = self.lookup(addr) else {
+                return num_written;
+            };
+
+            if descriptor.is_const() {
+                return num_written;
+            }
+
+            descriptor.ptr.add(offset).write(byte);
+        }
+
+        data.len()
     }
 }
 
-#[derive(custom_debug::Debug)]
+#[derive(Clone, custom_debug::Debug)]
 pub struct Descriptor {
+    #[debug(format = "0x{:X}")]
     flags: u64,
     ptr: *mut u8,
     #[debug(format = "0x{:X}")]
@@ -60,56 +396,128 @@ pub struct Descriptor {
     select: usize,
     #[debug(format = "0x{:X}")]
     disconnect: usize,
+    #[debug(format = "0x{:X}")]
     len: usize,
     address_space: String,
+    /// Bits of the address (relative to `start`) that can address the buffer.
+    #[debug(format = "0x{:X}")]
+    disconnect_mask: usize,
+    /// Whether addresses beyond `len` within `select` mirror the buffer, as
+    /// for all descriptors of the core. Descriptors synthesized from memory
+    /// regions are plain address ranges that don't, so they can't shadow the
+    /// regions placed right after them.
+    is_mirrored: bool,
 }
 
 impl Descriptor {
-    pub fn start(&self) -> usize {
-        self.start
+    /// A plain, writable range of `len` bytes at `start` of `address_space`.
+    pub(crate) fn new(ptr: *mut u8, start: usize, len: usize, address_space: &str) -> Self {
+        Self {
+            flags: 0,
+            ptr,
+            offset: 0,
+            start,
+            select: 0,
+            disconnect: 0,
+            len,
+            address_space: address_space.to_owned(),
+            disconnect_mask: 0,
+            is_mirrored: false,
+        }
     }
 
-    pub fn end(&self) -> usize {
-        self.start + self.len
+    pub fn is_const(&self) -> bool {
+        self.flags & u64::from(MEMDESC_CONST) != 0
     }
 
-    pub fn contains_address(&self, addr: usize) -> bool {
-        // if self.select != 0 {
-        //     // TODO: implement select != 0 case
-        //     return false;
-        // }
+    pub fn is_system_ram(&self) -> bool {
+        self.flags & MEMDESC_SYSTEM_RAM != 0
+    }
 
-        self.start <= addr && addr < self.end()
+    pub fn is_big_endian(&self) -> bool {
+        self.flags & u64::from(MEMDESC_BIGENDIAN) != 0
     }
 
-    unsafe fn get_raw_slice(&self, addr: usize, max_len: usize) -> Option<(*mut u8, usize)> {
-        if addr < self.start || addr >= self.end() {
-            return None;
+    /// Size in bytes that all accesses to this descriptor are aligned to.
+    pub fn alignment(&self) -> usize {
+        1 << ((self.flags >> ALIGN_SHIFT) & ALIGN_MASK)
+    }
+
+    /// Normalizes `select`, `len` and `disconnect` as the libretro spec
+    /// describes, relative to the highest address of the address space.
+    ///
+    /// Without a `select` mask, it is derived from `len` rounded up to a power
+    /// of two, like RetroArch does, and the rest of that window mirrors the
+    /// buffer.
+    fn preprocess(&mut self, top_address: usize) -> Result<(), &'static str> {
+        if self.select == 0 {
+            if self.len == 0 {
+                return Err("neither select nor len given");
+            }
+
+            let len_mask = add_bits_down(self.len - 1);
+            self.select = top_address & !inflate(len_mask, self.disconnect);
+        }
+
+        if self.len == 0 {
+            let free_bits = reduce(top_address & !self.select, self.disconnect);
+            self.len = add_bits_down(free_bits) + 1;
         }
 
-        let offset = addr - self.start;
-        let len = (self.len - offset).min(max_len);
-        let ptr = self.ptr.byte_add(self.offset + offset);
+        if self.start & !self.select != 0 {
+            return Err("start has bits set outside of select");
+        }
 
-        Some((ptr, len))
-    }
+        while reduce(top_address & !self.select, self.disconnect) >> 1 > self.len - 1 {
+            let bit = highest_bit(top_address & !self.select & !self.disconnect);
+
+            if bit == 0 {
+                break;
+            }
+
+            self.disconnect |= bit;
+        }
 
-    unsafe fn get_slice(&self, addr: usize, max_len: usize) -> Option<&[u8]> {
-        unsafe {
-            let (ptr, len) = self.get_raw_slice(addr, max_len)?;
-            let slice = slice::from_raw_parts(ptr, len);
+        self.disconnect_mask = add_bits_down(self.len - 1);
+        self.disconnect &= self.disconnect_mask;
 
-            Some(slice)
+        while (!self.disconnect_mask) >> 1 & self.disconnect != 0 {
+            self.disconnect_mask >>= 1;
+            self.disconnect &= self.disconnect_mask;
         }
+
+        Ok(())
     }
 
-    unsafe fn get_slice_mut(&self, addr: usize, max_len: usize) -> Option<&mut [u8]> {
-        unsafe {
-            let (ptr, len) = self.get_raw_slice(addr, max_len)?;
-            let slice = slice::from_raw_parts_mut(ptr, len);
+    /// Translates an address to an offset into `ptr`:
+    /// subtract `start`, pick off `disconnect`, apply `len`, add `offset`.
+    fn translate(&self, addr: usize) -> Option<usize> {
+        if (self.start ^ addr) & self.select != 0 {
+            return None;
+        }
+
+        let offset = (addr & !self.select) & self.disconnect_mask;
+        let mut offset = reduce(offset, self.disconnect);
+
+        if offset >= self.len {
+            if !self.is_mirrored {
+                return None;
+            }
 
-            Some(slice)
+            while offset >= self.len {
+                offset -= highest_bit(offset);
+            }
         }
+
+        // Big endian data stored in little endian host words has the bytes
+        // of each word reversed.
+        let alignment = self.alignment();
+
+        if self.is_big_endian() && alignment > 1 && cfg!(target_endian = "little") {
+            offset ^= alignment - 1;
+        }
+
+        Some(self.offset + offset)
     }
 
     unsafe fn from_raw_ref(descriptor: &libretro_sys::MemoryDescriptor) -> Self {
@@ -128,10 +536,203 @@ impl Descriptor {
             disconnect: descriptor.disconnect,
             len: descriptor.len,
             address_space,
+            disconnect_mask: 0,
+            is_mirrored: true,
         }
     }
 }
 
-fn highest_address_from_mask(mask: usize) -> usize {
-    usize::MAX.checked_shr(mask.leading_zeros()).unwrap_or(0)
+/// Sets all bits below the highest set bit.
+fn add_bits_down(mut n: usize) -> usize {
+    let mut shift = 1;
+
+    while shift < usize::BITS {
+        n |= n >> shift;
+        shift <<= 1;
+    }
+
+    n
+}
+
+fn highest_bit(n: usize) -> usize {
+    let n = add_bits_down(n);
+
+    n ^ (n >> 1)
+}
+
+/// Inserts a zero bit into `addr` at every bit position set in `mask`.
+fn inflate(mut addr: usize, mut mask: usize) -> usize {
+    while mask != 0 {
+        let tmp = (mask - 1) & !mask;
+
+        addr = ((addr & !tmp) << 1) | (addr & tmp);
+        mask &= mask - 1;
+    }
+
+    addr
+}
+
+/// Removes the bits set in `mask` from `addr`, shifting higher bits down.
+fn reduce(mut addr: usize, mut mask: usize) -> usize {
+    while mask != 0 {
+        let tmp = (mask - 1) & !mask;
+
+        addr = (addr & tmp) | ((addr >> 1) & !tmp);
+        mask = (mask & (mask - 1)) >> 1;
+    }
+
+    addr
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ptr;
+
+    use super::*;
+
+    /// A descriptor of the core, as `SET_MEMORY_MAPS` would pass it.
+    fn core_descriptor(buffer: &mut [u8], start: usize, select: usize, len: usize) -> Descriptor {
+        let raw = libretro_sys::MemoryDescriptor {
+            flags: 0,
+            ptr: buffer.as_mut_ptr().cast(),
+            offset: 0,
+            start,
+            select,
+            disconnect: 0,
+            len,
+            addrspace: ptr::null(),
+        };
+
+        unsafe { Descriptor::from_raw_ref(&raw) }
+    }
+
+    fn numbered(len: usize) -> Vec<u8> {
+        (0..len).map(|index| index as u8).collect()
+    }
+
+    #[test]
+    fn translates_with_derived_select() {
+        let mut wram = numbered(0x2000);
+        let map = MemoryMap::from_descriptors(
+            MemoryMapSource::Core,
+            vec![core_descriptor(&mut wram, 0xC000, 0, 0x2000)],
+        );
+
+        assert_eq!(unsafe { map.read(0xC001, 2) }, [1, 2]);
+        assert_eq!(unsafe { map.read(0xDFFF, 1) }, [0xFF]);
+        assert!(unsafe { map.read(0xE000, 1) }.is_empty());
+        assert!(unsafe { map.read(0xBFFF, 1) }.is_empty());
+    }
+
+    #[test]
+    fn mirrors_core_descriptors_that_are_not_a_power_of_two() {
+        let mut ram = numbered(0x6000);
+        let map = MemoryMap::from_descriptors(
+            MemoryMapSource::Core,
+            vec![core_descriptor(&mut ram, 0, 0, 0x6000)],
+        );
+
+        // 0x6000 is beyond `len`, the highest bit is removed: 0x6000 - 0x4000
+        assert_eq!(
+            map.system_bus()
+                .unwrap()
+                .lookup(0x6000)
+                .map(|(_, offset)| offset),
+            Some(0x2000)
+        );
+        assert_eq!(
+            map.system_bus()
+                .unwrap()
+                .lookup(0x7FFF)
+                .map(|(_, offset)| offset),
+            Some(0x3FFF)
+        );
+    }
+
+    #[test]
+    fn mirrors_within_select() {
+        let mut wram = numbered(0x2000);
+        // Game Boy WRAM with its echo at 0xE000
+        let map = MemoryMap::from_descriptors(
+            MemoryMapSource::Core,
+            vec![core_descriptor(&mut wram, 0xC000, 0xC000, 0x2000)],
+        );
+
+        assert_eq!(unsafe { map.read(0xE001, 1) }, [1]);
+        assert_eq!(unsafe { map.read(0xC001, 1) }, [1]);
+    }
+
+    #[test]
+    fn region_descriptors_do_not_mirror() {
+        let mut ram = vec![1; 0x6000];
+        let mut save_ram = vec![2; 0x2000];
+        let map = MemoryMap::from_regions(vec![
+            Descriptor::new(ram.as_mut_ptr(), 0, ram.len(), ""),
+            Descriptor::new(save_ram.as_mut_ptr(), 0x6000, save_ram.len(), ""),
+        ]);
+
+        assert_eq!(unsafe { map.read(0x5FFF, 2) }, [1, 2]);
+    }
+
+    #[test]
+    fn earlier_descriptors_take_precedence() {
+        let mut first = vec![1; 0x100];
+        let mut second = vec![2; 0x1000];
+        let map = MemoryMap::from_descriptors(
+            MemoryMapSource::Core,
+            vec![
+                core_descriptor(&mut first, 0x1000, 0, 0x100),
+                core_descriptor(&mut second, 0x1000, 0, 0x1000),
+            ],
+        );
+
+        assert_eq!(unsafe { map.read(0x10FF, 2) }, [1, 2]);
+    }
+
+    #[test]
+    fn const_descriptors_are_read_only() {
+        let mut rom = vec![0; 0x100];
+        let mut descriptor = core_descriptor(&mut rom, 0, 0, 0x100);
+
+        descriptor.flags = u64::from(MEMDESC_CONST);
+
+        let map = MemoryMap::from_descriptors(MemoryMapSource::Core, vec![descriptor]);
+
+        assert!(map.is_read_only(0x10));
+        assert_eq!(unsafe { map.write(0x10, &[1]) }, 0);
+        assert_eq!(rom[0x10], 0);
+    }
+
+    #[test]
+    fn writes_system_ram_across_descriptors() {
+        let mut wram0 = vec![0; 0x10];
+        let mut rom = vec![0; 0x10];
+        let mut wram1 = vec![0; 0x10];
+        let mut descriptors = vec![
+            core_descriptor(&mut wram0, 0xC000, 0, 0x10),
+            core_descriptor(&mut rom, 0, 0, 0x10),
+            core_descriptor(&mut wram1, 0xD000, 0, 0x10),
+        ];
+
+        descriptors[0].flags = MEMDESC_SYSTEM_RAM;
+        descriptors[2].flags = MEMDESC_SYSTEM_RAM;
+
+        let map = MemoryMap::from_descriptors(MemoryMapSource::Core, descriptors);
+
+        assert_eq!(unsafe { map.write_system_ram(0xF, &[1, 2, 3]) }, 3);
+        assert_eq!(unsafe { map.write_system_ram(0x1F, &[4, 5]) }, 1);
+        assert_eq!(unsafe { map.write_system_ram(0x20, &[6]) }, 0);
+        assert_eq!(wram0[0xF], 1);
+        assert_eq!(wram1[..2], [2, 3]);
+        assert_eq!(wram1[0xF], 4);
+        assert!(rom.iter().all(|&byte| byte == 0));
+    }
+
+    #[test]
+    fn inflate_and_reduce_are_inverse() {
+        assert_eq!(reduce(0b1011, 0b0100), 0b111);
+        assert_eq!(inflate(0b111, 0b0100), 0b1011);
+        assert_eq!(add_bits_down(0x4000), 0x7FFF);
+        assert_eq!(highest_bit(0x5FFF), 0x4000);
+    }
 }
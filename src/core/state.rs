@@ -1,29 +1,46 @@
-use std::cell::RefCell;
+use std::ffi::CString;
+use std::path::PathBuf;
 
 use libretro_sys::PixelFormat;
 
 use crate::core::MemoryMap;
-
-thread_local! {
-    pub static STATE: RefCell<State> = RefCell::new(State::new());
-}
+use crate::recording::Recording;
+use crate::replay::Replay;
+use crate::video::Frame;
 
 pub struct State {
-    pub is_core_loaded: bool,
     pub pixel_format: PixelFormat,
     pub memory_map: MemoryMap,
     pub rom: Vec<u8>,
+    pub rom_path: Option<PathBuf>,
     pub sha1_romhash: String,
+    pub crc32_romhash: u32,
+    pub fps: f64,
+    /// The most recent frame the core rendered, kept for screenshots.
+    pub last_frame: Option<Frame>,
+    pub recording: Option<Recording>,
+    pub replay: Option<Replay>,
+    /// Set once the core requested a shutdown with `SHUTDOWN`.
+    pub shutdown_requested: bool,
+    /// Returned by `GET_SYSTEM_DIRECTORY`, kept alive for the core.
+    pub system_directory: Option<CString>,
 }
 
 impl State {
     pub fn new() -> Self {
         Self {
-            is_core_loaded: false,
             pixel_format: PixelFormat::ARGB1555,
             memory_map: MemoryMap::empty(),
             rom: Vec::new(),
+            rom_path: None,
             sha1_romhash: String::new(),
+            crc32_romhash: 0,
+            fps: 0.,
+            last_frame: None,
+            recording: None,
+            replay: None,
+            shutdown_requested: false,
+            system_directory: None,
         }
     }
 }
@@ -0,0 +1,415 @@
+//! The libretro core info database.
+//!
+//! Core info files (`<core>_libretro.info`) describe which content a core
+//! runs, the system it emulates and the firmware it needs. A snapshot for
+//! common cores is bundled. A local info directory, e.g. RetroArch's, can add
+//! cores and overrides bundled ones of the same name.
+
+use std::fs;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use anyhow::{Context, Result};
+
+use crate::firmware::{self, Firmware};
+
+static DATABASE: OnceLock<CoreDatabase> = OnceLock::new();
+
+const INFO_EXTENSION: &str = "info";
+const LIBRARY_SUFFIX: &str = "_libretro";
+
+macro_rules! bundled {
+    ($name:literal) => {
+        (
+            $name,
+            include_str!(concat!("core_info/bundled/", $name, "_libretro.info")),
+        )
+    };
+}
+
+/// The bundled snapshot, in order of preference when several cores run the same content.
+const BUNDLED: &[(&str, &str)] = &[
+    bundled!("gambatte"),
+    bundled!("mgba"),
+    bundled!("sameboy"),
+    bundled!("vbam"),
+    bundled!("mesen"),
+    bundled!("nestopia"),
+    bundled!("fceumm"),
+    bundled!("snes9x"),
+    bundled!("bsnes"),
+    bundled!("mesen-s"),
+    bundled!("mupen64plus_next"),
+    bundled!("parallel_n64"),
+    bundled!("genesis_plus_gx"),
+    bundled!("picodrive"),
+    bundled!("gearsystem"),
+    bundled!("smsplus"),
+    bundled!("swanstation"),
+    bundled!("mednafen_psx"),
+    bundled!("pcsx_rearmed"),
+    bundled!("melonds"),
+    bundled!("desmume"),
+    bundled!("mednafen_pce_fast"),
+    bundled!("mednafen_saturn"),
+    bundled!("yabause"),
+    bundled!("mednafen_wswan"),
+    bundled!("mednafen_ngp"),
+    bundled!("mednafen_vb"),
+    bundled!("handy"),
+    bundled!("mednafen_lynx"),
+    bundled!("stella"),
+    bundled!("prosystem"),
+    bundled!("flycast"),
+    bundled!("ppsspp"),
+];
+
+/// Loads the core database, adding the info files in `info_dir` to the bundled ones.
+///
+/// Only has an effect before the database is first used, which loads the
+/// bundled snapshot alone.
+pub fn init(info_dir: Option<&Path>) -> Result<()> {
+    let database = match info_dir {
+        Some(info_dir) => CoreDatabase::load(info_dir)?,
+        None => CoreDatabase::bundled(),
+    };
+
+    DATABASE.set(database).ok();
+
+    Ok(())
+}
+
+pub fn database() -> &'static CoreDatabase {
+    DATABASE.get_or_init(CoreDatabase::bundled)
+}
+
+pub struct CoreDatabase {
+    cores: Vec<CoreInfo>,
+}
+
+impl CoreDatabase {
+    pub fn bundled() -> Self {
+        let cores = BUNDLED
+            .iter()
+            .map(|(name, info)| CoreInfo::parse(name, info))
+            .collect();
+
+        Self { cores }
+    }
+
+    /// The bundled snapshot together with all info files in `info_dir`.
+    pub fn load(info_dir: &Path) -> Result<Self> {
+        let mut database = Self::bundled();
+        let entries =
+            fs::read_dir(info_dir).with_context(|| format!("failed to read {info_dir:?}"))?;
+
+        for entry in entries {
+            let path = entry?.path();
+
+            if path.extension().and_then(|extension| extension.to_str()) != Some(INFO_EXTENSION) {
+                continue;
+            }
+
+            let Some(name) = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .map(|stem| stem.trim_end_matches(LIBRARY_SUFFIX))
+            else {
+                continue;
+            };
+            let info = fs::read_to_string(&path)
+                .with_context(|| format!("failed to read core info {path:?}"))?;
+
+            database.insert(CoreInfo::parse(name, &info));
+        }
+
+        Ok(database)
+    }
+
+    fn insert(&mut self, core: CoreInfo) {
+        match self.cores.iter_mut().find(|known| known.name == core.name) {
+            Some(known) => *known = core,
+            None => self.cores.push(core),
+        }
+    }
+
+    pub fn cores(&self) -> &[CoreInfo] {
+        &self.cores
+    }
+
+    /// The core whose library file is named after `name`, e.g. `gambatte`.
+    pub fn get(&self, name: &str) -> Option<&CoreInfo> {
+        self.cores.iter().find(|core| core.name == name)
+    }
+
+    /// The core reporting `library_name` in `retro_get_system_info`.
+    pub fn by_library_name(&self, library_name: &str) -> Option<&CoreInfo> {
+        self.cores
+            .iter()
+            .find(|core| core.library_name == library_name)
+    }
+
+    /// The core of the library at `path`, e.g. `cores/gambatte_libretro.so`.
+    pub fn for_library_path(&self, path: &Path) -> Option<&CoreInfo> {
+        let name = path.file_stem()?.to_str()?.trim_end_matches(LIBRARY_SUFFIX);
+
+        self.get(name)
+    }
+
+    /// The cores that run content of `database`, e.g. `Nintendo - Game Boy`, most preferred first.
+    pub fn for_database<'a, 'd>(
+        &'a self,
+        database: &'d str,
+    ) -> impl Iterator<Item = &'a CoreInfo> + 'd
+    where
+        'a: 'd,
+    {
+        self.cores
+            .iter()
+            .filter(move |core| core.databases.iter().any(|known| known == database))
+    }
+
+    /// The cores that can run content with `extension`, most preferred first.
+    pub fn for_extension<'a, 'e>(
+        &'a self,
+        extension: &'e str,
+    ) -> impl Iterator<Item = &'a CoreInfo> + 'e
+    where
+        'a: 'e,
+    {
+        self.cores
+            .iter()
+            .filter(move |core| core.supports_extension(extension))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct CoreInfo {
+    /// Name of the core as used for its library file, e.g. `gambatte`.
+    pub name: String,
+    /// Name shown to users, e.g. `Nintendo - Game Boy / Color (Gambatte)`.
+    pub display_name: String,
+    /// Name the core reports in `retro_get_system_info`, e.g. `Gambatte`.
+    pub library_name: String,
+    pub system_name: Option<String>,
+    /// System id as used by RetroArch, e.g. `game_boy`.
+    pub system_id: Option<String>,
+    /// Lowercase extensions of the content the core runs.
+    pub supported_extensions: Vec<String>,
+    /// Names of the No-Intro and Redump DATs of the content the core runs,
+    /// e.g. `Nintendo - Game Boy`.
+    pub databases: Vec<String>,
+    pub firmware: Vec<Firmware>,
+}
+
+impl CoreInfo {
+    /// Parses the `key = "value"` lines of an info file.
+    pub fn parse(name: &str, info: &str) -> Self {
+        let values = info
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.starts_with('#'))
+            .filter_map(|line| line.split_once('='))
+            .map(|(key, value)| (key.trim(), value.trim().trim_matches('"')))
+            .collect::<Vec<_>>();
+        let value = |key: &str| {
+            values
+                .iter()
+                .find(|(known, _)| *known == key)
+                .map(|(_, value)| *value)
+                .filter(|value| !value.is_empty())
+        };
+
+        let md5s = value("notes")
+            .into_iter()
+            .flat_map(|notes| notes.split('|'))
+            .filter_map(parse_md5_note)
+            .collect::<Vec<_>>();
+        let firmware_count = value("firmware_count")
+            .and_then(|count| count.parse().ok())
+            .unwrap_or(0);
+        let firmware = (0..firmware_count)
+            .filter_map(|index| {
+                let path = value(&format!("firmware{index}_path"))?;
+                let md5 = md5s
+                    .iter()
+                    .find(|(file, _)| *file == path)
+                    .map(|(_, md5)| md5.to_ascii_lowercase());
+
+                Some(Firmware {
+                    path: path.to_owned(),
+                    description: value(&format!("firmware{index}_desc"))
+                        .unwrap_or(path)
+                        .to_owned(),
+                    optional: value(&format!("firmware{index}_opt")) == Some("true"),
+                    sha1: md5.as_deref().and_then(firmware::known_sha1),
+                    md5,
+                })
+            })
+            .collect();
+
+        Self {
+            name: name.to_owned(),
+            display_name: value("display_name").unwrap_or(name).to_owned(),
+            library_name: value("corename").unwrap_or(name).to_owned(),
+            system_name: value("systemname").map(str::to_owned),
+            system_id: value("systemid").map(str::to_owned),
+            supported_extensions: value("supported_extensions")
+                .unwrap_or_default()
+                .split('|')
+                .filter(|extension| !extension.is_empty())
+                .map(str::to_ascii_lowercase)
+                .collect(),
+            databases: value("database")
+                .unwrap_or_default()
+                .split('|')
+                .filter(|database| !database.is_empty())
+                .map(str::to_owned)
+                .collect(),
+            firmware,
+        }
+    }
+
+    pub fn supports_extension(&self, extension: &str) -> bool {
+        self.supported_extensions
+            .iter()
+            .any(|supported| supported.eq_ignore_ascii_case(extension))
+    }
+}
+
+/// Parses a note like `(!) gba_bios.bin (md5): a860e8c0b6d573d191e4ec7db1b1e4f6`.
+fn parse_md5_note(note: &str) -> Option<(&str, &str)> {
+    let note = note.trim().strip_prefix("(!)")?;
+    let (file, md5) = note.split_once("(md5):")?;
+
+    Some((file.trim(), md5.trim()))
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn parses_bundled_info_files() {
+        let core = CoreInfo::parse("mgba", BUNDLED[1].1);
+
+        assert_eq!(core.display_name, "Nintendo - Game Boy Advance (mGBA)");
+        assert_eq!(core.library_name, "mGBA");
+        assert_eq!(core.system_name.as_deref(), Some("Game Boy Advance"));
+        assert_eq!(core.system_id.as_deref(), Some("game_boy_advance"));
+        assert_eq!(core.supported_extensions, ["gb", "gbc", "gba"]);
+        assert_eq!(
+            core.databases,
+            [
+                "Nintendo - Game Boy Advance",
+                "Nintendo - Game Boy",
+                "Nintendo - Game Boy Color"
+            ]
+        );
+
+        let bios = &core.firmware[0];
+
+        assert_eq!(core.firmware.len(), 4);
+        assert_eq!(bios.path, "gba_bios.bin");
+        assert_eq!(bios.description, "gba_bios.bin (Game Boy Advance BIOS)");
+        assert!(bios.optional);
+        assert_eq!(
+            bios.md5.as_deref(),
+            Some("a860e8c0b6d573d191e4ec7db1b1e4f6")
+        );
+        assert_eq!(bios.sha1, Some("300c20df6731a33952ded8c436f7f186d25d3492"));
+        assert_eq!(core.firmware[3].sha1, None);
+    }
+
+    #[test]
+    fn parses_sparse_info_files() {
+        let info = r#"
+# display_name = "Commented"
+supported_extensions = "ROM|Bin"
+database = ""
+firmware_count = 3
+firmware0_path = "bios.rom"
+firmware0_opt = "false"
+firmware1_desc = "no path"
+firmware2_path = "extra.rom"
+firmware2_opt = "true"
+notes = "(!) bios.rom (md5): 0123456789ABCDEF0123456789ABCDEF|not a hash"
+"#;
+        let core = CoreInfo::parse("test", info);
+
+        assert_eq!(core.display_name, "test");
+        assert_eq!(core.library_name, "test");
+        assert_eq!(core.system_name, None);
+        assert_eq!(core.supported_extensions, ["rom", "bin"]);
+        assert!(core.supports_extension("BIN"));
+        assert!(core.databases.is_empty());
+        assert_eq!(core.firmware.len(), 2);
+        assert_eq!(core.firmware[0].description, "bios.rom");
+        assert!(!core.firmware[0].optional);
+        assert_eq!(
+            core.firmware[0].md5.as_deref(),
+            Some("0123456789abcdef0123456789abcdef")
+        );
+        assert!(core.firmware[1].optional);
+        assert_eq!(core.firmware[1].md5, None);
+    }
+
+    #[test]
+    fn info_directories_add_and_override_cores() {
+        let temp = TempDir::new().unwrap();
+        let dir = temp.path();
+
+        fs::write(
+            dir.join("gambatte_libretro.info"),
+            "corename = \"Gambatte Override\"\nsupported_extensions = \"gb\"",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("new_libretro.info"),
+            "supported_extensions = \"new\"",
+        )
+        .unwrap();
+        fs::write(dir.join("readme.txt"), "supported_extensions = \"txt\"").unwrap();
+
+        let database = CoreDatabase::load(dir).unwrap();
+
+        assert_eq!(database.cores().len(), BUNDLED.len() + 1);
+        assert_eq!(
+            database.get("gambatte").unwrap().library_name,
+            "Gambatte Override"
+        );
+        assert!(database
+            .by_library_name("Gambatte Override")
+            .is_some_and(|core| core.name == "gambatte"));
+        assert_eq!(
+            database
+                .for_extension("new")
+                .map(|core| core.name.as_str())
+                .collect::<Vec<_>>(),
+            ["new"]
+        );
+        assert_eq!(database.for_extension("txt").count(), 0);
+    }
+
+    #[test]
+    fn finds_cores_by_content_and_library() {
+        let database = CoreDatabase::bundled();
+        let gba_cores = database
+            .for_extension("GBA")
+            .map(|core| core.name.as_str())
+            .collect::<Vec<_>>();
+
+        assert_eq!(gba_cores.first(), Some(&"mgba"));
+        assert!(database
+            .for_database("Nintendo - Game Boy")
+            .any(|core| core.name == "gambatte"));
+        assert_eq!(
+            database
+                .for_library_path(Path::new("cores/snes9x_libretro.so"))
+                .map(|core| core.name.as_str()),
+            Some("snes9x")
+        );
+    }
+}
@@ -0,0 +1,528 @@
+//! Installing, updating and verifying the cores downloaded from the buildbot.
+//!
+//! Installed cores are recorded in a manifest in the cores directory, with the
+//! SHA1 of their library. Libraries are replaced atomically, and the previous
+//! version is kept so a bad update can be rolled back.
+//!
+//! The buildbot publishes no checksums, so the SHA1 is taken from the download
+//! itself. It only tells whether the library changed since it was installed,
+//! not whether the download was correct, beyond it being a shared library.
+
+use std::collections::BTreeMap;
+use std::env::consts::{DLL_EXTENSION, OS};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use anyhow::{bail, ensure, Context, Result};
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+
+use crate::buildbot::{self, Progress, Source};
+use crate::config;
+use crate::util::{self, write_atomically};
+
+const MANIFEST_NAME: &str = "cores.json";
+const LIBRARY_SUFFIX: &str = "_libretro";
+/// Appended to the file name of the library a core was updated from.
+const PREVIOUS_SUFFIX: &str = ".previous";
+/// Source recorded for libraries that were found in the cores directory, e.g.
+/// installed by hand, when an update replaces them.
+const UNRECORDED: &str = "unrecorded";
+
+pub fn default_directory() -> PathBuf {
+    config::data_path("cores")
+}
+
+/// The file name of a core's library on this platform, e.g. `gambatte_libretro.so`.
+pub fn library_name(core_name: &str) -> String {
+    format!("{core_name}{LIBRARY_SUFFIX}.{DLL_EXTENSION}")
+}
+
+/// A core as recorded in the manifest.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Installed {
+    /// Lowercase hex SHA1 of the library as it was installed, see the module docs.
+    pub sha1: String,
+    /// Where the library was downloaded from.
+    pub source: String,
+    pub installed_at: String,
+    /// Pinned cores are skipped by updates.
+    #[serde(default)]
+    pub pinned: bool,
+    /// The version replaced by the last update, kept for rollbacks.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previous: Option<Box<Installed>>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct Manifest {
+    cores: BTreeMap<String, Installed>,
+}
+
+/// What installing or updating a core did.
+pub enum Outcome {
+    Installed,
+    Updated,
+    UpToDate,
+    /// The core is pinned and was left alone.
+    Pinned,
+}
+
+/// The result of checking an installed library against the manifest.
+pub enum Verification {
+    Ok,
+    /// The library doesn't match the recorded checksum.
+    Modified {
+        actual_sha1: String,
+    },
+    /// The core is recorded, but its library is gone.
+    Missing,
+    /// The library exists but was never recorded, e.g. installed by hand.
+    Unrecorded,
+}
+
+/// The cores directory together with its manifest.
+pub struct CoreStore {
+    dir: PathBuf,
+    source: Source,
+    manifest: Manifest,
+}
+
+impl CoreStore {
+    pub fn open(dir: impl Into<PathBuf>, source: Source) -> Result<Self> {
+        let dir = dir.into();
+        let manifest_path = dir.join(MANIFEST_NAME);
+        let manifest = match fs::read(&manifest_path) {
+            Ok(manifest) => serde_json::from_slice(&manifest)
+                .with_context(|| format!("invalid core manifest {manifest_path:?}"))?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Manifest::default(),
+            Err(err) => {
+                return Err(err).with_context(|| format!("failed to read {manifest_path:?}"))
+            }
+        };
+
+        Ok(Self {
+            dir,
+            source,
+            manifest,
+        })
+    }
+
+    pub fn library_path(&self, core_name: &str) -> PathBuf {
+        self.dir.join(library_name(core_name))
+    }
+
+    fn previous_library_path(&self, core_name: &str) -> PathBuf {
+        self.dir
+            .join(format!("{}{PREVIOUS_SUFFIX}", library_name(core_name)))
+    }
+
+    pub fn installed(&self, core_name: &str) -> Option<&Installed> {
+        self.manifest.cores.get(core_name)
+    }
+
+    /// The names of all recorded cores and of unrecorded libraries in the cores directory.
+    pub fn core_names(&self) -> Result<Vec<String>> {
+        let mut names = self.manifest.cores.keys().cloned().collect::<Vec<_>>();
+        let library_suffix = format!("{LIBRARY_SUFFIX}.{DLL_EXTENSION}");
+
+        if self.dir.is_dir() {
+            for entry in
+                fs::read_dir(&self.dir).with_context(|| format!("failed to read {:?}", self.dir))?
+            {
+                let file_name = entry?.file_name();
+                let Some(name) = file_name
+                    .to_str()
+                    .and_then(|file_name| file_name.strip_suffix(&library_suffix))
+                else {
+                    continue;
+                };
+
+                if !names.iter().any(|known| known == name) {
+                    names.push(name.to_owned());
+                }
+            }
+        }
+
+        names.sort();
+
+        Ok(names)
+    }
+
+    /// The path of the core's library, installing the core if it isn't yet.
+    pub fn find_or_install(&mut self, core_name: &str) -> Result<PathBuf> {
+        let library_path = self.library_path(core_name);
+
+        if !library_path.exists() {
+            eprintln!("Downloading `{}`…", library_name(core_name));
+
+            self.install(core_name, &Progress::default())?;
+
+            eprintln!("Download successful!");
+        }
+
+        Ok(library_path)
+    }
+
+    /// Downloads the core and replaces the installed version, unless it's the same.
+    pub fn install(&mut self, core_name: &str, progress: &Progress) -> Result<Outcome> {
+        let library_name = library_name(core_name);
+        let library = buildbot::download_library(&self.source, &library_name, progress)
+            .with_context(|| format!("failed to download `{library_name}`"))?;
+
+        check_library(&library)
+            .with_context(|| format!("downloaded `{library_name}` is broken"))?;
+
+        let sha1 = hex::encode(Sha1::digest(&library));
+        let current = self.installed(core_name).cloned();
+
+        if let Some(current) = &current {
+            if current.sha1 == sha1 && matches!(self.verify(core_name)?, Verification::Ok) {
+                return Ok(Outcome::UpToDate);
+            }
+        }
+
+        fs::create_dir_all(&self.dir).context("failed to create core directory")?;
+
+        let library_path = self.library_path(core_name);
+        let previous_path = self.previous_library_path(core_name);
+        // the library on disk becomes the previous version, whether or not it is recorded
+        let previous = match fs::read(&library_path) {
+            Ok(library) => Some(hex::encode(Sha1::digest(library))),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+            Err(err) => {
+                return Err(err).with_context(|| format!("failed to read {library_path:?}"))
+            }
+        }
+        .map(|previous_sha1| match &current {
+            Some(current) if current.sha1 == previous_sha1 => Installed {
+                previous: None,
+                ..current.clone()
+            },
+            _ => Installed {
+                sha1: previous_sha1,
+                source: UNRECORDED.to_owned(),
+                installed_at: UNRECORDED.to_owned(),
+                pinned: false,
+                previous: None,
+            },
+        });
+        let has_previous = previous.is_some();
+
+        if has_previous {
+            fs::rename(&library_path, &previous_path)
+                .with_context(|| format!("failed to back up {library_path:?}"))?;
+        }
+
+        let installed = Installed {
+            sha1,
+            source: self.source.to_string(),
+            installed_at: util::timestamp(),
+            pinned: current.as_ref().is_some_and(|current| current.pinned),
+            previous: previous.map(Box::new),
+        };
+        let res = write_atomically(&library_path, &library).and_then(|()| {
+            self.manifest.cores.insert(core_name.to_owned(), installed);
+            self.save_manifest()
+        });
+
+        if let Err(err) = res {
+            match current {
+                Some(current) => self.manifest.cores.insert(core_name.to_owned(), current),
+                None => self.manifest.cores.remove(core_name),
+            };
+
+            if has_previous {
+                fs::rename(&previous_path, &library_path).ok();
+            }
+
+            return Err(err.context(format!("failed to install `{library_name}`")));
+        }
+
+        Ok(if has_previous {
+            Outcome::Updated
+        } else {
+            Outcome::Installed
+        })
+    }
+
+    /// Installs the latest version of the core, unless it is pinned.
+    pub fn update(&mut self, core_name: &str) -> Result<Outcome> {
+        if self.installed(core_name).is_some_and(|core| core.pinned) {
+            return Ok(Outcome::Pinned);
+        }
+
+        self.install(core_name, &Progress::default())
+    }
+
+    pub fn remove(&mut self, core_name: &str) -> Result<()> {
+        let library_path = self.library_path(core_name);
+        let is_recorded = self.manifest.cores.remove(core_name).is_some();
+
+        ensure!(
+            is_recorded || library_path.exists(),
+            "`{core_name}` is not installed"
+        );
+
+        for path in [library_path, self.previous_library_path(core_name)] {
+            match fs::remove_file(&path) {
+                Ok(()) => {}
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                Err(err) => return Err(err).with_context(|| format!("failed to remove {path:?}")),
+            }
+        }
+
+        self.save_manifest()
+    }
+
+    pub fn set_pinned(&mut self, core_name: &str, pinned: bool) -> Result<()> {
+        let core = self
+            .manifest
+            .cores
+            .get_mut(core_name)
+            .with_context(|| format!("`{core_name}` is not installed"))?;
+
+        core.pinned = pinned;
+
+        self.save_manifest()
+    }
+
+    /// Restores the version the last update replaced.
+    pub fn rollback(&mut self, core_name: &str) -> Result<()> {
+        let library_path = self.library_path(core_name);
+        let previous_path = self.previous_library_path(core_name);
+        let core = self
+            .manifest
+            .cores
+            .get(core_name)
+            .with_context(|| format!("`{core_name}` is not installed"))?;
+        let pinned = core.pinned;
+        let Some(previous) = core.previous.clone() else {
+            bail!("no previous version of `{core_name}` recorded");
+        };
+
+        ensure!(
+            previous_path.exists(),
+            "previous version of `{core_name}` is gone"
+        );
+
+        fs::rename(&previous_path, &library_path)
+            .with_context(|| format!("failed to restore {previous_path:?}"))?;
+
+        self.manifest.cores.insert(
+            core_name.to_owned(),
+            Installed {
+                pinned,
+                ..*previous
+            },
+        );
+
+        self.save_manifest()
+    }
+
+    /// Checks the installed library against the checksum recorded when it was installed.
+    pub fn verify(&self, core_name: &str) -> Result<Verification> {
+        let library_path = self.library_path(core_name);
+        let installed = self.installed(core_name);
+
+        if !library_path.exists() {
+            return match installed {
+                Some(_) => Ok(Verification::Missing),
+                None => bail!("`{core_name}` is not installed"),
+            };
+        }
+
+        let Some(installed) = installed else {
+            return Ok(Verification::Unrecorded);
+        };
+
+        let library =
+            fs::read(&library_path).with_context(|| format!("failed to read {library_path:?}"))?;
+        let actual_sha1 = hex::encode(Sha1::digest(library));
+
+        Ok(if actual_sha1 == installed.sha1 {
+            Verification::Ok
+        } else {
+            Verification::Modified { actual_sha1 }
+        })
+    }
+
+    fn save_manifest(&self) -> Result<()> {
+        let manifest = serde_json::to_vec_pretty(&self.manifest)?;
+
+        write_atomically(&self.dir.join(MANIFEST_NAME), &manifest)
+    }
+}
+
+/// Checks that `library` is a shared library for this platform.
+fn check_library(library: &[u8]) -> Result<()> {
+    let magics: &[&[u8]] = match OS {
+        "windows" => &[b"MZ"],
+        "macos" => &[
+            &[0xCF, 0xFA, 0xED, 0xFE],
+            &[0xCE, 0xFA, 0xED, 0xFE],
+            &[0xCA, 0xFE, 0xBA, 0xBE],
+        ],
+        _ => &[b"\x7FELF"],
+    };
+
+    ensure!(
+        magics.iter().any(|magic| library.starts_with(magic)),
+        "not a shared library for {OS}"
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::path::Path;
+
+    use tempfile::TempDir;
+    use zip::ZipWriter;
+
+    use super::*;
+
+    const CORE: &str = "test";
+
+    /// A library that passes [`check_library`] on this platform.
+    fn library(version: &str) -> Vec<u8> {
+        let magic: &[u8] = match OS {
+            "windows" => b"MZ",
+            "macos" => &[0xCF, 0xFA, 0xED, 0xFE],
+            _ => b"\x7FELF",
+        };
+
+        [magic, version.as_bytes()].concat()
+    }
+
+    /// A cores directory and a local mirror serving `version` of the test core.
+    fn store() -> (TempDir, CoreStore) {
+        let temp = TempDir::new().unwrap();
+        let dir = temp.path();
+
+        fs::create_dir_all(dir.join("mirror")).unwrap();
+
+        let store = CoreStore::open(dir.join("cores"), Source::Directory(dir.join("mirror")));
+
+        (temp, store.unwrap())
+    }
+
+    fn publish(dir: &Path, version: &str) {
+        let name = library_name(CORE);
+        let file = fs::File::create(dir.join("mirror").join(format!("{name}.zip"))).unwrap();
+        let mut zip = ZipWriter::new(file);
+
+        zip.start_file(name, Default::default()).unwrap();
+        zip.write_all(&library(version)).unwrap();
+        zip.finish().unwrap();
+    }
+
+    fn install(store: &mut CoreStore) -> Outcome {
+        store.install(CORE, &Progress::default()).unwrap()
+    }
+
+    fn sha1(version: &str) -> String {
+        hex::encode(Sha1::digest(library(version)))
+    }
+
+    #[test]
+    fn updates_and_rolls_back() {
+        let (temp, mut store) = store();
+        let dir = temp.path();
+
+        publish(dir, "1");
+        assert!(matches!(install(&mut store), Outcome::Installed));
+        assert!(matches!(install(&mut store), Outcome::UpToDate));
+
+        publish(dir, "2");
+        assert!(matches!(install(&mut store), Outcome::Updated));
+
+        let installed = store.installed(CORE).unwrap();
+
+        assert_eq!(installed.sha1, sha1("2"));
+        assert_eq!(installed.previous.as_ref().unwrap().sha1, sha1("1"));
+
+        store.rollback(CORE).unwrap();
+
+        assert_eq!(fs::read(store.library_path(CORE)).unwrap(), library("1"));
+        assert!(store.installed(CORE).unwrap().previous.is_none());
+        assert!(matches!(store.verify(CORE).unwrap(), Verification::Ok));
+    }
+
+    #[test]
+    fn keeps_unrecorded_libraries_as_previous_version() {
+        let (temp, mut store) = store();
+        let dir = temp.path();
+
+        fs::create_dir_all(dir.join("cores")).unwrap();
+        fs::write(store.library_path(CORE), library("manual")).unwrap();
+        assert!(matches!(
+            store.verify(CORE).unwrap(),
+            Verification::Unrecorded
+        ));
+
+        publish(dir, "1");
+        assert!(matches!(install(&mut store), Outcome::Updated));
+
+        let previous = store.installed(CORE).unwrap().previous.clone().unwrap();
+
+        assert_eq!(previous.sha1, sha1("manual"));
+        assert_eq!(previous.source, UNRECORDED);
+
+        store.rollback(CORE).unwrap();
+
+        assert_eq!(
+            fs::read(store.library_path(CORE)).unwrap(),
+            library("manual")
+        );
+    }
+
+    #[test]
+    fn records_no_previous_version_for_missing_libraries() {
+        let (temp, mut store) = store();
+        let dir = temp.path();
+
+        publish(dir, "1");
+        install(&mut store);
+        fs::remove_file(store.library_path(CORE)).unwrap();
+        assert!(matches!(store.verify(CORE).unwrap(), Verification::Missing));
+
+        publish(dir, "2");
+        assert!(matches!(install(&mut store), Outcome::Installed));
+        assert!(store.installed(CORE).unwrap().previous.is_none());
+
+        let err = store.rollback(CORE).unwrap_err();
+
+        assert!(err.to_string().contains("no previous version"), "{err}");
+    }
+
+    #[test]
+    fn reinstalls_modified_libraries() {
+        let (temp, mut store) = store();
+        let dir = temp.path();
+
+        publish(dir, "1");
+        install(&mut store);
+        fs::write(store.library_path(CORE), library("patched")).unwrap();
+        assert!(matches!(
+            store.verify(CORE).unwrap(),
+            Verification::Modified { .. }
+        ));
+
+        assert!(matches!(install(&mut store), Outcome::Updated));
+        assert!(matches!(store.verify(CORE).unwrap(), Verification::Ok));
+        assert_eq!(
+            store
+                .installed(CORE)
+                .unwrap()
+                .previous
+                .as_ref()
+                .unwrap()
+                .source,
+            UNRECORDED
+        );
+    }
+}
@@ -11,6 +11,7 @@ pub const ENVIRONMENT_PRIVATE: c_uint = 0x20000;
 
 #[derive(FromRepr, Debug, PartialEq)]
 #[repr(u32)]
+#[allow(non_camel_case_types, clippy::upper_case_acronyms)]
 pub enum Command {
     // const unsigned * --
     // Sets screen rotation of graphics.
@@ -508,4 +509,24 @@ pub enum Command {
     GET_INPUT_BITMASKS = 51 | ENVIRONMENT_EXPERIMENTAL,
     GET_CORE_OPTIONS_VERSION = 52,
     SET_CORE_OPTIONS_DISPLAY = 55,
+
+    // unsigned * --
+    // Unsigned value is the API version number of the message
+    // interface supported by the frontend. If callback returns
+    // false, API version is assumed to be 0.
+    //
+    // In legacy code, messages may be displayed in an
+    // implementation-specific manner via the
+    // SET_MESSAGE environment callback.
+    //
+    // If version is >= 1 however, messages may instead be
+    // displayed via the SET_MESSAGE_EXT environment callback.
+    GET_MESSAGE_INTERFACE_VERSION = 59,
+
+    // const struct MessageExt * --
+    // Sets a message to be displayed in an implementation-specific
+    // manner for a certain amount of milliseconds.
+    // Additionally allows the core to specify message logging level,
+    // priority and destination (OSD, logging interface or both).
+    SET_MESSAGE_EXT = 60,
 }
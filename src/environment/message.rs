@@ -0,0 +1,38 @@
+use std::ffi::{c_char, c_int, c_uint};
+
+// Message is only sent to the logging interface.
+pub const MESSAGE_TARGET_LOG: c_int = 2;
+
+// Progress indicator, `progress` is a percentage from 0 to 100
+// or -1 for an indeterminate progress.
+pub const MESSAGE_TYPE_PROGRESS: c_int = 3;
+
+// Mirrors `struct retro_message_ext`, which libretro-sys does not provide.
+#[repr(C)]
+pub struct MessageExt {
+    // Message string to be displayed/logged.
+    pub msg: *const c_char,
+
+    // Duration (in ms) of message when targeting the OSD.
+    pub duration: c_uint,
+
+    // Message priority when targeting the OSD.
+    // When multiple concurrent messages are sent to the frontend
+    // and the frontend does not have the capacity to display them
+    // all, messages with the *highest* priority value should be
+    // shown.
+    pub priority: c_uint,
+
+    // Message logging level (info, warn, error, etc.).
+    pub level: c_int,
+
+    // Message destination: OSD, logging interface or both.
+    pub target: c_int,
+
+    // Message 'type' when targeting the OSD.
+    pub ty: c_int,
+
+    // Task progress when targeting the OSD and message is of type
+    // MESSAGE_TYPE_PROGRESS.
+    pub progress: i8,
+}
@@ -1,2 +1,5 @@
 mod command;
 pub use command::*;
+
+mod message;
+pub use message::*;
@@ -0,0 +1,339 @@
+//! Firmware (BIOS) files cores expect in the system directory.
+//!
+//! The registry lists the firmware of each core in the core database, with the
+//! MD5 from its info file and, for well-known dumps, the SHA1. Checking a core
+//! hashes its files in the system directory and compares them.
+
+use std::fmt::{self, Display};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use md5::Md5;
+use sha1::{Digest, Sha1};
+
+use crate::config;
+use crate::core_info::CoreInfo;
+
+/// SHA1s of well-known dumps, by the MD5 core info files name them with.
+const KNOWN_SHA1S: &[(&str, &str)] = &[
+    // gb_bios.bin
+    (
+        "32fbbd84168d3482956eb3c5051637f5",
+        "4ed31ec6b0b175bb109c0eb5fd3d193da823339f",
+    ),
+    // gbc_bios.bin
+    (
+        "dbfce9db9deaa2567f6a84fde55f9680",
+        "1293d68bf9643bc4f36954c1e80e38f39864528d",
+    ),
+    // gba_bios.bin
+    (
+        "a860e8c0b6d573d191e4ec7db1b1e4f6",
+        "300c20df6731a33952ded8c436f7f186d25d3492",
+    ),
+    // disksys.rom
+    (
+        "ca30b50f880eb660a320674ed365ef7a",
+        "57fe1bdee955bb48d357e463ccbf129496930b62",
+    ),
+    // scph5501.bin
+    (
+        "490f666e1afb15b7362b406ed1cea246",
+        "0555c6fae8906f3f09baf5988f00e55f88e9f30b",
+    ),
+];
+
+pub fn default_system_directory() -> PathBuf {
+    config::data_path("system")
+}
+
+/// A firmware file a core expects, with the hashes a good dump has.
+#[derive(Clone, Debug)]
+pub struct Firmware {
+    /// Path relative to the system directory.
+    pub path: String,
+    pub description: String,
+    /// Optional firmware improves accuracy or enables features, content runs without it.
+    pub optional: bool,
+    /// Lowercase hex MD5, from the info file's notes.
+    pub md5: Option<String>,
+    /// Lowercase hex SHA1.
+    pub sha1: Option<&'static str>,
+}
+
+/// The firmware `core` expects, required files first.
+pub fn for_core(core: &CoreInfo) -> Vec<Firmware> {
+    let mut firmware = core.firmware.clone();
+
+    firmware.sort_by_key(|firmware| firmware.optional);
+
+    firmware
+}
+
+/// The SHA1 of the well-known dump with the lowercase hex `md5`.
+pub(crate) fn known_sha1(md5: &str) -> Option<&'static str> {
+    KNOWN_SHA1S
+        .iter()
+        .find(|(known_md5, _)| *known_md5 == md5)
+        .map(|(_, sha1)| *sha1)
+}
+
+/// The state of a firmware file in the system directory.
+#[derive(Clone, Debug)]
+pub enum Status {
+    /// The file matches all known hashes.
+    Ok,
+    /// The file exists, but there is no hash to check it against.
+    Unverified,
+    Missing,
+    /// The file doesn't match a known hash, e.g. it is a bad or different dump.
+    Mismatch {
+        algorithm: &'static str,
+        expected: String,
+        actual: String,
+    },
+}
+
+impl Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Status::Ok => write!(f, "ok"),
+            Status::Unverified => write!(f, "present, no known hash"),
+            Status::Missing => write!(f, "missing"),
+            Status::Mismatch {
+                algorithm,
+                expected,
+                actual,
+            } => write!(f, "{algorithm} mismatch, expected {expected}, got {actual}"),
+        }
+    }
+}
+
+/// A firmware file together with its state.
+#[derive(Clone, Debug)]
+pub struct Check {
+    pub firmware: Firmware,
+    pub status: Status,
+}
+
+impl Check {
+    /// Whether content may fail to run, or run incorrectly, because of this file.
+    pub fn is_problem(&self) -> bool {
+        match self.status {
+            Status::Ok | Status::Unverified => false,
+            Status::Missing => !self.firmware.optional,
+            Status::Mismatch { .. } => true,
+        }
+    }
+}
+
+/// Checks the firmware `core` expects in `system_dir`.
+pub fn check_core(core: &CoreInfo, system_dir: &Path) -> Result<Vec<Check>> {
+    for_core(core)
+        .into_iter()
+        .map(|firmware| {
+            let status = check(&firmware, system_dir)?;
+
+            Ok(Check { firmware, status })
+        })
+        .collect()
+}
+
+/// Checks `firmware` in `system_dir` against its known hashes.
+pub fn check(firmware: &Firmware, system_dir: &Path) -> Result<Status> {
+    let path = system_dir.join(&firmware.path);
+    let data = match fs::read(&path) {
+        Ok(data) => data,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Status::Missing),
+        Err(err) => return Err(err).with_context(|| format!("failed to read {path:?}")),
+    };
+
+    let hashes = [
+        (
+            "MD5",
+            firmware.md5.as_deref(),
+            hex::encode(Md5::digest(&data)),
+        ),
+        ("SHA1", firmware.sha1, hex::encode(Sha1::digest(&data))),
+    ];
+    let mut status = Status::Unverified;
+
+    for (algorithm, expected, actual) in hashes {
+        let Some(expected) = expected else {
+            continue;
+        };
+
+        if !expected.eq_ignore_ascii_case(&actual) {
+            return Ok(Status::Mismatch {
+                algorithm,
+                expected: expected.to_owned(),
+                actual,
+            });
+        }
+
+        status = Status::Ok;
+    }
+
+    Ok(status)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    const BIOS: &[u8] = b"not really a BIOS";
+    const BIOS_SHA1: &str = "72660fd87d95654e55eccd65674b421e9083de8c";
+
+    /// A system directory with `bios.bin`.
+    fn system_directory() -> TempDir {
+        let dir = TempDir::new().unwrap();
+
+        fs::write(dir.path().join("bios.bin"), BIOS).unwrap();
+
+        dir
+    }
+
+    fn firmware(path: &str, md5: Option<String>, sha1: Option<&'static str>) -> Firmware {
+        Firmware {
+            path: path.to_owned(),
+            description: path.to_owned(),
+            optional: false,
+            md5,
+            sha1,
+        }
+    }
+
+    fn md5() -> String {
+        hex::encode(Md5::digest(BIOS))
+    }
+
+    #[test]
+    fn reports_missing_firmware_as_a_problem_unless_optional() {
+        let dir = system_directory();
+        let mut missing = firmware("missing.bin", Some(md5()), None);
+        let status = check(&missing, dir.path()).unwrap();
+
+        assert!(matches!(status, Status::Missing));
+        assert!(Check {
+            firmware: missing.clone(),
+            status: status.clone(),
+        }
+        .is_problem());
+
+        missing.optional = true;
+
+        assert!(!Check {
+            firmware: missing,
+            status,
+        }
+        .is_problem());
+    }
+
+    #[test]
+    fn accepts_files_without_known_hashes_as_unverified() {
+        let dir = system_directory();
+        let status = check(&firmware("bios.bin", None, None), dir.path()).unwrap();
+
+        assert!(matches!(status, Status::Unverified));
+        assert!(!Check {
+            firmware: firmware("bios.bin", None, None),
+            status,
+        }
+        .is_problem());
+    }
+
+    #[test]
+    fn verifies_md5_and_sha1() {
+        let dir = system_directory();
+        let upper_md5 = md5().to_ascii_uppercase();
+
+        for firmware in [
+            firmware("bios.bin", Some(md5()), None),
+            firmware("bios.bin", None, Some(BIOS_SHA1)),
+            firmware("bios.bin", Some(upper_md5), Some(BIOS_SHA1)),
+        ] {
+            assert!(matches!(check(&firmware, dir.path()).unwrap(), Status::Ok));
+        }
+    }
+
+    #[test]
+    fn reports_mismatching_hashes() {
+        let dir = system_directory();
+        let bad_md5 = "0".repeat(32);
+        let bad_sha1 = "0000000000000000000000000000000000000000";
+
+        let status = check(
+            &firmware("bios.bin", Some(bad_md5.clone()), None),
+            dir.path(),
+        );
+
+        assert!(matches!(
+            status.unwrap(),
+            Status::Mismatch { algorithm: "MD5", expected, actual }
+                if expected == bad_md5 && actual == md5()
+        ));
+
+        // a matching MD5 doesn't hide a mismatching SHA1
+        let mismatch = firmware("bios.bin", Some(md5()), Some(bad_sha1));
+        let status = check(&mismatch, dir.path()).unwrap();
+
+        assert!(matches!(
+            status,
+            Status::Mismatch {
+                algorithm: "SHA1",
+                ..
+            }
+        ));
+        assert!(Check {
+            firmware: Firmware {
+                optional: true,
+                ..mismatch
+            },
+            status,
+        }
+        .is_problem());
+    }
+
+    #[test]
+    fn lists_required_firmware_first() {
+        let core = CoreInfo::parse(
+            "test",
+            r#"
+firmware_count = 3
+firmware0_path = "optional.bin"
+firmware0_opt = "true"
+firmware1_path = "required.bin"
+firmware1_opt = "false"
+firmware2_path = "also-required.bin"
+"#,
+        );
+        let paths = for_core(&core)
+            .into_iter()
+            .map(|firmware| firmware.path)
+            .collect::<Vec<_>>();
+
+        assert_eq!(paths, ["required.bin", "also-required.bin", "optional.bin"]);
+    }
+
+    #[test]
+    fn checks_all_firmware_of_a_core() {
+        let dir = system_directory();
+        let core = CoreInfo::parse(
+            "test",
+            r#"
+firmware_count = 2
+firmware0_path = "bios.bin"
+firmware1_path = "missing.bin"
+"#,
+        );
+        let checks = check_core(&core, dir.path()).unwrap();
+
+        assert!(matches!(checks[0].status, Status::Unverified));
+        assert!(matches!(checks[1].status, Status::Missing));
+        assert_eq!(checks.iter().filter(|check| check.is_problem()).count(), 1);
+    }
+}
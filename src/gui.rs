@@ -1,22 +1,42 @@
-use std::path::PathBuf;
-use std::sync::mpsc::Receiver;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{Receiver, Sender};
+use std::sync::Arc;
 use std::time::Duration;
+use std::{fs, mem};
 
 use anyhow::{anyhow, Context, Result};
 
-use eframe::CreationContext;
 use egui::epaint::ImageDelta;
 
 use egui::widgets::Image;
+use parking_lot::RwLock;
+
 use egui::{
     menu, CentralPanel, ColorImage, ImageData, TextureFilter, TextureHandle, TextureOptions,
-    TextureWrapMode, TopBottomPanel,
+    TextureWrapMode, TopBottomPanel, ViewportCommand,
 };
 
-use crate::core;
-use crate::video::Frame;
+use ape::buildbot;
+use ape::config::Settings;
+use ape::core;
+use ape::osd::Notifications;
+use ape::util;
+use ape::video::Frame;
+
+use self::core_resolver::{CoreResolver, Resolution};
+use self::file_dialog::{FileDialog, Outcome, Target};
+use self::firmware::FirmwareWindow;
+use self::library::LibraryView;
+use super::Switch;
 
+mod cheats;
+mod core_resolver;
+mod file_dialog;
+mod firmware;
 mod input;
+mod library;
+mod osd;
+mod screenshot;
 
 const CORE_TEXTURE_OPTIONS: TextureOptions = TextureOptions {
     magnification: TextureFilter::Nearest,
@@ -24,7 +44,27 @@ const CORE_TEXTURE_OPTIONS: TextureOptions = TextureOptions {
     wrap_mode: TextureWrapMode::ClampToEdge,
 };
 
-pub fn run(core: PathBuf, rom: PathBuf) -> Result<()> {
+/// The startup settings of the GUI itself.
+///
+/// The running content has its own, see [`Gui::settings`].
+pub struct Options {
+    /// Directory cores are installed to.
+    pub cores_directory: PathBuf,
+    /// Library file with the scanned games and their play times.
+    pub library_path: PathBuf,
+    /// Directories scanned for the library.
+    pub library_dirs: Vec<PathBuf>,
+    /// Directory of DAT files identifying library games.
+    pub dat_dir: Option<PathBuf>,
+    /// Where missing cores are downloaded from.
+    pub buildbot: buildbot::Source,
+}
+
+pub fn run(
+    launch: Option<super::Launch>,
+    run_options: super::RunOptions,
+    options: Options,
+) -> Result<()> {
     let native_options = eframe::NativeOptions {
         vsync: true,
         ..<_>::default()
@@ -33,7 +73,32 @@ pub fn run(core: PathBuf, rom: PathBuf) -> Result<()> {
     eframe::run_native(
         "APE",
         native_options,
-        Box::new(move |cc| Box::new(Gui::new(cc, core, rom))),
+        Box::new(move |_cc| {
+            let Some(launch) = launch else {
+                return Box::new(Launcher::Browsing(Box::new(Browsing {
+                    library: LibraryView::new(&options),
+                    run_options,
+                    options,
+                })));
+            };
+
+            let resolver = CoreResolver::new(
+                launch.rom.clone(),
+                launch.core.clone(),
+                None,
+                false,
+                &options,
+                &run_options.layers,
+            );
+
+            Box::new(Launcher::Resolving(Box::new(Resolving {
+                resolver,
+                launch,
+                run_options,
+                options,
+                library: None,
+            })))
+        }),
     )
     .map_err(|err| anyhow!("{err}"))
     .context("failed to run eframe")?;
@@ -41,29 +106,186 @@ pub fn run(core: PathBuf, rom: PathBuf) -> Result<()> {
     Ok(())
 }
 
+/// Picks the content to launch and resolves its core before running it.
+enum Launcher {
+    /// No ROM was given, the game is picked from the library.
+    Browsing(Box<Browsing>),
+    Resolving(Box<Resolving>),
+    Running(Box<Gui>),
+    /// Launching was cancelled and the window is closing.
+    Closing,
+}
+
+struct Browsing {
+    library: LibraryView,
+    run_options: super::RunOptions,
+    options: Options,
+}
+
+struct Resolving {
+    resolver: CoreResolver,
+    launch: super::Launch,
+    run_options: super::RunOptions,
+    options: Options,
+    /// The library the game was picked from, shown again if resolving is cancelled.
+    library: Option<LibraryView>,
+}
+
+impl Launcher {
+    fn browse(&mut self, ctx: &egui::Context) {
+        let Launcher::Browsing(browsing) = self else {
+            return;
+        };
+        let Some(outcome) = browsing.library.show(ctx, false) else {
+            return;
+        };
+        let Launcher::Browsing(browsing) = mem::replace(self, Launcher::Closing) else {
+            unreachable!();
+        };
+        let Browsing {
+            library,
+            run_options,
+            options,
+        } = *browsing;
+
+        match outcome {
+            library::Outcome::Play { rom, database } => {
+                let resolver = CoreResolver::new(
+                    rom.clone(),
+                    None,
+                    database,
+                    false,
+                    &options,
+                    &run_options.layers,
+                );
+                let launch = super::Launch {
+                    core: None,
+                    rom,
+                    cheats: None,
+                    patches: Vec::new(),
+                };
+
+                *self = Launcher::Resolving(Box::new(Resolving {
+                    resolver,
+                    launch,
+                    run_options,
+                    options,
+                    library: Some(library),
+                }));
+            }
+            library::Outcome::Closed => ctx.send_viewport_cmd(ViewportCommand::Close),
+        }
+    }
+
+    fn resolve(&mut self, ctx: &egui::Context) {
+        let Launcher::Resolving(resolving) = self else {
+            return;
+        };
+        let Some(resolution) = resolving.resolver.show(ctx) else {
+            return;
+        };
+        let Launcher::Resolving(resolving) = mem::replace(self, Launcher::Closing) else {
+            unreachable!();
+        };
+        let Resolving {
+            launch,
+            run_options,
+            options,
+            library,
+            ..
+        } = *resolving;
+
+        match (resolution, library) {
+            (Resolution::Resolved { rom, core }, _) => {
+                // there's no current core to keep while launching
+                let core = core.expect("launched ROM resolved without a core");
+                let content = launch.into_content(core, rom);
+
+                *self = Launcher::Running(Box::new(Gui::new(ctx, content, run_options, options)));
+            }
+            (Resolution::Cancelled, Some(library)) => {
+                *self = Launcher::Browsing(Box::new(Browsing {
+                    library,
+                    run_options,
+                    options,
+                }));
+            }
+            (Resolution::Cancelled, None) => ctx.send_viewport_cmd(ViewportCommand::Close),
+        }
+    }
+}
+
+impl eframe::App for Launcher {
+    fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
+        match self {
+            Launcher::Browsing(_) => self.browse(ctx),
+            Launcher::Resolving(_) => self.resolve(ctx),
+            Launcher::Running(gui) => gui.update(ctx, frame),
+            Launcher::Closing => {}
+        }
+    }
+}
+
 pub struct Gui {
     core_texture: TextureHandle,
     frame_rx: Receiver<Option<Frame>>,
     core_handle: core::Handle,
+    switch_tx: Sender<Switch>,
+    notifications: Notifications,
+    file_dialog: Option<FileDialog>,
+    core_resolver: Option<CoreResolver>,
+    firmware_window: Option<FirmwareWindow>,
+    library: Option<LibraryView>,
+    options: Options,
+    /// Loads the config of ROMs loaded from the GUI.
+    layers: super::Layers,
+    /// The settings of the running content, see [`Gui::settings`].
+    content_settings: Arc<RwLock<Settings>>,
     save_state: Option<Vec<u8>>,
     show_menu: bool,
     fullscreen: bool,
 }
 
 impl Gui {
-    fn new(cc: &CreationContext, core: PathBuf, rom: PathBuf) -> Self {
+    fn new(
+        ctx: &egui::Context,
+        content: super::Content,
+        run_options: super::RunOptions,
+        options: Options,
+    ) -> Self {
         let texture_name = "Core";
         let image = ImageData::from(ColorImage::example());
-        let core_texture = cc
-            .egui_ctx
-            .load_texture(texture_name, image, CORE_TEXTURE_OPTIONS);
+        let core_texture = ctx.load_texture(texture_name, image, CORE_TEXTURE_OPTIONS);
 
-        let (frame_rx, core_handle) = super::run(core, rom, cc.egui_ctx.clone()).unwrap();
+        let notifications = Notifications::new();
+        let layers = run_options.layers.clone();
+        let super::Running {
+            frame_rx,
+            core_handle,
+            switch_tx,
+            settings,
+            ..
+        } = super::run(
+            content,
+            run_options,
+            notifications.clone(),
+            super::Frontend::Gui(ctx.clone()),
+        )
+        .unwrap();
 
         Self {
             core_texture,
             frame_rx,
             core_handle,
+            switch_tx,
+            notifications,
+            file_dialog: None,
+            core_resolver: None,
+            firmware_window: None,
+            library: None,
+            options,
+            layers,
+            content_settings: settings,
             save_state: None,
             show_menu: false,
             fullscreen: false,
@@ -71,21 +293,300 @@ impl Gui {
     }
 }
 
+impl Gui {
+    /// The settings of the running content, with its per-core and per-game files.
+    ///
+    /// This is a copy, the core thread replaces them when switching content.
+    fn settings(&self) -> Settings {
+        self.content_settings.read().clone()
+    }
+
+    /// The state file of the loaded ROM in the states directory.
+    fn state_path(&self, states_directory: &Path) -> Option<PathBuf> {
+        let rom = self.core_handle.run(|core| core.rom_path()).unwrap()?;
+
+        Some(util::file_for_rom(states_directory, &rom, "state"))
+    }
+
+    /// Saves the state in memory and to the state file, which survives restarts.
+    fn save_state(&mut self) {
+        let save_state = match self.core_handle.run(|core| core.state()).unwrap() {
+            Ok(save_state) => save_state,
+            Err(err) => {
+                self.notifications
+                    .error(format!("Failed to save state: {err}"));
+                return;
+            }
+        };
+
+        let states_directory = self.settings().states_dir;
+
+        if let Some(state_path) = self.state_path(&states_directory) {
+            let res = fs::create_dir_all(&states_directory)
+                .context("failed to create states directory")
+                .and_then(|()| util::write_atomically(&state_path, &save_state));
+
+            if let Err(err) = res {
+                self.notifications
+                    .error(format!("Failed to write state file: {err:#}"));
+            }
+        }
+
+        self.save_state = Some(save_state);
+        self.notifications.info("State saved");
+    }
+
+    /// Loads the state saved last, from the state file if none was saved since starting.
+    fn load_state(&mut self) {
+        let save_state = match &self.save_state {
+            Some(save_state) => save_state.clone(),
+            None => match self.state_path(&self.settings().states_dir).map(fs::read) {
+                Some(Ok(save_state)) => save_state,
+                _ => {
+                    self.notifications.info("No state saved yet");
+                    return;
+                }
+            },
+        };
+
+        let res = self
+            .core_handle
+            .run(move |core| core.restore_state(&save_state))
+            .unwrap();
+
+        match res {
+            Ok(()) => self.notifications.info("State loaded"),
+            Err(err) => self
+                .notifications
+                .error(format!("Failed to load state: {err}")),
+        }
+    }
+
+    fn reset(&self) {
+        self.core_handle.run(|core| core.reset()).unwrap();
+        self.notifications.info("Reset");
+    }
+
+    /// Reloads the content from disk, like power cycling the console.
+    fn hard_reset(&self) {
+        match self.core_handle.run(|core| core.reload_game()).unwrap() {
+            Ok(()) => self.notifications.info("Hard reset"),
+            Err(err) => self
+                .notifications
+                .error(format!("Failed to hard reset: {err:#}")),
+        }
+    }
+
+    fn toggle_recording(&self) {
+        let recording_directory = self.settings().recording_dir;
+        let res = self
+            .core_handle
+            .run(move |core| {
+                if core.is_recording() {
+                    core.stop_recording().map(Some)
+                } else {
+                    core.start_recording(&recording_directory).map(|()| None)
+                }
+            })
+            .unwrap();
+
+        match res {
+            Ok(None) => self.notifications.info("Recording started"),
+            Ok(Some(files)) => self.notifications.info(format!(
+                "Recording saved to {}",
+                files.map_or_else(String::new, |files| files.video.display().to_string())
+            )),
+            Err(err) => self
+                .notifications
+                .error(format!("Recording failed: {err:#}")),
+        }
+    }
+
+    fn switch(&self, switch: Switch) {
+        let path = match &switch {
+            Switch::Rom { rom: path, .. } | Switch::Core(path) => path.clone(),
+        };
+
+        if self.switch_tx.send(switch).is_err() {
+            self.notifications.error("Core thread is gone");
+            return;
+        }
+
+        self.notifications
+            .info(format!("Loading {}", path.display()));
+    }
+
+    /// Resolves the core for `rom` in the background, then loads it.
+    ///
+    /// `database` is the DAT the game is in, if it is known from the library.
+    fn load_rom(&mut self, rom: PathBuf, database: Option<String>) {
+        self.core_resolver = Some(CoreResolver::new(
+            rom,
+            None,
+            database,
+            true,
+            &self.options,
+            &self.layers,
+        ));
+    }
+
+    fn show_library(&mut self, ctx: &egui::Context) {
+        let Some(library) = &mut self.library else {
+            return;
+        };
+
+        match library.show(ctx, true) {
+            Some(library::Outcome::Play { rom, database }) => {
+                self.library = None;
+                self.load_rom(rom, database);
+            }
+            Some(library::Outcome::Closed) => self.library = None,
+            None => {}
+        }
+    }
+
+    fn show_core_resolver(&mut self, ctx: &egui::Context) {
+        let Some(core_resolver) = &mut self.core_resolver else {
+            return;
+        };
+
+        match core_resolver.show(ctx) {
+            Some(Resolution::Resolved { rom, core }) => {
+                self.core_resolver = None;
+                self.switch(Switch::Rom { rom, core });
+            }
+            Some(Resolution::Cancelled) => self.core_resolver = None,
+            None => {}
+        }
+    }
+
+    fn open_file_dialog(&mut self, target: Target) {
+        let directory = self
+            .core_handle
+            .run(|core| core.rom_path())
+            .unwrap()
+            .and_then(|rom_path| rom_path.parent().map(ToOwned::to_owned))
+            .or_else(|| std::env::current_dir().ok())
+            .unwrap_or_default();
+
+        self.file_dialog = Some(FileDialog::new(target, directory));
+    }
+
+    fn show_file_dialog(&mut self, ctx: &egui::Context) {
+        let Some(file_dialog) = &mut self.file_dialog else {
+            return;
+        };
+
+        let target = file_dialog.target();
+
+        match file_dialog.show(ctx) {
+            Some(Outcome::Picked(path)) => {
+                self.file_dialog = None;
+
+                match target {
+                    Target::Rom => self.load_rom(path, None),
+                    Target::Core => self.switch(Switch::Core(path)),
+                }
+            }
+            Some(Outcome::Cancelled) => self.file_dialog = None,
+            None => {}
+        }
+    }
+
+    /// Loads a dropped core library or ROM.
+    fn handle_dropped_files(&mut self, ctx: &egui::Context) {
+        let path = ctx.input(|input| {
+            input
+                .raw
+                .dropped_files
+                .iter()
+                .find_map(|file| file.path.clone())
+        });
+
+        let Some(path) = path else {
+            return;
+        };
+
+        if file_dialog::is_core_library(&path) {
+            self.switch(Switch::Core(path));
+        } else {
+            self.load_rom(path, None);
+        }
+    }
+}
+
 impl eframe::App for Gui {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
         ctx.request_repaint_after(Duration::from_secs(1) / 60);
 
         self.handle_input(ctx);
+        self.handle_dropped_files(ctx);
+        self.handle_screenshot_events(ctx);
+        self.show_file_dialog(ctx);
+        self.show_core_resolver(ctx);
+        self.show_firmware_window(ctx);
+        self.show_library(ctx);
 
         if self.show_menu {
             TopBottomPanel::top("top").show(ctx, |ui| {
                 menu::bar(ui, |ui| {
                     ui.menu_button("File", |ui| {
-                        if ui.button("Load ROM").clicked() {
-                            println!("load rom!");
+                        if ui.button("Library…").clicked() {
+                            self.library = Some(LibraryView::new(&self.options));
+                            ui.close_menu();
+                        }
+
+                        if ui.button("Load ROM…").clicked() {
+                            self.open_file_dialog(Target::Rom);
+                            ui.close_menu();
+                        }
+
+                        if ui.button("Load Core…").clicked() {
+                            self.open_file_dialog(Target::Core);
+                            ui.close_menu();
+                        }
+
+                        if ui.button("Firmware…").clicked() {
+                            self.open_firmware_window();
+                            ui.close_menu();
+                        }
+
+                        ui.separator();
+
+                        if ui.button("Reset").clicked() {
+                            self.reset();
+                            ui.close_menu();
+                        }
+
+                        if ui.button("Hard Reset").clicked() {
+                            self.hard_reset();
+                            ui.close_menu();
+                        }
+
+                        ui.separator();
+
+                        if ui.button("Screenshot").clicked() {
+                            self.take_screenshot();
+                            ui.close_menu();
+                        }
+
+                        if ui.button("Screenshot (as displayed)").clicked() {
+                            self.request_displayed_screenshot(ctx);
+                            ui.close_menu();
+                        }
+
+                        if ui.button("Save Replay").clicked() {
+                            self.save_replay();
+                            ui.close_menu();
+                        }
+
+                        if ui.button("Start/Stop Recording").clicked() {
+                            self.toggle_recording();
                             ui.close_menu();
                         }
                     });
+
+                    ui.menu_button("Cheats", |ui| self.cheats_menu(ui));
                 });
             });
         }
@@ -102,7 +603,21 @@ impl eframe::App for Gui {
 
         let frame = egui::Frame::default();
         CentralPanel::default().frame(frame).show(ctx, |ui| {
-            self.core_handle.run(|core| core.run()).unwrap();
+            let shutdown_requested = self
+                .core_handle
+                .run(|core| {
+                    if !core.is_paused() {
+                        core.run()
+                    }
+
+                    core.is_shutdown_requested()
+                })
+                .unwrap();
+
+            if shutdown_requested {
+                ctx.send_viewport_cmd(ViewportCommand::Close);
+            }
+
             if let Ok(Some(frame)) = self.frame_rx.try_recv() {
                 let pixels = frame.buffer_to_packed_rgb888();
                 let size = [frame.width, frame.height];
@@ -116,5 +631,7 @@ impl eframe::App for Gui {
 
             ui.add_sized(ui.available_size(), image);
         });
+
+        self.show_notifications(ctx);
     }
 }
@@ -0,0 +1,57 @@
+use egui::Ui;
+
+impl super::Gui {
+    pub(super) fn cheats_menu(&mut self, ui: &mut Ui) {
+        let cheats = self
+            .core_handle
+            .run(|core| {
+                core.cheats()
+                    .iter()
+                    .map(|cheat| {
+                        (
+                            cheat.description.clone(),
+                            cheat.enabled,
+                            cheat.error().map(str::to_owned),
+                        )
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .unwrap();
+
+        if cheats.is_empty() {
+            ui.label("No cheats loaded");
+            return;
+        }
+
+        for (index, (description, mut enabled, error)) in cheats.into_iter().enumerate() {
+            let checkbox = ui.add_enabled(
+                error.is_none(),
+                egui::Checkbox::new(&mut enabled, &description),
+            );
+
+            if let Some(error) = error {
+                checkbox.on_disabled_hover_text(error);
+                continue;
+            }
+
+            if !checkbox.changed() {
+                continue;
+            }
+
+            let res = self
+                .core_handle
+                .run(move |core| core.set_cheat_enabled(index, enabled))
+                .unwrap();
+
+            match res {
+                Ok(()) => self.notifications.info(format!(
+                    "Cheat {}: {description}",
+                    if enabled { "enabled" } else { "disabled" }
+                )),
+                Err(err) => self
+                    .notifications
+                    .error(format!("Failed to toggle cheat: {err:#}")),
+            }
+        }
+    }
+}
@@ -0,0 +1,419 @@
+//! Resolving the core for a ROM without blocking the GUI.
+//!
+//! Archipelago patches are applied and cores are downloaded on background
+//! threads while a dialog shows their progress. If several cores run the ROM
+//! and none of them is installed, the user picks the one to download.
+
+use std::env::consts::{ARCH, OS};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use egui::{Align2, ProgressBar, Ui, Window};
+
+use ape::buildbot::{self, Progress, Source};
+use ape::cores::{self, CoreStore};
+use ape::{ap_patch, core_info, util};
+
+use super::file_dialog::{self, FileDialog, Target};
+use super::Options;
+use crate::Layers;
+
+const REPAINT_INTERVAL: Duration = Duration::from_millis(100);
+
+pub(super) enum Resolution {
+    /// `core` is `None` if the ROM runs with the current core.
+    Resolved {
+        rom: PathBuf,
+        core: Option<PathBuf>,
+    },
+    Cancelled,
+}
+
+pub(super) struct CoreResolver {
+    /// File name of the ROM as given, for the dialog's title.
+    name: String,
+    /// The core to use regardless of the ROM, e.g. from `--core`.
+    core: Option<PathBuf>,
+    /// From the startup config until the ROM's config is loaded.
+    cores_directory: PathBuf,
+    buildbot: Source,
+    /// Whether ROMs no known core runs are loaded with the current core.
+    keep_current_core: bool,
+    state: State,
+    file_dialog: Option<FileDialog>,
+}
+
+enum State {
+    /// Applying Archipelago patches and looking up cores in the background.
+    Preparing(Receiver<Result<Prepared>>),
+    /// Several cores run the ROM and none of them is installed.
+    Picking {
+        rom: PathBuf,
+        core_names: Vec<&'static str>,
+    },
+    Downloading {
+        rom: PathBuf,
+        core_name: &'static str,
+        progress: Arc<Progress>,
+        result_rx: Receiver<Result<PathBuf>>,
+    },
+    /// The buildbot has no cores for this platform.
+    Unsupported { rom: PathBuf },
+    Failed {
+        /// `None` if the ROM itself failed to load.
+        rom: Option<PathBuf>,
+        error: String,
+    },
+}
+
+struct Prepared {
+    rom: PathBuf,
+    core_names: Vec<&'static str>,
+    cores_directory: PathBuf,
+    buildbot: Source,
+}
+
+impl CoreResolver {
+    pub(super) fn new(
+        rom: PathBuf,
+        core: Option<PathBuf>,
+        database: Option<String>,
+        keep_current_core: bool,
+        options: &Options,
+        layers: &Layers,
+    ) -> Self {
+        let name = rom
+            .file_name()
+            .unwrap_or(rom.as_os_str())
+            .to_string_lossy()
+            .into_owned();
+        let layers = layers.clone();
+        let (prepared_tx, prepared_rx) = mpsc::channel();
+
+        thread::spawn(move || {
+            prepared_tx
+                .send(prepare(&rom, database.as_deref(), &layers))
+                .ok();
+        });
+
+        Self {
+            name,
+            core,
+            cores_directory: options.cores_directory.clone(),
+            buildbot: options.buildbot.clone(),
+            keep_current_core,
+            state: State::Preparing(prepared_rx),
+            file_dialog: None,
+        }
+    }
+
+    /// Shows the dialog and returns how the core was resolved, once it was.
+    pub(super) fn show(&mut self, ctx: &egui::Context) -> Option<Resolution> {
+        if let Some(resolution) = self.poll() {
+            return Some(resolution);
+        }
+
+        if let Some(resolution) = self.show_file_dialog(ctx) {
+            return Some(resolution);
+        }
+
+        if matches!(self.state, State::Preparing(_) | State::Downloading { .. }) {
+            ctx.request_repaint_after(REPAINT_INTERVAL);
+        }
+
+        let mut resolution = None;
+
+        Window::new(format!("Loading {}", self.name))
+            .anchor(Align2::CENTER_CENTER, [0., 0.])
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| resolution = self.show_state(ui));
+
+        resolution
+    }
+
+    /// Advances the background work, if it is done.
+    fn poll(&mut self) -> Option<Resolution> {
+        match &self.state {
+            State::Preparing(prepared_rx) => match try_recv(prepared_rx)? {
+                Ok(prepared) => return self.choose(prepared),
+                Err(err) => {
+                    self.state = State::Failed {
+                        rom: None,
+                        error: format!("{err:#}"),
+                    }
+                }
+            },
+            State::Downloading { rom, result_rx, .. } => match try_recv(result_rx)? {
+                Ok(core) => {
+                    return Some(Resolution::Resolved {
+                        rom: rom.clone(),
+                        core: Some(core),
+                    })
+                }
+                Err(err) => {
+                    self.state = State::Failed {
+                        rom: Some(rom.clone()),
+                        error: format!("{err:#}"),
+                    }
+                }
+            },
+            State::Picking { .. } | State::Unsupported { .. } | State::Failed { .. } => {}
+        }
+
+        None
+    }
+
+    /// Uses an installed core if there is one, else downloads or lets the user pick one.
+    fn choose(&mut self, prepared: Prepared) -> Option<Resolution> {
+        let Prepared {
+            rom,
+            core_names,
+            cores_directory,
+            buildbot,
+        } = prepared;
+
+        self.cores_directory = cores_directory;
+        self.buildbot = buildbot;
+
+        if let Some(core) = self.core.take() {
+            return Some(Resolution::Resolved {
+                rom,
+                core: Some(core),
+            });
+        }
+
+        let installed = core_names
+            .iter()
+            .map(|core_name| self.cores_directory.join(cores::library_name(core_name)))
+            .find(|library_path| library_path.exists());
+
+        if let Some(core) = installed {
+            return Some(Resolution::Resolved {
+                rom,
+                core: Some(core),
+            });
+        }
+
+        match core_names.as_slice() {
+            [] if self.keep_current_core => {
+                return Some(Resolution::Resolved { rom, core: None });
+            }
+            [] => {
+                self.state = State::Failed {
+                    error: format!("No known core runs {rom:?}."),
+                    rom: Some(rom),
+                }
+            }
+            [core_name] => self.download(rom, core_name),
+            _ => self.state = State::Picking { rom, core_names },
+        }
+
+        None
+    }
+
+    fn download(&mut self, rom: PathBuf, core_name: &'static str) {
+        if !buildbot::is_platform_supported(&self.buildbot) {
+            self.state = State::Unsupported { rom };
+            return;
+        }
+
+        let progress = Arc::<Progress>::default();
+        let (result_tx, result_rx) = mpsc::channel();
+        let cores_directory = self.cores_directory.clone();
+        let source = self.buildbot.clone();
+
+        thread::spawn({
+            let progress = progress.clone();
+
+            move || {
+                let res = CoreStore::open(cores_directory, source).and_then(|mut store| {
+                    store.install(core_name, &progress)?;
+
+                    Ok(store.library_path(core_name))
+                });
+
+                result_tx.send(res).ok();
+            }
+        });
+
+        self.state = State::Downloading {
+            rom,
+            core_name,
+            progress,
+            result_rx,
+        };
+    }
+
+    fn show_state(&mut self, ui: &mut Ui) -> Option<Resolution> {
+        let mut resolution = None;
+        let mut picked = None;
+        let mut choose_core = false;
+
+        match &self.state {
+            State::Preparing(_) => {
+                ui.horizontal(|ui| {
+                    ui.spinner();
+                    ui.label("Preparing ROM…");
+                });
+            }
+            State::Picking { core_names, .. } => {
+                ui.label("Several cores run this ROM. Pick one to download:");
+
+                for &core_name in core_names {
+                    if ui.button(display_name(core_name)).clicked() {
+                        picked = Some(core_name);
+                    }
+                }
+
+                ui.separator();
+            }
+            State::Downloading {
+                core_name,
+                progress,
+                ..
+            } => {
+                ui.label(format!("Downloading {}…", display_name(core_name)));
+
+                let downloaded = mebibytes(progress.downloaded());
+                let progress_bar = match progress.total() {
+                    Some(total) => ProgressBar::new(progress.downloaded() as f32 / total as f32)
+                        .text(format!("{downloaded:.1} / {:.1} MiB", mebibytes(total))),
+                    None => ProgressBar::new(0.)
+                        .animate(true)
+                        .text(format!("{downloaded:.1} MiB")),
+                };
+
+                ui.add(progress_bar);
+            }
+            State::Unsupported { .. } => {
+                ui.colored_label(
+                    ui.visuals().error_fg_color,
+                    format!("The buildbot has no cores for {OS} on {ARCH}."),
+                );
+                ui.label("Choose a core library instead, or pass one with `--core`.");
+            }
+            State::Failed { rom, error } => {
+                ui.colored_label(ui.visuals().error_fg_color, error);
+
+                if rom.is_some() {
+                    ui.label("Choose a core library instead.");
+                }
+            }
+        }
+
+        ui.horizontal(|ui| {
+            if self.rom().is_some() && ui.button("Choose Core…").clicked() {
+                choose_core = true;
+            }
+
+            if ui.button("Cancel").clicked() {
+                if let State::Downloading { progress, .. } = &self.state {
+                    progress.cancel();
+                }
+
+                resolution = Some(Resolution::Cancelled);
+            }
+        });
+
+        if let Some(core_name) = picked {
+            if let State::Picking { rom, .. } = &self.state {
+                self.download(rom.clone(), core_name);
+            }
+        }
+
+        if choose_core {
+            let directory = if self.cores_directory.is_dir() {
+                self.cores_directory.clone()
+            } else {
+                std::env::current_dir().unwrap_or_default()
+            };
+
+            self.file_dialog = Some(FileDialog::new(Target::Core, directory));
+        }
+
+        resolution
+    }
+
+    fn show_file_dialog(&mut self, ctx: &egui::Context) -> Option<Resolution> {
+        let outcome = self.file_dialog.as_mut()?.show(ctx)?;
+
+        self.file_dialog = None;
+
+        match outcome {
+            file_dialog::Outcome::Picked(core) => Some(Resolution::Resolved {
+                rom: self.rom()?.clone(),
+                core: Some(core),
+            }),
+            file_dialog::Outcome::Cancelled => None,
+        }
+    }
+
+    /// The ROM to load, once it is prepared and while no core is being downloaded.
+    fn rom(&self) -> Option<&PathBuf> {
+        match &self.state {
+            State::Picking { rom, .. } | State::Unsupported { rom } => Some(rom),
+            State::Failed { rom, .. } => rom.as_ref(),
+            State::Preparing(_) | State::Downloading { .. } => None,
+        }
+    }
+}
+
+/// Loads the config of `rom` like that of `--rom` at startup, with the per-core
+/// file of the first core that runs it, and applies Archipelago patches.
+fn prepare(rom: &Path, database: Option<&str>, layers: &Layers) -> Result<Prepared> {
+    let settings = layers
+        .load(core_names(rom, database).first().copied(), Some(rom))?
+        .settings;
+    let rom = ap_patch::resolve_rom(rom, settings.base_rom_dir.as_deref())?;
+
+    Ok(Prepared {
+        core_names: core_names(&rom, database),
+        rom,
+        cores_directory: settings.cores_dir,
+        buildbot: settings.buildbot_url,
+    })
+}
+
+/// The cores that run the games of `database`, else the cores that run `rom`
+/// by its extension.
+fn core_names(rom: &Path, database: Option<&str>) -> Vec<&'static str> {
+    let database_core_names = database
+        .map(|database| {
+            core_info::database()
+                .for_database(database)
+                .map(|core| core.name.as_str())
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+
+    if !database_core_names.is_empty() {
+        return database_core_names;
+    }
+
+    // ROMs without a known core can still run with a picked one
+    util::core_names_for_rom(rom).unwrap_or_default()
+}
+
+/// The result of the background thread, if it is done.
+fn try_recv<T>(rx: &Receiver<Result<T>>) -> Option<Result<T>> {
+    match rx.try_recv() {
+        Ok(res) => Some(res),
+        Err(TryRecvError::Empty) => None,
+        Err(TryRecvError::Disconnected) => Some(Err(anyhow!("background thread panicked"))),
+    }
+}
+
+fn display_name(core_name: &str) -> &str {
+    core_info::database()
+        .get(core_name)
+        .map_or(core_name, |core| core.display_name.as_str())
+}
+
+fn mebibytes(bytes: u64) -> f64 {
+    bytes as f64 / (1024. * 1024.)
+}
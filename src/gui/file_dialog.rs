@@ -0,0 +1,164 @@
+//! A minimal file browser for picking ROMs and cores.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use egui::{ScrollArea, Window};
+
+/// File extensions of libretro core libraries.
+const CORE_EXTENSIONS: &[&str] = &["so", "dll", "dylib"];
+
+/// Whether `path` looks like a libretro core library rather than a ROM.
+pub(super) fn is_core_library(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| CORE_EXTENSIONS.contains(&extension))
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(super) enum Target {
+    Rom,
+    Core,
+}
+
+pub(super) struct FileDialog {
+    target: Target,
+    directory: PathBuf,
+    entries: Vec<Entry>,
+    path: String,
+    error: Option<String>,
+}
+
+struct Entry {
+    path: PathBuf,
+    is_dir: bool,
+}
+
+impl FileDialog {
+    pub(super) fn new(target: Target, directory: PathBuf) -> Self {
+        let mut dialog = Self {
+            target,
+            directory: PathBuf::new(),
+            entries: Vec::new(),
+            path: String::new(),
+            error: None,
+        };
+
+        dialog.change_directory(directory);
+        dialog
+    }
+
+    pub(super) fn target(&self) -> Target {
+        self.target
+    }
+
+    /// Shows the dialog and returns what it ended with, if it did.
+    pub(super) fn show(&mut self, ctx: &egui::Context) -> Option<Outcome> {
+        let title = match self.target {
+            Target::Rom => "Load ROM",
+            Target::Core => "Load Core",
+        };
+        let mut open = true;
+        let mut outcome = None;
+
+        Window::new(title)
+            .open(&mut open)
+            .collapsible(false)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    if ui.button("⬆").on_hover_text("Parent directory").clicked() {
+                        if let Some(parent) = self.directory.parent() {
+                            self.change_directory(parent.to_owned());
+                        }
+                    }
+
+                    ui.label(self.directory.display().to_string());
+                });
+
+                ui.separator();
+
+                let mut selected = None;
+
+                ScrollArea::vertical().max_height(300.).show(ui, |ui| {
+                    for entry in &self.entries {
+                        let name = entry.path.file_name().unwrap_or_default().to_string_lossy();
+                        let label = if entry.is_dir {
+                            format!("📁 {name}")
+                        } else {
+                            name.into_owned()
+                        };
+
+                        if ui.selectable_label(false, label).clicked() {
+                            selected = Some((entry.path.clone(), entry.is_dir));
+                        }
+                    }
+                });
+
+                match selected {
+                    Some((path, true)) => self.change_directory(path),
+                    Some((path, false)) => self.path = path.display().to_string(),
+                    None => {}
+                }
+
+                ui.separator();
+
+                ui.horizontal(|ui| {
+                    ui.text_edit_singleline(&mut self.path);
+
+                    if ui.button("Open").clicked() {
+                        let path = PathBuf::from(&self.path);
+
+                        if path.is_dir() {
+                            self.change_directory(path);
+                        } else if path.is_file() {
+                            outcome = Some(Outcome::Picked(path));
+                        } else {
+                            self.error = Some(format!("{path:?} does not exist"));
+                        }
+                    }
+                });
+
+                if let Some(error) = &self.error {
+                    ui.colored_label(ui.visuals().error_fg_color, error);
+                }
+            });
+
+        if !open {
+            return Some(Outcome::Cancelled);
+        }
+
+        outcome
+    }
+
+    fn change_directory(&mut self, directory: PathBuf) {
+        let read_dir = match fs::read_dir(&directory) {
+            Ok(read_dir) => read_dir,
+            Err(err) => {
+                self.error = Some(format!("Failed to read {directory:?}: {err}"));
+                return;
+            }
+        };
+
+        let mut entries = read_dir
+            .filter_map(|entry| entry.ok())
+            .map(|entry| Entry {
+                is_dir: entry.path().is_dir(),
+                path: entry.path(),
+            })
+            .filter(|entry| {
+                entry.is_dir || self.target != Target::Core || is_core_library(&entry.path)
+            })
+            .collect::<Vec<_>>();
+
+        entries.sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then_with(|| a.path.cmp(&b.path)));
+
+        self.entries = entries;
+        self.directory = directory;
+        self.error = None;
+    }
+}
+
+pub(super) enum Outcome {
+    Picked(PathBuf),
+    Cancelled,
+}
@@ -0,0 +1,108 @@
+//! A window checking the firmware the running core expects.
+
+use std::path::PathBuf;
+
+use egui::{Grid, Window};
+
+use ape::{core_info, firmware};
+
+pub(super) struct FirmwareWindow {
+    core_name: String,
+    system_directory: PathBuf,
+    checks: Result<Vec<firmware::Check>, String>,
+}
+
+impl super::Gui {
+    pub(super) fn open_firmware_window(&mut self) {
+        self.firmware_window = Some(self.check_firmware());
+    }
+
+    fn check_firmware(&self) -> FirmwareWindow {
+        let library_name = self
+            .core_handle
+            .run(|core| core.get_system_info().library_name.into_owned())
+            .unwrap();
+        let system_directory = self.settings().system_dir;
+
+        match core_info::database().by_library_name(&library_name) {
+            Some(core) => FirmwareWindow {
+                core_name: core.display_name.clone(),
+                checks: firmware::check_core(core, &system_directory)
+                    .map_err(|err| format!("{err:#}")),
+                system_directory,
+            },
+            None => FirmwareWindow {
+                checks: Err(format!("{library_name} is not in the core database")),
+                core_name: library_name,
+                system_directory,
+            },
+        }
+    }
+
+    pub(super) fn show_firmware_window(&mut self, ctx: &egui::Context) {
+        let Some(firmware_window) = &self.firmware_window else {
+            return;
+        };
+
+        let mut open = true;
+        let mut check_again = false;
+
+        Window::new("Firmware")
+            .open(&mut open)
+            .collapsible(false)
+            .show(ctx, |ui| {
+                ui.label(format!(
+                    "{} in {}",
+                    firmware_window.core_name,
+                    firmware_window.system_directory.display()
+                ));
+
+                ui.separator();
+
+                match &firmware_window.checks {
+                    Ok(checks) if checks.is_empty() => {
+                        ui.label("The core needs no firmware");
+                    }
+                    Ok(checks) => {
+                        Grid::new("firmware").striped(true).show(ui, |ui| {
+                            for check in checks {
+                                ui.label(&check.firmware.path)
+                                    .on_hover_text(&check.firmware.description);
+                                ui.label(if check.firmware.optional {
+                                    "optional"
+                                } else {
+                                    "required"
+                                });
+
+                                if check.is_problem() {
+                                    ui.colored_label(
+                                        ui.visuals().error_fg_color,
+                                        check.status.to_string(),
+                                    );
+                                } else {
+                                    ui.label(check.status.to_string());
+                                }
+
+                                ui.end_row();
+                            }
+                        });
+                    }
+                    Err(err) => {
+                        ui.colored_label(ui.visuals().error_fg_color, err);
+                    }
+                }
+
+                ui.separator();
+
+                if ui.button("Check Again").clicked() {
+                    check_again = true;
+                }
+            });
+
+        if !open {
+            self.firmware_window = None;
+        } else if check_again {
+            self.open_firmware_window();
+        }
+    }
+}
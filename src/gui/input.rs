@@ -4,20 +4,51 @@ use egui::{Key, Modifiers, ViewportCommand};
 
 impl super::Gui {
     pub(super) fn handle_input(&mut self, ctx: &egui::Context) {
-        ctx.input_mut(|input| {
+        // Viewport commands lock the context, which is locked while handling input
+        let request_screenshot = ctx.input_mut(|input| {
             if input.consume_key(Modifiers::SHIFT, Key::F1) {
-                let save_state = self.core_handle.run(|core| core.state()).unwrap().unwrap();
-                self.save_state = Some(save_state);
+                self.save_state();
             }
 
             if input.consume_key(Modifiers::NONE, Key::F1) {
-                if let Some(save_state) = &self.save_state {
-                    let save_state = save_state.clone();
-                    self.core_handle
-                        .run(move |core| core.restore_state(&save_state))
-                        .unwrap()
-                        .unwrap();
-                }
+                self.load_state();
+            }
+
+            if input.consume_key(Modifiers::SHIFT, Key::F2) {
+                self.hard_reset();
+            }
+
+            if input.consume_key(Modifiers::NONE, Key::F2) {
+                self.reset();
+            }
+
+            let request_screenshot = input.consume_key(Modifiers::SHIFT, Key::F12);
+
+            if input.consume_key(Modifiers::NONE, Key::F12) {
+                self.take_screenshot();
+            }
+
+            if input.consume_key(Modifiers::NONE, Key::F10) {
+                self.save_replay();
+            }
+
+            if input.consume_key(Modifiers::NONE, Key::F9) {
+                self.toggle_recording();
+            }
+
+            if input.consume_key(Modifiers::NONE, Key::P) {
+                let paused = self
+                    .core_handle
+                    .run(|core| {
+                        let paused = !core.is_paused();
+
+                        core.set_paused(paused);
+                        paused
+                    })
+                    .unwrap();
+
+                self.notifications
+                    .info(if paused { "Paused" } else { "Resumed" });
             }
 
             if input.consume_key(Modifiers::NONE, Key::Escape) {
@@ -33,6 +64,12 @@ impl super::Gui {
                     ctx.send_viewport_cmd(cmd);
                 });
             }
+
+            request_screenshot
         });
+
+        if request_screenshot {
+            self.request_displayed_screenshot(ctx);
+        }
     }
 }
@@ -0,0 +1,272 @@
+//! The game library, for picking the game to play.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use anyhow::Result;
+use egui::{Grid, ScrollArea, TextEdit, Window};
+
+use ape::library::dat::DatIndex;
+use ape::library::{Game, Library};
+
+use super::Options;
+
+const REPAINT_INTERVAL: Duration = Duration::from_millis(100);
+
+pub(super) enum Outcome {
+    Play {
+        rom: PathBuf,
+        /// The DAT the game is in, selecting the cores that run it.
+        database: Option<String>,
+    },
+    Closed,
+}
+
+pub(super) struct LibraryView {
+    library_path: PathBuf,
+    library_dirs: Vec<PathBuf>,
+    dat_dir: Option<PathBuf>,
+    library: Library,
+    /// When the library file was last changed as of loading `library`, to
+    /// reload it when plays are recorded while the view is open.
+    modified: Option<SystemTime>,
+    filter: String,
+    scan_rx: Option<Receiver<Result<Scanned>>>,
+    status: Option<Result<String, String>>,
+}
+
+struct Scanned {
+    library: Library,
+    num_roms: usize,
+    num_dat_entries: usize,
+}
+
+impl LibraryView {
+    pub(super) fn new(options: &Options) -> Self {
+        let mut view = Self {
+            library_path: options.library_path.clone(),
+            library_dirs: options.library_dirs.clone(),
+            dat_dir: options.dat_dir.clone(),
+            library: Library::default(),
+            modified: None,
+            filter: String::new(),
+            scan_rx: None,
+            status: None,
+        };
+
+        view.reload();
+        view
+    }
+
+    fn reload(&mut self) {
+        self.modified = modified(&self.library_path);
+
+        match Library::load(&self.library_path) {
+            Ok(library) => self.library = library,
+            Err(err) => self.status = Some(Err(format!("{err:#}"))),
+        }
+    }
+
+    /// Reloads the library if the file changed, e.g. when a play was recorded.
+    fn poll_file(&mut self) {
+        if self.scan_rx.is_none() && modified(&self.library_path) != self.modified {
+            self.reload();
+        }
+    }
+
+    fn start_scan(&mut self) {
+        let (scanned_tx, scanned_rx) = mpsc::channel();
+        let library_path = self.library_path.clone();
+        let library_dirs = self.library_dirs.clone();
+        let dat_dir = self.dat_dir.clone();
+
+        thread::spawn(move || {
+            scanned_tx
+                .send(scan(&library_path, &library_dirs, dat_dir.as_deref()))
+                .ok();
+        });
+
+        self.scan_rx = Some(scanned_rx);
+        self.status = None;
+    }
+
+    fn poll_scan(&mut self) {
+        let Some(scan_rx) = &self.scan_rx else {
+            return;
+        };
+
+        let res = match scan_rx.try_recv() {
+            Ok(res) => res,
+            Err(TryRecvError::Empty) => return,
+            Err(TryRecvError::Disconnected) => Err(anyhow::anyhow!("scan panicked")),
+        };
+
+        self.scan_rx = None;
+        self.status = Some(match res {
+            Ok(scanned) => {
+                self.library = scanned.library;
+                self.modified = modified(&self.library_path);
+
+                Ok(format!(
+                    "Found {} ROMs, {} known dumps in DATs",
+                    scanned.num_roms, scanned.num_dat_entries
+                ))
+            }
+            Err(err) => Err(format!("Scan failed: {err:#}")),
+        });
+    }
+
+    /// Shows the library and returns what the user did, if anything.
+    ///
+    /// The window can only be closed if `closable`.
+    pub(super) fn show(&mut self, ctx: &egui::Context, closable: bool) -> Option<Outcome> {
+        self.poll_scan();
+        self.poll_file();
+
+        if self.scan_rx.is_some() {
+            ctx.request_repaint_after(REPAINT_INTERVAL);
+        }
+
+        let mut open = true;
+        let mut outcome = None;
+        let mut window = Window::new("Library")
+            .collapsible(false)
+            .default_size([640., 480.]);
+
+        if closable {
+            window = window.open(&mut open);
+        }
+
+        window.show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.add(TextEdit::singleline(&mut self.filter).hint_text("Filter"));
+
+                let can_scan = self.scan_rx.is_none() && !self.library_dirs.is_empty();
+
+                if ui
+                    .add_enabled(can_scan, egui::Button::new("Scan"))
+                    .on_disabled_hover_text("Add library folders with --library-dir")
+                    .clicked()
+                {
+                    self.start_scan();
+                }
+
+                if self.scan_rx.is_some() {
+                    ui.spinner();
+                    ui.label("Scanning…");
+                }
+            });
+
+            match &self.status {
+                Some(Ok(status)) => {
+                    ui.label(status);
+                }
+                Some(Err(error)) => {
+                    ui.colored_label(ui.visuals().error_fg_color, error);
+                }
+                None => {}
+            }
+
+            ui.separator();
+
+            let filter = self.filter.to_lowercase();
+            let mut games = self
+                .library
+                .games()
+                .filter(|(path, game)| game.title(path).to_lowercase().contains(&filter))
+                .collect::<Vec<_>>();
+
+            games.sort_by_cached_key(|(path, game)| game.title(path).to_lowercase());
+
+            if games.is_empty() {
+                ui.label("No games. Scan the library folders to add them.");
+                return;
+            }
+
+            ScrollArea::vertical().show(ui, |ui| {
+                Grid::new("library").striped(true).show(ui, |ui| {
+                    ui.strong("");
+                    ui.strong("Title");
+                    ui.strong("Region");
+                    ui.strong("System");
+                    ui.strong("Last played");
+                    ui.strong("Play time");
+                    ui.end_row();
+
+                    for (path, game) in games {
+                        if ui.button("▶").on_hover_text("Play").clicked() {
+                            outcome = Some(Outcome::Play {
+                                rom: path.to_owned(),
+                                database: game.database.clone(),
+                            });
+                        }
+
+                        ui.label(game.title(path))
+                            .on_hover_text(path.display().to_string());
+                        ui.label(game.region().unwrap_or("-"));
+                        ui.label(game.database.as_deref().unwrap_or("-"));
+                        ui.label(last_played(game));
+                        ui.label(play_time(game));
+                        ui.end_row();
+                    }
+                });
+            });
+        });
+
+        if !open {
+            return Some(Outcome::Closed);
+        }
+
+        outcome
+    }
+}
+
+fn scan(library_path: &Path, library_dirs: &[PathBuf], dat_dir: Option<&Path>) -> Result<Scanned> {
+    let dats = match dat_dir {
+        Some(dat_dir) => DatIndex::load_dir(dat_dir)?,
+        None => DatIndex::default(),
+    };
+    let (library, num_roms) = Library::scan_file(library_path, library_dirs, &dats)?;
+
+    Ok(Scanned {
+        library,
+        num_roms,
+        num_dat_entries: dats.len(),
+    })
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// Formats timestamps like `2024-05-01_18-30-12-345` as `2024-05-01 18:30`.
+fn last_played(game: &Game) -> String {
+    let Some(last_played) = &game.last_played else {
+        return "-".into();
+    };
+    let Some((date, time)) = last_played.split_once('_') else {
+        return last_played.clone();
+    };
+    let mut time = time.split('-');
+
+    match (time.next(), time.next()) {
+        (Some(hours), Some(minutes)) => format!("{date} {hours}:{minutes}"),
+        _ => date.to_owned(),
+    }
+}
+
+fn play_time(game: &Game) -> String {
+    let minutes = game.play_time_secs / 60;
+
+    match (minutes / 60, minutes % 60) {
+        (0, 0) if game.play_time_secs == 0 => "-".into(),
+        (0, 0) => "<1m".into(),
+        (0, minutes) => format!("{minutes}m"),
+        (hours, minutes) => format!("{hours}h {minutes}m"),
+    }
+}
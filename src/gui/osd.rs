@@ -0,0 +1,38 @@
+use egui::{Align2, Area, Color32, Frame, Id, RichText};
+
+use ape::osd::Level;
+
+impl super::Gui {
+    pub(super) fn show_notifications(&self, ctx: &egui::Context) {
+        let notifications = self.notifications.visible();
+
+        if notifications.is_empty() {
+            return;
+        }
+
+        Area::new(Id::new("osd"))
+            .anchor(Align2::LEFT_BOTTOM, [8., -8.])
+            .interactable(false)
+            .show(ctx, |ui| {
+                for notification in notifications {
+                    // fade out during the last quarter of the duration
+                    let alpha = (notification.remaining * 4.).min(1.);
+                    let color = match notification.level {
+                        Level::Debug => Color32::GRAY,
+                        Level::Info => Color32::WHITE,
+                        Level::Warn => Color32::YELLOW,
+                        Level::Error => Color32::LIGHT_RED,
+                    };
+                    let text = RichText::new(notification.text)
+                        .size(16.)
+                        .color(color.gamma_multiply(alpha));
+
+                    Frame::none()
+                        .fill(Color32::from_black_alpha((180. * alpha) as u8))
+                        .rounding(4.)
+                        .inner_margin(6.)
+                        .show(ui, |ui| ui.label(text));
+                }
+            });
+    }
+}
@@ -0,0 +1,103 @@
+use std::path::PathBuf;
+use std::thread;
+
+use anyhow::Result;
+use egui::{Event, ViewportCommand};
+
+use ape::{replay, screenshot};
+
+impl super::Gui {
+    /// Saves the core's last frame at its native resolution.
+    pub(super) fn take_screenshot(&self) {
+        let (png, rom_path) = self
+            .core_handle
+            .run(|core| (core.screenshot(), core.rom_path()))
+            .unwrap();
+        let screenshot_directory = self.settings().screenshot_dir;
+        let res =
+            png.and_then(|png| screenshot::save(&screenshot_directory, rom_path.as_deref(), &png));
+
+        self.notify_screenshot(res);
+    }
+
+    /// Asks the window for a screenshot of what is displayed, scaled and filtered.
+    ///
+    /// The screenshot arrives as an event in a later frame.
+    pub(super) fn request_displayed_screenshot(&self, ctx: &egui::Context) {
+        ctx.send_viewport_cmd(ViewportCommand::Screenshot);
+    }
+
+    pub(super) fn handle_screenshot_events(&self, ctx: &egui::Context) {
+        let images = ctx.input(|input| {
+            input
+                .raw
+                .events
+                .iter()
+                .filter_map(|event| match event {
+                    Event::Screenshot { image, .. } => Some(image.clone()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        });
+
+        if images.is_empty() {
+            return;
+        }
+
+        let rom_path = self.core_handle.run(|core| core.rom_path()).unwrap();
+        let screenshot_directory = self.settings().screenshot_dir;
+
+        for image in images {
+            let pixels = image
+                .pixels
+                .iter()
+                .flat_map(|pixel| pixel.to_srgba_unmultiplied())
+                .collect::<Vec<_>>();
+            let [width, height] = image.size;
+            let res = screenshot::encode_png(width, height, png::ColorType::Rgba, &pixels)
+                .and_then(|png| screenshot::save(&screenshot_directory, rom_path.as_deref(), &png));
+
+            self.notify_screenshot(res);
+        }
+    }
+
+    /// Saves the instant replay buffer as an animated PNG.
+    ///
+    /// Encoding happens in the background, emulation keeps running meanwhile.
+    pub(super) fn save_replay(&self) {
+        let (replay, rom_path) = self
+            .core_handle
+            .run(|core| (core.replay(), core.rom_path()))
+            .unwrap();
+
+        let Some(replay) = replay else {
+            self.notifications.error("Instant replay is disabled");
+            return;
+        };
+
+        let screenshot_directory = self.settings().screenshot_dir;
+        let notifications = self.notifications.clone();
+
+        thread::spawn(move || {
+            let res = replay
+                .save(None)
+                .and_then(|png| replay::save_to(&screenshot_directory, rom_path.as_deref(), &png));
+
+            match res {
+                Ok(path) => notifications.info(format!("Replay saved to {}", path.display())),
+                Err(err) => notifications.error(format!("Failed to save replay: {err:#}")),
+            }
+        });
+    }
+
+    fn notify_screenshot(&self, res: Result<PathBuf>) {
+        match res {
+            Ok(path) => self
+                .notifications
+                .info(format!("Screenshot saved to {}", path.display())),
+            Err(err) => self
+                .notifications
+                .error(format!("Failed to take screenshot: {err:#}")),
+        }
+    }
+}
@@ -0,0 +1,89 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+
+use ape::core::{self, Core};
+use ape::osd::Notifications;
+
+/// Frame rate used for pacing if the core doesn't report one.
+const DEFAULT_FPS: f64 = 60.;
+
+pub struct Options {
+    /// Exit after running this many frames.
+    pub frames: Option<u64>,
+    /// Exit once the core requests a shutdown.
+    /// Otherwise emulation is paused and the remotes keep serving requests.
+    pub until_exit: bool,
+    pub audio: bool,
+}
+
+/// Runs the core without a window until it is done according to `options`.
+pub fn run(
+    content: super::Content,
+    run_options: super::RunOptions,
+    options: Options,
+) -> Result<()> {
+    let super::Running {
+        frame_rx, thread, ..
+    } = super::run(
+        content,
+        run_options,
+        Notifications::new(),
+        super::Frontend::Headless(options),
+    )?;
+
+    // Frames aren't displayed, drain them until the core thread exits
+    for _frame in frame_rx {}
+
+    thread.join().map_err(|_| anyhow!("core thread panicked"))?
+}
+
+/// Runs frames at the core's frame rate, serving core handles in between.
+pub(crate) fn run_frames(
+    core: &mut Core,
+    core_host: &core::Host,
+    options: &Options,
+    mut after_frame: impl FnMut(&mut Core),
+) {
+    let fps = core.get_system_av_info().timing.fps;
+    let fps = if fps > 0. { fps } else { DEFAULT_FPS };
+    let frame_duration = Duration::from_secs_f64(1. / fps);
+    let mut next_frame = Instant::now();
+    let mut frames_run = 0;
+
+    loop {
+        core_host.run_pending(core);
+
+        if core.is_shutdown_requested() {
+            if options.until_exit {
+                break;
+            }
+
+            if !core.is_paused() {
+                eprintln!("Pausing emulation, remote interfaces keep running");
+                core.set_paused(true);
+            }
+        }
+
+        if options.frames.is_some_and(|frames| frames_run >= frames) {
+            eprintln!("Ran {frames_run} frames, exiting");
+            break;
+        }
+
+        if !core.is_paused() {
+            core.run();
+            frames_run += 1;
+        }
+
+        after_frame(core);
+
+        next_frame += frame_duration;
+
+        match next_frame.checked_duration_since(Instant::now()) {
+            Some(delay) => thread::sleep(delay),
+            // don't try to catch up after falling behind
+            None => next_frame = Instant::now(),
+        }
+    }
+}
@@ -0,0 +1,33 @@
+//! A libretro frontend host.
+//!
+//! Load a core with [`Session::new`] (or [`Core::new`]), step it frame by frame
+//! and receive its video, audio and input requests through [`Callbacks`].
+
+pub mod ap_patch;
+pub mod ap_remote;
+pub mod archive;
+pub mod buildbot;
+pub mod cheat;
+pub mod config;
+pub mod core;
+pub mod core_info;
+pub mod cores;
+mod environment;
+pub mod firmware;
+pub mod input;
+pub mod library;
+pub mod memory_domain;
+pub mod osd;
+pub mod patch;
+pub mod recording;
+pub mod remote;
+pub mod replay;
+pub mod screenshot;
+mod session;
+pub mod system;
+pub mod util;
+pub mod video;
+
+pub use crate::core::{Callbacks, Config, Core, Handle, MemoryMap};
+pub use crate::session::Session;
+pub use crate::video::Frame;
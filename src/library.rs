@@ -0,0 +1,396 @@
+//! The game library: the ROMs in the library folders, identified by DAT files.
+//!
+//! Scanning hashes each ROM once and caches the hash in the library file,
+//! together with when the game was last played and for how long.
+
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::io;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+
+use self::dat::DatIndex;
+use crate::archive;
+use crate::config;
+use crate::core_info;
+use crate::util;
+
+pub mod dat;
+
+const ARCHIVE_EXTENSIONS: &[&str] = &["zip", "7z"];
+
+/// Held while loading, modifying and saving the library file, so that threads
+/// don't overwrite each other's changes.
+static FILE_LOCK: Mutex<()> = Mutex::new(());
+
+pub fn default_path() -> PathBuf {
+    config::data_path("library.json")
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Game {
+    /// Lowercase hex SHA1 of the ROM, `None` until it was scanned.
+    pub sha1: Option<String>,
+    /// Size of the file when it was hashed.
+    #[serde(default)]
+    size: u64,
+    /// Modification time of the file when it was hashed, in seconds since the Unix epoch.
+    #[serde(default)]
+    modified: u64,
+    /// Name of the game in its DAT, e.g. `Tetris (World) (Rev 1)`.
+    pub name: Option<String>,
+    /// Name of the DAT the game is in, e.g. `Nintendo - Game Boy`.
+    pub database: Option<String>,
+    pub last_played: Option<String>,
+    #[serde(default)]
+    pub play_time_secs: u64,
+}
+
+impl Game {
+    /// The title from the DAT, else the file name.
+    pub fn title<'a>(&'a self, path: &'a Path) -> &'a str {
+        match &self.name {
+            Some(name) => dat::title(name),
+            None => path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .unwrap_or_default(),
+        }
+    }
+
+    pub fn region(&self) -> Option<&str> {
+        self.name.as_deref().and_then(dat::region)
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct Library {
+    games: BTreeMap<PathBuf, Game>,
+}
+
+impl Library {
+    /// Loads the library file at `path`, an empty library if there is none yet.
+    pub fn load(path: &Path) -> Result<Self> {
+        match fs::read(path) {
+            Ok(library) => serde_json::from_slice(&library)
+                .with_context(|| format!("invalid library file {path:?}")),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err).with_context(|| format!("failed to read {path:?}")),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let library = serde_json::to_vec_pretty(self)?;
+
+        util::write_atomically(path, &library)
+    }
+
+    /// Loads the library file at `path`, lets `f` modify it and saves it, and
+    /// returns the saved library.
+    ///
+    /// Other updates wait until this one is saved.
+    pub fn update(path: &Path, f: impl FnOnce(&mut Self)) -> Result<Self> {
+        let _lock = FILE_LOCK.lock();
+        let mut library = Self::load(path)?;
+
+        f(&mut library);
+        library.save(path)?;
+
+        Ok(library)
+    }
+
+    /// Scans `dirs` like [`Library::scan`] and saves the result to the library
+    /// file at `path`. Returns the saved library and the number of ROMs found.
+    ///
+    /// Hashing takes a while, so a snapshot is scanned without holding up other
+    /// updates, and plays recorded meanwhile are merged into the result.
+    pub fn scan_file(path: &Path, dirs: &[PathBuf], dats: &DatIndex) -> Result<(Self, usize)> {
+        let mut scanned = Self::load(path)?;
+        let snapshot = scanned.games.keys().cloned().collect::<HashSet<_>>();
+        let num_roms = scanned.scan(dirs, dats)?;
+
+        let library = Self::update(path, |latest| {
+            for (rom, game) in mem::take(&mut latest.games) {
+                match scanned.games.get_mut(&rom) {
+                    Some(scanned) => {
+                        scanned.last_played = game.last_played;
+                        scanned.play_time_secs = game.play_time_secs;
+                    }
+                    // added while scanning rather than removed by the scan
+                    None if !snapshot.contains(&rom) => {
+                        scanned.games.insert(rom, game);
+                    }
+                    None => {}
+                }
+            }
+
+            latest.games = mem::take(&mut scanned.games);
+        })?;
+
+        Ok((library, num_roms))
+    }
+
+    pub fn games(&self) -> impl Iterator<Item = (&Path, &Game)> {
+        self.games.iter().map(|(path, game)| (path.as_path(), game))
+    }
+
+    pub fn get(&self, rom: &Path) -> Option<&Game> {
+        self.games.get(&canonicalize(rom))
+    }
+
+    /// Adds the ROMs in `dirs` and their subdirectories, and removes games from
+    /// them that are gone. Games are identified by the DATs in `dats`.
+    ///
+    /// Returns the number of ROMs found.
+    pub fn scan(&mut self, dirs: &[PathBuf], dats: &DatIndex) -> Result<usize> {
+        let extensions = rom_extensions();
+        let mut roms = Vec::new();
+        let mut visited = HashSet::new();
+
+        for dir in dirs {
+            find_roms(dir, &extensions, &mut visited, &mut roms)
+                .with_context(|| format!("failed to scan {dir:?}"))?;
+        }
+
+        let dirs = dirs.iter().map(|dir| canonicalize(dir)).collect::<Vec<_>>();
+        let found = roms.iter().collect::<HashSet<_>>();
+
+        self.games.retain(|path, _| {
+            !dirs.iter().any(|dir| path.starts_with(dir)) || found.contains(path)
+        });
+
+        for rom in &roms {
+            let game = self.games.entry(rom.clone()).or_default();
+
+            if let Err(err) = identify(rom, game, &extensions, dats) {
+                eprintln!("Failed to identify {rom:?}: {err:#}");
+            }
+        }
+
+        Ok(roms.len())
+    }
+
+    /// Records that `rom` was played from `started_at` for `duration`.
+    pub fn record_play(&mut self, rom: &Path, started_at: String, duration: Duration) {
+        let game = self.games.entry(canonicalize(rom)).or_default();
+
+        game.last_played = Some(started_at);
+        game.play_time_secs += duration.as_secs();
+    }
+}
+
+/// Records a play of `rom` in the library file at `library_path`.
+pub fn record_play(
+    library_path: &Path,
+    rom: &Path,
+    started_at: String,
+    duration: Duration,
+) -> Result<()> {
+    Library::update(library_path, |library| {
+        library.record_play(rom, started_at, duration)
+    })
+    .map(drop)
+}
+
+/// Hashes `rom` unless it is unchanged since the last scan, and looks it up in `dats`.
+fn identify(rom: &Path, game: &mut Game, extensions: &[String], dats: &DatIndex) -> Result<()> {
+    let metadata = fs::metadata(rom)?;
+    let size = metadata.len();
+    let modified = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+
+    if game.sha1.is_none() || game.size != size || game.modified != modified {
+        // DATs hash the ROM, so archives are always extracted even if some core runs them
+        let extensions = extensions
+            .iter()
+            .map(String::as_str)
+            .filter(|extension| !is_archive_extension(extension))
+            .collect::<Vec<_>>();
+        let data = archive::read_rom(rom, &extensions, false)?;
+
+        game.sha1 = Some(hex::encode(Sha1::digest(data)));
+        game.size = size;
+        game.modified = modified;
+    }
+
+    // identify again even if unchanged, DATs may have been added
+    if let Some(entry) = game.sha1.as_deref().and_then(|sha1| dats.get(sha1)) {
+        game.name = Some(entry.name.clone());
+        game.database = Some(entry.database.clone());
+    }
+
+    Ok(())
+}
+
+/// Adds the ROMs in `dir` and its subdirectories to `roms`, skipping the
+/// directories in `visited`, e.g. reached again through a symlink.
+fn find_roms(
+    dir: &Path,
+    extensions: &[String],
+    visited: &mut HashSet<PathBuf>,
+    roms: &mut Vec<PathBuf>,
+) -> Result<()> {
+    if !visited.insert(canonicalize(dir)) {
+        return Ok(());
+    }
+
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+
+        if path.is_dir() {
+            find_roms(&path, extensions, visited, roms)?;
+            continue;
+        }
+
+        let is_rom = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| {
+                is_archive_extension(extension)
+                    || extensions
+                        .iter()
+                        .any(|known| known.eq_ignore_ascii_case(extension))
+            });
+
+        if is_rom {
+            roms.push(canonicalize(&path));
+        }
+    }
+
+    Ok(())
+}
+
+fn is_archive_extension(extension: &str) -> bool {
+    ARCHIVE_EXTENSIONS
+        .iter()
+        .any(|archive| archive.eq_ignore_ascii_case(extension))
+}
+
+/// The extensions of all content the cores in the core database run.
+fn rom_extensions() -> Vec<String> {
+    let mut extensions = core_info::database()
+        .cores()
+        .iter()
+        .flat_map(|core| core.supported_extensions.iter().cloned())
+        .collect::<Vec<_>>();
+
+    extensions.sort();
+    extensions.dedup();
+
+    extensions
+}
+
+/// Library paths are absolute, so a ROM is the same game however it was opened.
+fn canonicalize(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    const ZIPPED_ROM: &[u8] = include_bytes!("../tests/fixtures/library/Game (World).zip");
+
+    /// A directory with an empty `roms` directory to scan.
+    fn library_directory() -> TempDir {
+        let dir = TempDir::new().unwrap();
+
+        fs::create_dir_all(dir.path().join("roms")).unwrap();
+
+        dir
+    }
+
+    fn dats() -> DatIndex {
+        let dir = Path::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/fixtures/library"
+        ));
+
+        DatIndex::load_dir(dir).unwrap()
+    }
+
+    #[test]
+    fn identifies_zipped_roms() {
+        let temp = library_directory();
+        let dir = temp.path();
+        let rom = dir.join("roms/Game (World).zip");
+
+        fs::write(&rom, ZIPPED_ROM).unwrap();
+
+        let mut library = Library::default();
+
+        assert_eq!(library.scan(&[dir.join("roms")], &dats()).unwrap(), 1);
+
+        let game = library.get(&rom).unwrap();
+
+        assert_eq!(game.name.as_deref(), Some("Game (World)"));
+        assert_eq!(game.database.as_deref(), Some("Nintendo - Game Boy"));
+        assert_eq!(game.title(&rom), "Game");
+        assert_eq!(game.region(), Some("World"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn scans_symlinked_dirs_once() {
+        let temp = library_directory();
+        let dir = temp.path();
+
+        fs::write(dir.join("roms/Game (World).zip"), ZIPPED_ROM).unwrap();
+        std::os::unix::fs::symlink(dir.join("roms"), dir.join("roms/loop")).unwrap();
+
+        let mut library = Library::default();
+        let num_roms = library.scan(&[dir.join("roms")], &dats()).unwrap();
+
+        assert_eq!(num_roms, 1);
+        assert_eq!(library.games().count(), 1);
+    }
+
+    #[test]
+    fn scanning_keeps_recorded_plays() {
+        let temp = library_directory();
+        let dir = temp.path();
+        let library_path = dir.join("library.json");
+        let rom = dir.join("roms/Game (World).zip");
+        let elsewhere = dir.join("elsewhere.gb");
+
+        fs::write(&rom, ZIPPED_ROM).unwrap();
+        fs::write(&elsewhere, [0; 4]).unwrap();
+        record_play(&library_path, &rom, "a".into(), Duration::from_secs(60)).unwrap();
+        record_play(
+            &library_path,
+            &elsewhere,
+            "b".into(),
+            Duration::from_secs(1),
+        )
+        .unwrap();
+        record_play(&library_path, &rom, "c".into(), Duration::from_secs(30)).unwrap();
+
+        let (library, num_roms) =
+            Library::scan_file(&library_path, &[dir.join("roms")], &dats()).unwrap();
+
+        assert_eq!(num_roms, 1);
+
+        let game = library.get(&rom).unwrap();
+
+        assert_eq!(game.last_played.as_deref(), Some("c"));
+        assert_eq!(game.play_time_secs, 90);
+        assert_eq!(game.name.as_deref(), Some("Game (World)"));
+        assert!(library.get(&elsewhere).is_some());
+
+        let saved = Library::load(&library_path).unwrap();
+
+        assert_eq!(saved.get(&rom).unwrap().play_time_secs, 90);
+        assert_eq!(saved.games().count(), 2);
+    }
+}
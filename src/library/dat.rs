@@ -0,0 +1,223 @@
+//! No-Intro and Redump DAT files in the Logiqx XML format.
+//!
+//! A DAT lists the known good dumps of one system, e.g. `Nintendo - Game Boy`,
+//! with the hashes of their files.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+
+const DAT_EXTENSIONS: &[&str] = &["dat", "xml"];
+
+/// A known dump.
+#[derive(Clone, Debug)]
+pub struct Entry {
+    /// Name of the DAT, e.g. `Nintendo - Game Boy`.
+    pub database: String,
+    /// Name of the game, e.g. `Tetris (World) (Rev 1)`.
+    pub name: String,
+}
+
+/// The dumps of all loaded DATs by their lowercase hex SHA1.
+#[derive(Default)]
+pub struct DatIndex {
+    entries: HashMap<String, Entry>,
+}
+
+impl DatIndex {
+    /// Loads all DAT files in `dir`.
+    pub fn load_dir(dir: &Path) -> Result<Self> {
+        let mut index = Self::default();
+        let entries = fs::read_dir(dir).with_context(|| format!("failed to read {dir:?}"))?;
+
+        for entry in entries {
+            let path = entry?.path();
+            let is_dat = path
+                .extension()
+                .and_then(|extension| extension.to_str())
+                .is_some_and(|extension| {
+                    DAT_EXTENSIONS
+                        .iter()
+                        .any(|dat| dat.eq_ignore_ascii_case(extension))
+                });
+
+            if !is_dat {
+                continue;
+            }
+
+            let dat =
+                fs::read_to_string(&path).with_context(|| format!("failed to read {path:?}"))?;
+
+            index
+                .add(&dat)
+                .with_context(|| format!("invalid DAT file {path:?}"))?;
+        }
+
+        Ok(index)
+    }
+
+    /// Adds the dumps of a DAT file's contents.
+    pub fn add(&mut self, dat: &str) -> Result<()> {
+        let mut reader = Reader::from_str(dat);
+        let mut database = String::new();
+        let mut game = None;
+        let mut in_header_name = false;
+
+        reader.trim_text(true);
+
+        loop {
+            match reader.read_event()? {
+                Event::Start(element) if element.local_name().as_ref() == b"name" => {
+                    in_header_name = game.is_none();
+                }
+                Event::Text(text) if in_header_name => {
+                    database = clean_database_name(&text.unescape()?);
+                    in_header_name = false;
+                }
+                Event::Start(element) if is_game(&element) => {
+                    game = attribute(&element, "name")?;
+                }
+                Event::End(element)
+                    if matches!(element.local_name().as_ref(), b"game" | b"machine") =>
+                {
+                    game = None;
+                }
+                Event::Start(element) | Event::Empty(element)
+                    if element.local_name().as_ref() == b"rom" =>
+                {
+                    let (Some(name), Some(sha1)) = (&game, attribute(&element, "sha1")?) else {
+                        continue;
+                    };
+
+                    self.entries.insert(
+                        sha1.to_ascii_lowercase(),
+                        Entry {
+                            database: database.clone(),
+                            name: name.clone(),
+                        },
+                    );
+                }
+                Event::Eof => break,
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, sha1: &str) -> Option<&Entry> {
+        self.entries.get(sha1)
+    }
+}
+
+fn is_game(element: &BytesStart) -> bool {
+    matches!(element.local_name().as_ref(), b"game" | b"machine")
+}
+
+fn attribute(element: &BytesStart, name: &str) -> Result<Option<String>> {
+    Ok(element
+        .try_get_attribute(name)?
+        .map(|attribute| attribute.unescape_value())
+        .transpose()?
+        .map(|value| value.into_owned()))
+}
+
+/// Strips the suffix some DATs add to the system, as in `Nintendo - Game Boy (Parent-Clone)`.
+fn clean_database_name(name: &str) -> String {
+    name.trim_end_matches(" (Parent-Clone)").trim().to_owned()
+}
+
+/// The title of a game without the tags in parentheses, e.g. `Tetris` for
+/// `Tetris (World) (Rev 1)`.
+pub fn title(name: &str) -> &str {
+    name.split_once(" (")
+        .map_or(name, |(title, _)| title)
+        .trim()
+}
+
+/// The region of a game, the first tag of its name by the No-Intro and Redump
+/// naming conventions, e.g. `World` for `Tetris (World) (Rev 1)`.
+pub fn region(name: &str) -> Option<&str> {
+    let (_, tags) = name.split_once(" (")?;
+    let (region, _) = tags.split_once(')')?;
+
+    Some(region)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GB_DAT: &str = include_str!("../../tests/fixtures/library/gb.dat");
+
+    #[test]
+    fn indexes_roms_by_lowercase_sha1() {
+        let mut index = DatIndex::default();
+
+        index.add(GB_DAT).unwrap();
+
+        assert_eq!(index.len(), 2);
+
+        let game = index
+            .get("56178b86a57fac22899a9964185c2cc96e7da589")
+            .unwrap();
+
+        assert_eq!(game.database, "Nintendo - Game Boy");
+        assert_eq!(game.name, "Game (World)");
+
+        let machine = index
+            .get("d858e024b4522c98d4009c6448bba7b627e3a3fc")
+            .unwrap();
+
+        assert_eq!(machine.name, "Tom & Jerry (USA) (Rev 1)");
+        assert!(index
+            .get("56178B86A57FAC22899A9964185C2CC96E7DA589")
+            .is_none());
+    }
+
+    #[test]
+    fn rejects_malformed_dats() {
+        let mut index = DatIndex::default();
+
+        assert!(index.add("<datafile><game name=\"A\"></datafile>").is_err());
+    }
+
+    #[test]
+    fn loads_dat_and_xml_files_in_dir() {
+        let dir = Path::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/fixtures/library"
+        ));
+        let index = DatIndex::load_dir(dir).unwrap();
+
+        assert_eq!(index.len(), 3);
+
+        let game = index
+            .get("5ba93c9db0cff93f52b521d7420e43f6eda2784f")
+            .unwrap();
+
+        assert_eq!(game.database, "Nintendo - Game Boy Color");
+        assert_eq!(game.name, "Color Game (Europe) (En,Fr)");
+    }
+
+    #[test]
+    fn splits_names_into_title_and_region() {
+        assert_eq!(title("Tom & Jerry (USA) (Rev 1)"), "Tom & Jerry");
+        assert_eq!(title("Homebrew"), "Homebrew");
+        assert_eq!(region("Tom & Jerry (USA) (Rev 1)"), Some("USA"));
+        assert_eq!(region("Color Game (Europe) (En,Fr)"), Some("Europe"));
+        assert_eq!(region("Homebrew"), None);
+    }
+}
@@ -1,15 +1,18 @@
 use std::ffi::c_uint;
 use std::fs;
-use std::path::PathBuf;
+use std::mem;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 
-use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::sync::mpsc::{channel, sync_channel, Receiver, Sender, SyncSender};
 use std::sync::Arc;
+use std::thread::JoinHandle;
 use std::time::{Duration, Instant};
 use std::{io, thread, vec};
 
-use anyhow::{anyhow, bail, Context, Result};
+use anyhow::{Context, Result};
 
-use clap::Parser;
+use clap::{CommandFactory, FromArgMatches};
 
 use enumset::EnumSet;
 use gilrs::Gilrs;
@@ -18,135 +21,547 @@ use libretro_sys::PixelFormat;
 use parking_lot::RwLock;
 use rodio::Source;
 
+use ape::config::{self, Config, Settings};
+use ape::core::{self, Callbacks, Core};
+use ape::osd::{Notification, Notifications};
+use ape::video::Frame;
+use ape::{
+    ap_patch, ap_remote, archive, core_info, firmware, input, library, patch, remote, replay, util,
+};
+
 use crate::audio::RetroAudio;
-use crate::core::{Callbacks, Core};
-use crate::video::Frame;
 
-mod ap_remote;
 mod audio;
-mod buildbot;
-pub(crate) mod core;
-mod environment;
+mod commands;
 mod gui;
-mod input;
-mod remote;
-mod util;
-mod video;
+mod headless;
 
 #[derive(clap::Parser)]
+#[clap(subcommand_negates_reqs = true)]
 struct Cli {
+    #[clap(subcommand)]
+    command: Option<commands::Command>,
     #[clap(long, env = "APE_CORE")]
     core: Option<PathBuf>,
-    #[clap(long, env = "APE_ROM")]
-    rom: PathBuf,
+    /// ROM or Archipelago patch (`.apgbc`, `.aplttp`, …) to load, the GUI shows the library without one
+    #[clap(long, env = "APE_ROM", required_if_eq("headless", "true"))]
+    rom: Option<PathBuf>,
+    /// RetroArch `.cht` cheat file, defaults to a `.cht` file next to the ROM
+    #[clap(long, env = "APE_CHEATS")]
+    cheats: Option<PathBuf>,
+    /// IPS, UPS or BPS patch applied in order, defaults to patches named like the ROM next to it
+    #[clap(long = "patch", env = "APE_PATCHES", value_delimiter = ',')]
+    patches: Vec<PathBuf>,
+    /// Settings that can also be set in config files, see `ape config show`
+    #[clap(flatten)]
+    settings: config::Layer,
+    /// Start recording video and audio as soon as the content is loaded
+    #[clap(long)]
+    record: bool,
+    /// Run without a window, e.g. on a server, in CI or as a background bridge
+    #[clap(long, env = "APE_HEADLESS")]
+    headless: bool,
+    /// Exit after running this many frames
+    #[clap(long, requires = "headless", conflicts_with = "until_exit")]
+    frames: Option<u64>,
+    /// Exit once the core requests a shutdown
+    #[clap(long, requires = "headless")]
+    until_exit: bool,
+    /// Play audio while running headless
+    #[clap(long, requires = "headless")]
+    audio: bool,
+}
+
+impl Cli {
+    /// Loads the config, including the per-core and per-game files of the
+    /// content given on the command line, and the core database.
+    fn load_config(&self, layers: &Layers) -> Result<Config> {
+        let config = layers.load(None, None)?;
+
+        // The core database names the core, so it is loaded with the settings
+        // of the user config alone
+        core_info::init(config.settings.core_info_dir.as_deref())
+            .context("failed to load core info")?;
+
+        let Some(rom) = &self.rom else {
+            return Ok(config);
+        };
+        let core_name = match &self.core {
+            Some(core) => core_info::database()
+                .for_library_path(core)
+                .map(|core| core.name.as_str()),
+            None => util::core_names_for_rom(rom)
+                .ok()
+                .and_then(|core_names| core_names.first().copied()),
+        };
+
+        layers.load(core_name, Some(rom))
+    }
+
+    fn run_options(&self, settings: &Settings, layers: Layers) -> RunOptions {
+        RunOptions {
+            remote: remote_config(settings),
+            layers,
+            record: self.record,
+        }
+    }
+
+    fn headless_options(&self) -> headless::Options {
+        headless::Options {
+            frames: self.frames,
+            until_exit: self.until_exit,
+            audio: self.audio,
+        }
+    }
+}
+
+/// The settings from the environment and the command line, layered over the
+/// config files again for each content launched, so that its per-core and
+/// per-game files apply.
+#[derive(Clone)]
+struct Layers {
+    env: config::Layer,
+    cli: config::Layer,
+}
+
+impl Layers {
+    /// Loads the config with the files of `core` and `rom`, see [`Config::load`].
+    fn load(&self, core: Option<&str>, rom: Option<&Path>) -> Result<Config> {
+        Config::load(&self.env, &self.cli, core, rom)
+    }
+
+    /// Loads the config `content` runs with.
+    fn load_for_content(&self, content: &Content) -> Result<Config> {
+        let core_name = core_info::database()
+            .for_library_path(&content.core)
+            .map(|core| core.name.as_str());
+
+        self.load(core_name, Some(&content.rom))
+    }
+}
+
+fn replay_config(settings: &Settings) -> Option<replay::Config> {
+    (settings.replay_seconds > 0.).then(|| replay::Config {
+        seconds: settings.replay_seconds,
+        max_memory: settings.replay_memory.saturating_mul(1024 * 1024),
+        ..<_>::default()
+    })
+}
+
+fn remote_config(settings: &Settings) -> remote::Config {
+    remote::Config {
+        address: SocketAddr::new(settings.remote_address, settings.remote_port),
+        allow_lan: settings.remote_lan,
+        allowlist: settings.remote_allow.clone(),
+        screenshot_directory: settings.screenshot_dir.clone(),
+    }
+}
+
+fn gui_options(settings: &Settings) -> gui::Options {
+    gui::Options {
+        cores_directory: settings.cores_dir.clone(),
+        library_path: settings.library_file.clone(),
+        library_dirs: settings.library_dirs.clone(),
+        dat_dir: settings.dat_dir.clone(),
+        buildbot: settings.buildbot_url.clone(),
+    }
 }
 
 fn main() -> Result<()> {
     dotenv::dotenv().ok();
 
-    let cli = Cli::parse();
-    let rom = cli.rom;
-    let core = match cli.core {
-        Some(core) => core,
-        None => {
-            util::find_and_potentially_fetch_core_for_rom(&rom).context("failed to resolve core")?
-        }
+    let matches = Cli::command().get_matches();
+    let mut cli = Cli::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+    let env = cli.settings.split_env(&matches);
+    let layers = Layers {
+        env,
+        cli: cli.settings.clone(),
     };
+    let config = cli.load_config(&layers)?;
+
+    if let Some(command) = cli.command {
+        return command.run(&config);
+    }
+
+    let settings = &config.settings;
+    let run_options = cli.run_options(settings, layers);
+
+    // The remote interface starts on the core thread, fail before opening a window
+    run_options
+        .remote
+        .validate()
+        .context("invalid remote settings")?;
+    let headless_options = cli.headless.then(|| cli.headless_options());
+    let launch = cli.rom.map(|rom| Launch {
+        core: cli.core,
+        rom,
+        cheats: cli.cheats,
+        patches: cli.patches,
+    });
 
-    gui::run(core, rom).context("failed to run gui")?;
+    match headless_options {
+        Some(options) => {
+            let content = launch.context("no ROM given")?.resolve(settings)?;
+
+            headless::run(content, run_options, options).context("failed to run headless")?
+        }
+        // The GUI resolves the core itself, downloading it in the background
+        None => {
+            gui::run(launch, run_options, gui_options(settings)).context("failed to run gui")?
+        }
+    }
 
     Ok(())
 }
 
-fn run(
-    core: impl Into<PathBuf>,
-    rom: impl Into<PathBuf>,
-    egui_ctx: egui::Context,
-) -> Result<(Receiver<Option<Frame>>, core::Handle)> {
-    let core = core.into();
-    let rom = rom.into();
+/// Content the core thread switches to without restarting the process.
+pub enum Switch {
+    /// Load another ROM with the core resolved for it, `None` keeping the current core.
+    Rom { rom: PathBuf, core: Option<PathBuf> },
+    /// Load the current ROM with another core.
+    Core(PathBuf),
+}
+
+/// Content as given on the command line, before its core is resolved.
+struct Launch {
+    core: Option<PathBuf>,
+    /// A ROM or an Archipelago patch.
+    rom: PathBuf,
+    cheats: Option<PathBuf>,
+    patches: Vec<PathBuf>,
+}
+
+impl Launch {
+    /// Applies Archipelago patches and finds the core, downloading it if needed.
+    fn resolve(self, settings: &Settings) -> Result<Content> {
+        let rom = ap_patch::resolve_rom(&self.rom, settings.base_rom_dir.as_deref())?;
+        let core = match self.core.clone() {
+            Some(core) => core,
+            None => util::find_and_potentially_fetch_core_for_rom(
+                &rom,
+                &settings.cores_dir,
+                &settings.buildbot_url,
+            )
+            .context("failed to resolve core")?,
+        };
+
+        Ok(self.into_content(core, rom))
+    }
+
+    /// The content with the resolved `core` and `rom`, the latter possibly patched.
+    fn into_content(self, core: PathBuf, rom: PathBuf) -> Content {
+        Content {
+            core,
+            cheats: self.cheats.or_else(|| default_cheats(&rom)),
+            patches: if self.patches.is_empty() {
+                patch::find_patches(&rom)
+            } else {
+                self.patches
+            },
+            rom,
+        }
+    }
+}
+
+/// A core together with the content it runs.
+#[derive(Clone)]
+struct Content {
+    core: PathBuf,
+    rom: PathBuf,
+    cheats: Option<PathBuf>,
+    patches: Vec<PathBuf>,
+}
+
+impl Content {
+    fn switch(&self, switch: Switch) -> Self {
+        match switch {
+            Switch::Rom { rom, core } => Self {
+                core: core.unwrap_or_else(|| self.core.clone()),
+                cheats: default_cheats(&rom),
+                patches: patch::find_patches(&rom),
+                rom,
+            },
+            Switch::Core(core) => Self {
+                core,
+                ..self.clone()
+            },
+        }
+    }
+}
+
+/// The `.cht` file next to the ROM, if there is one.
+fn default_cheats(rom: &Path) -> Option<PathBuf> {
+    Some(archive::strip_entry(rom).with_extension("cht")).filter(|path| path.exists())
+}
+
+/// Handles to the core thread started by [`run`].
+struct Running {
+    frame_rx: Receiver<Option<Frame>>,
+    core_handle: core::Handle,
+    switch_tx: Sender<Switch>,
+    /// The settings of the content that is running.
+    settings: Arc<RwLock<Settings>>,
+    thread: JoinHandle<Result<()>>,
+}
+
+/// What drives the emulation loop.
+enum Frontend {
+    /// The GUI runs frames through the core handle and displays them.
+    Gui(egui::Context),
+    /// The core thread runs frames on its own at the core's frame rate.
+    Headless(headless::Options),
+}
+
+/// Settings of the core thread that apply to all content it loads.
+struct RunOptions {
+    /// The remotes outlive the content, so they keep the settings they started with.
+    remote: remote::Config,
+    /// The other settings are loaded for each content.
+    layers: Layers,
+    /// Whether to record all content from the start.
+    record: bool,
+}
 
+fn run(
+    content: Content,
+    options: RunOptions,
+    notifications: Notifications,
+    frontend: Frontend,
+) -> Result<Running> {
     let (frame_tx, frame_rx) = sync_channel(1);
-    let (audio_tx, audio_rx) = sync_channel(1);
+    let (switch_tx, switch_rx) = channel();
+    let settings = options
+        .layers
+        .load_for_content(&content)
+        .context("failed to load config")?
+        .settings;
+    let settings = Arc::new(RwLock::new(settings));
 
     let core_host = core::Host::new();
     let core_handle = core_host.handle();
 
-    thread::spawn(move || {
-        let (_stream, stream_handle) = rodio::OutputStream::try_default()?;
+    let thread = thread::spawn({
+        let settings = Arc::clone(&settings);
+
+        move || {
+            let play_audio = match &frontend {
+                Frontend::Gui(_) => true,
+                Frontend::Headless(options) => options.audio,
+            };
+            let audio_output = if play_audio {
+                Some(rodio::OutputStream::try_default().context("failed to open audio output")?)
+            } else {
+                None
+            };
+
+            // The remotes outlive the loaded content, so switching doesn't rebind their ports
+            ap_remote::start(core_host.handle(), notifications.clone());
+            remote::start(core_host.handle(), options.remote, notifications.clone());
+
+            let runner = Runner {
+                frontend,
+                core_host,
+                frame_tx,
+                audio_output: audio_output
+                    .as_ref()
+                    .map(|(_, stream_handle)| stream_handle),
+                notifications,
+                switch_rx,
+                layers: options.layers,
+                settings,
+                record: options.record,
+            };
 
-        let gilrs = Gilrs::new()
-            .map_err(|err| anyhow!("{err}"))
-            .context("failed to initialize gilrs")?;
+            let mut content = content;
+            let mut previous_content = None;
 
-        for (id, gamepad) in gilrs.gamepads() {
-            println!("Gamepad #{id}: {:?}", gamepad.name());
+            loop {
+                match runner.run_content(&content) {
+                    Ok(Some(switch)) => {
+                        let next_content = content.switch(switch);
+
+                        previous_content = Some(mem::replace(&mut content, next_content));
+                    }
+                    Ok(None) => break,
+                    Err(err) => {
+                        let Some(previous) = previous_content.take() else {
+                            return Err(err);
+                        };
+
+                        runner
+                            .notifications
+                            .error(format!("Failed to switch content: {err:#}"));
+                        content = previous;
+                    }
+                }
+            }
+
+            println!("Exiting normally");
+
+            anyhow::Ok(())
         }
+    });
+
+    Ok(Running {
+        frame_rx,
+        core_handle,
+        switch_tx,
+        settings,
+        thread,
+    })
+}
+
+/// Runs content on the core thread, one core at a time.
+struct Runner<'a> {
+    frontend: Frontend,
+    core_host: core::Host,
+    frame_tx: SyncSender<Option<Frame>>,
+    /// `None` if audio is not played
+    audio_output: Option<&'a rodio::OutputStreamHandle>,
+    notifications: Notifications,
+    switch_rx: Receiver<Switch>,
+    layers: Layers,
+    /// The settings of the content that is running, shared with the frontend.
+    settings: Arc<RwLock<Settings>>,
+    /// Whether to record all content from the start.
+    record: bool,
+}
+
+impl Runner<'_> {
+    /// Loads `content` and runs it until the frontend is done with it.
+    ///
+    /// Returns the content to switch to next, if any.
+    fn run_content(&self, content: &Content) -> Result<Option<Switch>> {
+        let notifications = &self.notifications;
+        let (audio_tx, audio_rx) = sync_channel(1);
+        let settings = self
+            .layers
+            .load_for_content(content)
+            .context("failed to load config")?
+            .settings;
+
+        *self.settings.write() = settings.clone();
+
+        let gilrs = match Gilrs::new() {
+            Ok(gilrs) => {
+                for (id, gamepad) in gilrs.gamepads() {
+                    println!("Gamepad #{id}: {:?}", gamepad.name());
+                }
+
+                Some(gilrs)
+            }
+            Err(err) => {
+                eprintln!("Gamepad input unavailable: {err}");
+                None
+            }
+        };
 
-        let sram_path = rom.with_extension("sram");
+        let sram_path = util::file_for_rom(&settings.saves_dir, &content.rom, "sram");
+        // SRAM used to be saved next to the ROM, it moves to the saves directory on the next save
+        let legacy_sram_path = archive::strip_entry(&content.rom).with_extension("sram");
+
+        if let Err(err) = fs::create_dir_all(&settings.saves_dir) {
+            eprintln!(
+                "Failed to create saves directory {:?}: {err}",
+                settings.saves_dir
+            );
+        }
 
         let speed_factor = Arc::new(RwLock::new(1.0));
 
+        let egui_ctx = match &self.frontend {
+            Frontend::Gui(egui_ctx) => Some(egui_ctx.clone()),
+            Frontend::Headless(_) => None,
+        };
+
         let callbacks = ApeCallbacks {
-            frame_tx,
-            audio_tx,
+            frame_tx: self.frame_tx.clone(),
+            audio_tx: self.audio_output.is_some().then_some(audio_tx),
             gilrs,
             egui_ctx,
             buttons: <_>::default(),
             speed_factor: Arc::clone(&speed_factor),
+            notifications: notifications.clone(),
         };
 
         let core_config = core::Config {
-            core,
-            rom,
+            core: content.core.clone(),
+            rom: content.rom.clone(),
+            patches: content.patches.clone(),
+            system_directory: Some(settings.system_dir.clone()),
             callbacks: callbacks.boxed(),
         };
 
+        self.warn_about_firmware(&content.core, &settings.system_dir);
+
         let mut last_sram_save = Instant::now();
+        let started_at = util::timestamp();
+        let started = Instant::now();
 
-        Core::load(core_config, |core| -> Result<()> {
-            match fs::read(&sram_path) {
+        let switch = Core::load(core_config, |core| {
+            let restore_path = if !sram_path.exists() && legacy_sram_path.exists() {
+                &legacy_sram_path
+            } else {
+                &sram_path
+            };
+
+            match fs::read(restore_path) {
                 Ok(sram) => {
-                    eprintln!("Restoring SRAM from {sram_path:?}");
+                    eprintln!("Restoring SRAM from {restore_path:?}");
                     core.restore_save_ram(&sram);
                 }
                 Err(err) => {
                     if err.kind() == io::ErrorKind::NotFound {
-                        eprintln!("No SRAM file found at {sram_path:?}");
+                        eprintln!("No SRAM file found at {restore_path:?}");
                     } else {
-                        eprintln!("Failed to read SRAM from {sram_path:?}");
+                        eprintln!("Failed to read SRAM from {restore_path:?}");
                     }
                 }
             }
 
-            ap_remote::start(core_host.handle());
-            remote::start(core_host.handle());
+            if let Some(cheats) = &content.cheats {
+                match core.load_cheats(cheats) {
+                    Ok(num_cheats) => notifications.info(format!("Loaded {num_cheats} cheats")),
+                    Err(err) => notifications.error(format!("Failed to load cheats: {err:#}")),
+                }
+            }
+
+            if let Some(replay_config) = replay_config(&settings) {
+                core.start_replay(replay_config);
+            }
+
+            if self.record {
+                if let Err(err) = core.start_recording(&settings.recording_dir) {
+                    notifications.error(format!("Failed to start recording: {err:#}"));
+                }
+            }
 
             let system_av_info = core.get_system_av_info();
 
             println!("{:#?}", system_av_info);
             // panic!("sample rate: {}", system_av_info.timing.sample_rate);
 
-            let retro_audio = RetroAudio {
-                rx: audio_rx,
-                current_frame: Vec::new().into_iter(),
-                base_sample_rate: system_av_info.timing.sample_rate as f32,
-                speed_factor: Arc::clone(&speed_factor),
-            };
-
-            thread::spawn(move || {
-                let res = stream_handle
-                    .play_raw(retro_audio.convert_samples())
-                    .context("failed to play stream");
-
-                if let Err(err) = res {
-                    eprintln!("Error while playing audio: {err}");
-                }
-            });
-
-            loop {
-                core_host.run(core);
+            if let Some(stream_handle) = self.audio_output {
+                let retro_audio = RetroAudio {
+                    rx: audio_rx,
+                    current_frame: Vec::new().into_iter(),
+                    base_sample_rate: system_av_info.timing.sample_rate as f32,
+                    speed_factor: Arc::clone(&speed_factor),
+                };
+                let stream_handle = stream_handle.clone();
+
+                thread::spawn(move || {
+                    let res = stream_handle
+                        .play_raw(retro_audio.convert_samples())
+                        .context("failed to play stream");
+
+                    if let Err(err) = res {
+                        eprintln!("Error while playing audio: {err}");
+                    }
+                });
+            }
 
+            let mut save_sram_periodically = |core: &mut Core| {
                 if last_sram_save.elapsed() >= Duration::from_secs(5) {
                     if let Err(err) = core.save_sram_to(&sram_path) {
                         eprintln!("Failed to save SRAM: {err:?}");
@@ -154,32 +569,76 @@ fn run(
 
                     last_sram_save = Instant::now();
                 }
-            }
+            };
+
+            let switch = match &self.frontend {
+                Frontend::Gui(_) => loop {
+                    self.core_host.run(core);
+                    save_sram_periodically(core);
+
+                    if let Ok(switch) = self.switch_rx.try_recv() {
+                        break Some(switch);
+                    }
+                },
+                Frontend::Headless(options) => {
+                    headless::run_frames(core, &self.core_host, options, save_sram_periodically);
+                    None
+                }
+            };
 
             if let Err(err) = core.save_sram_to(&sram_path) {
                 eprintln!("Failed to save SRAM: {err:?}");
             }
 
-            Ok(())
+            switch
         })
-        .context("failed to load core")?
-        .context("runtime error")?;
+        .context("failed to load core")?;
+
+        if let Err(err) = library::record_play(
+            &settings.library_file,
+            &content.rom,
+            started_at,
+            started.elapsed(),
+        ) {
+            eprintln!("Failed to record play in library: {err:#}");
+        }
 
-        println!("Exiting normally");
+        Ok(switch)
+    }
 
-        anyhow::Ok(())
-    });
+    /// Warns about missing or bad firmware, before the core fails on it or runs incorrectly.
+    fn warn_about_firmware(&self, core: &Path, system_directory: &Path) {
+        let Some(core_info) = core_info::database().for_library_path(core) else {
+            return;
+        };
+
+        let checks = match firmware::check_core(core_info, system_directory) {
+            Ok(checks) => checks,
+            Err(err) => {
+                eprintln!("Failed to check firmware: {err:#}");
+                return;
+            }
+        };
 
-    Ok((frame_rx, core_handle))
+        for check in checks.iter().filter(|check| check.is_problem()) {
+            self.notifications.warn(format!(
+                "Firmware {}: {}",
+                check.firmware.path, check.status
+            ));
+        }
+    }
 }
 
 struct ApeCallbacks {
     frame_tx: SyncSender<Option<Frame>>,
-    audio_tx: SyncSender<Vec<i16>>,
-    gilrs: Gilrs,
-    egui_ctx: egui::Context,
+    /// `None` if audio is not played
+    audio_tx: Option<SyncSender<Vec<i16>>>,
+    gilrs: Option<Gilrs>,
+    /// `None` when running headless
+    egui_ctx: Option<egui::Context>,
     buttons: EnumSet<input::Button>,
     speed_factor: Arc<RwLock<f32>>,
+    notifications: Notifications,
 }
 
 impl Callbacks for ApeCallbacks {
@@ -188,7 +647,9 @@ impl Callbacks for ApeCallbacks {
             eprintln!("Dropping frame, failed to send");
         }
 
-        self.egui_ctx.request_repaint();
+        if let Some(egui_ctx) = &self.egui_ctx {
+            egui_ctx.request_repaint();
+        }
     }
 
     fn supports_pixel_format(&mut self, pixel_format: PixelFormat) -> bool {
@@ -201,15 +662,23 @@ impl Callbacks for ApeCallbacks {
 
     fn audio_sample(&mut self, left: i16, right: i16) {
         // TODO: avoid vec, probably use enum
-        self.audio_tx.send(vec![left, right]).ok();
+        if let Some(audio_tx) = &self.audio_tx {
+            audio_tx.send(vec![left, right]).ok();
+        }
     }
 
     fn audio_samples(&mut self, samples: &[i16]) {
-        self.audio_tx.send(samples.to_vec()).ok();
+        if let Some(audio_tx) = &self.audio_tx {
+            audio_tx.send(samples.to_vec()).ok();
+        }
     }
 
     fn input_poll(&mut self) {
-        while let Some(event) = self.gilrs.next_event() {
+        let Some(gilrs) = &mut self.gilrs else {
+            return;
+        };
+
+        while let Some(event) = gilrs.next_event() {
             let mut release = false;
 
             if usize::from(event.id) != 0 {
@@ -272,4 +741,8 @@ impl Callbacks for ApeCallbacks {
     fn can_dupe_frames(&mut self) -> bool {
         true
     }
+
+    fn show_notification(&mut self, notification: Notification) {
+        self.notifications.push(notification);
+    }
 }
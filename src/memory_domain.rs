@@ -0,0 +1,268 @@
+use std::borrow::Cow;
+use std::ffi::c_uint;
+
+use libretro_sys::{MEMORY_SAVE_RAM, MEMORY_SYSTEM_RAM, MEMORY_VIDEO_RAM};
+
+use crate::core::Core;
+use crate::system::System;
+
+/// Where the bytes of a memory domain come from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Source {
+    Rom,
+    SystemBus,
+    /// A named address space of the core's memory map other than the system bus
+    AddressSpace(String),
+    /// A `libretro_sys::MEMORY_*` region from `retro_get_memory_data`
+    Region(c_uint),
+    /// A range of the system bus as described by the core's memory map
+    Bus {
+        start: usize,
+        len: usize,
+    },
+}
+
+const fn bus(start: usize, len: usize) -> Source {
+    Source::Bus { start, len }
+}
+
+const fn region(region: c_uint) -> Source {
+    Source::Region(region)
+}
+
+type DomainTable = &'static [(&'static str, &'static [Source])];
+
+const SYSTEM_BUS: &str = "System Bus";
+
+// Domain names follow BizHawk so that Archipelago worlds can address them
+// unchanged. Sources are listed in order of preference, the first one the
+// loaded core provides is used.
+
+const GB_DOMAINS: DomainTable = &[
+    ("WRAM", &[region(MEMORY_SYSTEM_RAM), bus(0xC000, 0x2000)]),
+    ("VRAM", &[region(MEMORY_VIDEO_RAM), bus(0x8000, 0x2000)]),
+    ("CartRAM", &[region(MEMORY_SAVE_RAM), bus(0xA000, 0x2000)]),
+    ("OAM", &[bus(0xFE00, 0xA0)]),
+    ("HRAM", &[bus(0xFF80, 0x7F)]),
+];
+
+const GBA_DOMAINS: DomainTable = &[
+    (
+        "EWRAM",
+        &[bus(0x0200_0000, 0x4_0000), region(MEMORY_SYSTEM_RAM)],
+    ),
+    ("IWRAM", &[bus(0x0300_0000, 0x8000)]),
+    ("BIOS", &[bus(0x0000_0000, 0x4000)]),
+    ("PALRAM", &[bus(0x0500_0000, 0x400)]),
+    (
+        "VRAM",
+        &[region(MEMORY_VIDEO_RAM), bus(0x0600_0000, 0x1_8000)],
+    ),
+    ("OAM", &[bus(0x0700_0000, 0x400)]),
+    ("SRAM", &[region(MEMORY_SAVE_RAM)]),
+];
+
+const NES_DOMAINS: DomainTable = &[
+    ("RAM", &[region(MEMORY_SYSTEM_RAM), bus(0x0000, 0x800)]),
+    ("WRAM", &[bus(0x6000, 0x2000)]),
+    ("Battery RAM", &[region(MEMORY_SAVE_RAM)]),
+];
+
+const SNES_DOMAINS: DomainTable = &[
+    (
+        "WRAM",
+        &[region(MEMORY_SYSTEM_RAM), bus(0x7E_0000, 0x2_0000)],
+    ),
+    ("CARTRAM", &[region(MEMORY_SAVE_RAM)]),
+    ("VRAM", &[region(MEMORY_VIDEO_RAM)]),
+];
+
+const N64_DOMAINS: DomainTable = &[(
+    "RDRAM",
+    &[region(MEMORY_SYSTEM_RAM), bus(0x8000_0000, 0x80_0000)],
+)];
+
+const GENESIS_DOMAINS: DomainTable = &[
+    (
+        "68K RAM",
+        &[region(MEMORY_SYSTEM_RAM), bus(0xFF_0000, 0x1_0000)],
+    ),
+    ("SRAM", &[region(MEMORY_SAVE_RAM)]),
+    ("VRAM", &[region(MEMORY_VIDEO_RAM)]),
+];
+
+const GENERIC_DOMAINS: DomainTable = &[
+    ("RAM", &[region(MEMORY_SYSTEM_RAM)]),
+    ("SRAM", &[region(MEMORY_SAVE_RAM)]),
+    ("VRAM", &[region(MEMORY_VIDEO_RAM)]),
+];
+
+/// A named view into the core's memory, e.g. `WRAM` or `System Bus`.
+#[derive(Clone, Debug)]
+pub struct MemoryDomain {
+    name: Cow<'static, str>,
+    source: Source,
+}
+
+impl MemoryDomain {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn size(&self, core: &Core) -> usize {
+        match self.source {
+            Source::Rom => core.rom(|rom| rom.len()),
+            Source::SystemBus => core.system_bus_size(),
+            Source::AddressSpace(ref name) => core.address_space_size(name),
+            Source::Region(region) => core.get_memory_region(region).len(),
+            Source::Bus { len, .. } => len,
+        }
+    }
+
+    pub fn is_writable(&self) -> bool {
+        self.source != Source::Rom
+    }
+
+    /// Reads up to `len` bytes starting at the domain relative `address`.
+    pub fn read(&self, core: &Core, address: usize, len: usize) -> Vec<u8> {
+        match self.source {
+            Source::Rom => core.rom(|rom| subslice(rom, address, len).to_vec()),
+            Source::SystemBus => core.get_memory(address, len),
+            Source::AddressSpace(ref name) => core.get_address_space_memory(name, address, len),
+            Source::Region(region) => {
+                subslice(core.get_memory_region(region), address, len).to_vec()
+            }
+            Source::Bus {
+                start,
+                len: domain_len,
+            } => {
+                let len = len.min(domain_len.saturating_sub(address));
+
+                if len == 0 {
+                    return Vec::new();
+                }
+
+                core.get_memory(start + address, len)
+            }
+        }
+    }
+
+    /// Writes `data` to the domain relative `address` and returns the number of bytes written.
+    pub fn write(&self, core: &mut Core, address: usize, data: &[u8]) -> usize {
+        match self.source {
+            Source::Rom => 0,
+            Source::SystemBus => core.write_memory(address, data),
+            Source::AddressSpace(ref name) => core.write_address_space_memory(name, address, data),
+            Source::Region(region) => {
+                let memory = core.get_memory_region_mut(region);
+                let target = subslice_mut(memory, address, data.len());
+                let len = target.len();
+
+                target.copy_from_slice(&data[..len]);
+
+                len
+            }
+            Source::Bus {
+                start,
+                len: domain_len,
+            } => {
+                let len = data.len().min(domain_len.saturating_sub(address));
+
+                if len == 0 {
+                    return 0;
+                }
+
+                core.write_memory(start + address, &data[..len])
+            }
+        }
+    }
+}
+
+/// Lists the memory domains available for the loaded core and content.
+pub fn domains(core: &Core) -> Vec<MemoryDomain> {
+    let table = match System::detect(core) {
+        Some(System::GameBoy | System::GameBoyColor) => GB_DOMAINS,
+        Some(System::GameBoyAdvance) => GBA_DOMAINS,
+        Some(System::Nes) => NES_DOMAINS,
+        Some(System::Snes) => SNES_DOMAINS,
+        Some(System::N64) => N64_DOMAINS,
+        Some(System::Genesis) => GENESIS_DOMAINS,
+        _ => GENERIC_DOMAINS,
+    };
+
+    let mut domains = table
+        .iter()
+        .filter_map(|(name, sources)| {
+            let source = sources
+                .iter()
+                .find(|source| is_source_available(core, source))?;
+
+            Some(MemoryDomain {
+                name: Cow::Borrowed(name),
+                source: source.clone(),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    domains.push(MemoryDomain {
+        name: Cow::Borrowed("ROM"),
+        source: Source::Rom,
+    });
+
+    if is_source_available(core, &Source::SystemBus) {
+        domains.push(MemoryDomain {
+            name: Cow::Borrowed(SYSTEM_BUS),
+            source: Source::SystemBus,
+        });
+    }
+
+    for address_space in core.address_spaces() {
+        if domains.iter().any(|domain| domain.name == address_space) {
+            continue;
+        }
+
+        domains.push(MemoryDomain {
+            name: Cow::Owned(address_space.clone()),
+            source: Source::AddressSpace(address_space),
+        });
+    }
+
+    domains
+}
+
+/// Finds a domain by name.
+///
+/// Names are matched case-insensitively and `_` may be used in place of a space,
+/// so `system_bus` finds the `System Bus` domain.
+pub fn find(core: &Core, name: &str) -> Option<MemoryDomain> {
+    let normalize = |name: &str| name.replace('_', " ").to_ascii_lowercase();
+    let name = normalize(name);
+
+    domains(core)
+        .into_iter()
+        .find(|domain| normalize(&domain.name) == name)
+}
+
+fn is_source_available(core: &Core, source: &Source) -> bool {
+    match *source {
+        Source::Rom => true,
+        Source::SystemBus => core.has_memory_map(),
+        Source::AddressSpace(ref name) => core.address_space_size(name) > 0,
+        Source::Region(region) => !core.get_memory_region(region).is_empty(),
+        Source::Bus { start, .. } => !core.get_memory(start, 1).is_empty(),
+    }
+}
+
+fn subslice(data: &[u8], start: usize, len: usize) -> &[u8] {
+    let start = start.min(data.len());
+    let end = start.saturating_add(len).min(data.len());
+
+    &data[start..end]
+}
+
+fn subslice_mut(data: &mut [u8], start: usize, len: usize) -> &mut [u8] {
+    let start = start.min(data.len());
+    let end = start.saturating_add(len).min(data.len());
+
+    &mut data[start..end]
+}
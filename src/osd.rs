@@ -0,0 +1,169 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+
+pub const DEFAULT_DURATION: Duration = Duration::from_secs(3);
+const MAX_VISIBLE: usize = 5;
+/// Notifications queued beyond this are dropped, lowest priority first, so
+/// nothing piles up when no one shows them, e.g. when running headless.
+const MAX_PENDING: usize = 32;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+#[derive(Clone, Debug)]
+pub struct Notification {
+    pub text: String,
+    pub duration: Duration,
+    /// Queued notifications with a higher priority are shown first.
+    pub priority: u32,
+    pub level: Level,
+}
+
+impl Notification {
+    pub fn new(text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            duration: DEFAULT_DURATION,
+            priority: 0,
+            level: Level::Info,
+        }
+    }
+
+    pub fn with_duration(mut self, duration: Duration) -> Self {
+        self.duration = duration;
+        self
+    }
+
+    pub fn with_priority(mut self, priority: u32) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    pub fn with_level(mut self, level: Level) -> Self {
+        self.level = level;
+        self
+    }
+}
+
+pub struct VisibleNotification {
+    pub text: String,
+    pub level: Level,
+    /// Fraction of the notification's duration that is left, from `1.0` to `0.0`.
+    pub remaining: f32,
+}
+
+/// Queue of on-screen notifications shared between the GUI and everything
+/// that wants to display a message.
+#[derive(Clone)]
+pub struct Notifications {
+    inner: Arc<Mutex<Inner>>,
+}
+
+struct Inner {
+    pending: VecDeque<Notification>,
+    visible: Vec<(Notification, Instant)>,
+    min_interval: Duration,
+    last_shown: Option<Instant>,
+}
+
+impl Default for Notifications {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Notifications {
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                pending: VecDeque::new(),
+                visible: Vec::new(),
+                min_interval: Duration::ZERO,
+                last_shown: None,
+            })),
+        }
+    }
+
+    pub fn push(&self, notification: Notification) {
+        eprintln!("[OSD] {}", notification.text);
+
+        let mut inner = self.inner.lock();
+        let position = inner
+            .pending
+            .iter()
+            .position(|pending| pending.priority < notification.priority)
+            .unwrap_or(inner.pending.len());
+
+        inner.pending.insert(position, notification);
+        inner.pending.truncate(MAX_PENDING);
+    }
+
+    pub fn info(&self, text: impl Into<String>) {
+        self.push(Notification::new(text));
+    }
+
+    pub fn warn(&self, text: impl Into<String>) {
+        self.push(Notification::new(text).with_level(Level::Warn));
+    }
+
+    pub fn error(&self, text: impl Into<String>) {
+        self.push(Notification::new(text).with_level(Level::Error));
+    }
+
+    /// Sets the minimum time between two queued notifications becoming visible.
+    pub fn set_min_interval(&self, min_interval: Duration) {
+        self.inner.lock().min_interval = min_interval;
+    }
+
+    /// Expires old notifications, promotes queued ones and returns everything
+    /// that should currently be on screen, oldest first.
+    pub fn visible(&self) -> Vec<VisibleNotification> {
+        let mut inner = self.inner.lock();
+        let now = Instant::now();
+
+        inner
+            .visible
+            .retain(|(notification, shown_at)| now - *shown_at < notification.duration);
+
+        while !inner.pending.is_empty() {
+            let interval_elapsed = inner
+                .last_shown
+                .is_none_or(|last_shown| now - last_shown >= inner.min_interval);
+
+            if !interval_elapsed {
+                break;
+            }
+
+            if let Some(notification) = inner.pending.pop_front() {
+                inner.visible.push((notification, now));
+                inner.last_shown = Some(now);
+            }
+        }
+
+        let num_hidden = inner.visible.len().saturating_sub(MAX_VISIBLE);
+        inner.visible.drain(..num_hidden);
+
+        inner
+            .visible
+            .iter()
+            .map(|(notification, shown_at)| {
+                let elapsed = (now - *shown_at).as_secs_f32();
+                let duration = notification.duration.as_secs_f32().max(f32::EPSILON);
+
+                VisibleNotification {
+                    text: notification.text.clone(),
+                    level: notification.level,
+                    remaining: (1. - elapsed / duration).clamp(0., 1.),
+                }
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,240 @@
+//! ROM patch formats.
+//!
+//! IPS, UPS and BPS patches next to a ROM, or given explicitly, are applied
+//! in memory when the ROM is loaded, so the file on disk is never modified.
+
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use itertools::Itertools;
+
+use crate::archive;
+
+pub mod bps;
+pub mod bsdiff;
+pub mod ips;
+pub mod ups;
+
+/// Extensions of the soft patch formats, in the order they are applied.
+pub const EXTENSIONS: &[&str] = &["ips", "ups", "bps"];
+
+/// The size patches writing past the end of a ROM may grow it to, bounding
+/// what malformed patches allocate. Far larger than any cartridge.
+const MAX_GROWN_SIZE: usize = 256 * 1024 * 1024;
+
+/// The patches named like the ROM next to it, e.g. `game.ips` for `game.sfc`.
+///
+/// For a ROM in an archive, e.g. `game.zip#inner.sfc`, these are the patches
+/// named like the archive or like the ROM inside it, next to the archive.
+pub fn find_patches(rom: &Path) -> Vec<PathBuf> {
+    // the paths without extension, `with_extension` would cut stems like `Game (v1.1)`
+    let mut stems = vec![rom.with_extension("")];
+
+    if let Some((archive_path, entry)) = archive::split(rom) {
+        stems = vec![archive_path.with_extension("")];
+
+        if let Some(entry_stem) = entry.and_then(|entry| Path::new(entry).file_stem()) {
+            stems.push(archive_path.with_file_name(entry_stem));
+        }
+    }
+
+    EXTENSIONS
+        .iter()
+        .flat_map(|extension| {
+            stems.iter().map(move |stem| {
+                let mut path = stem.clone().into_os_string();
+
+                path.push(".");
+                path.push(extension);
+
+                PathBuf::from(path)
+            })
+        })
+        .unique()
+        .filter(|path| path.is_file())
+        .collect()
+}
+
+/// Applies the patch at `patch_path` to `rom`, detecting the format by its extension.
+pub fn apply(patch_path: &Path, rom: &[u8]) -> Result<Vec<u8>> {
+    let patch = fs::read(patch_path).with_context(|| format!("failed to read {patch_path:?}"))?;
+    let extension = patch_path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_ascii_lowercase)
+        .unwrap_or_default();
+
+    let patched = match extension.as_str() {
+        "ips" => ips::apply(rom, &patch),
+        "ups" => ups::apply(rom, &patch),
+        "bps" => bps::apply(rom, &patch),
+        _ => bail!("unknown patch format of {patch_path:?}"),
+    };
+
+    patched.with_context(|| format!("failed to apply {patch_path:?}"))
+}
+
+/// Writes `data` at `offset`, growing `target` if needed.
+pub(crate) fn write(target: &mut Vec<u8>, offset: usize, data: &[u8]) -> Result<()> {
+    let range = grow(target, offset, data.len())?;
+
+    target[range].copy_from_slice(data);
+
+    Ok(())
+}
+
+/// Fills `len` bytes at `offset` with `value`, growing `target` if needed.
+pub(crate) fn fill(target: &mut Vec<u8>, offset: usize, len: usize, value: u8) -> Result<()> {
+    let range = grow(target, offset, len)?;
+
+    target[range].fill(value);
+
+    Ok(())
+}
+
+/// Grows `target` to include `len` bytes at `offset`, and returns their range.
+fn grow(target: &mut Vec<u8>, offset: usize, len: usize) -> Result<Range<usize>> {
+    let end = offset
+        .checked_add(len)
+        .filter(|&end| end <= target.len() || end <= MAX_GROWN_SIZE)
+        .context("patch writes past the maximum ROM size")?;
+
+    if target.len() < end {
+        target
+            .try_reserve_exact(end - target.len())
+            .context("target size is too large")?;
+        target.resize(end, 0);
+    }
+
+    Ok(offset..end)
+}
+
+/// Reads the fields of a patch, failing on truncated data.
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        let bytes = self
+            .data
+            .get(self.pos..)
+            .and_then(|data| data.get(..len))
+            .context("patch is truncated")?;
+
+        self.pos += len;
+
+        Ok(bytes)
+    }
+
+    fn skip(&mut self, len: usize) -> Result<()> {
+        self.bytes(len).map(|_| ())
+    }
+
+    /// Reads a variable length number, which BPS and UPS encode without redundancy.
+    fn number(&mut self) -> Result<u64> {
+        let mut number = 0u64;
+        let mut shift = 1u64;
+
+        loop {
+            let byte = self.bytes(1)?[0];
+
+            number = u64::from(byte & 0x7F)
+                .checked_mul(shift)
+                .and_then(|value| number.checked_add(value))
+                .context("number overflows")?;
+
+            if byte & 0x80 != 0 {
+                return Ok(number);
+            }
+
+            shift = shift.checked_shl(7).context("number overflows")?;
+            number = number.checked_add(shift).context("number overflows")?;
+        }
+    }
+
+    /// Reads a number whose lowest bit is the sign.
+    fn signed_number(&mut self) -> Result<i64> {
+        let number = self.number()?;
+        let magnitude = i64::try_from(number >> 1)?;
+
+        Ok(if number & 1 != 0 {
+            -magnitude
+        } else {
+            magnitude
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    /// A directory with empty `files`.
+    fn directory(files: &[&str]) -> TempDir {
+        let dir = TempDir::new().unwrap();
+
+        for file in files {
+            fs::write(dir.path().join(file), b"").unwrap();
+        }
+
+        dir
+    }
+
+    #[test]
+    fn finds_patches_named_like_the_rom() {
+        let temp = directory(&["Game (v1.1).sfc", "Game (v1.1).bps", "Game (v1.1).ips"]);
+        let dir = temp.path();
+
+        assert_eq!(
+            find_patches(&dir.join("Game (v1.1).sfc")),
+            [dir.join("Game (v1.1).ips"), dir.join("Game (v1.1).bps")]
+        );
+    }
+
+    #[test]
+    fn finds_patches_of_roms_in_archives() {
+        let temp = directory(&["a.zip", "a.ips", "inner.ups", "inner#x.bps"]);
+        let dir = temp.path();
+
+        assert_eq!(
+            find_patches(Path::new(&format!(
+                "{}#dir/inner.gb",
+                dir.join("a.zip").display()
+            ))),
+            [dir.join("a.ips"), dir.join("inner.ups")]
+        );
+        assert_eq!(find_patches(&dir.join("a.zip")), [dir.join("a.ips")]);
+    }
+
+    #[test]
+    fn writes_and_grows_within_bounds() {
+        let mut target = vec![0; 2];
+
+        write(&mut target, 1, b"AB").unwrap();
+        fill(&mut target, 4, 2, 0xFF).unwrap();
+
+        assert_eq!(target, [0, b'A', b'B', 0, 0xFF, 0xFF]);
+        assert!(fill(&mut target, MAX_GROWN_SIZE, 1, 0).is_err());
+        assert!(write(&mut target, usize::MAX, b"A").is_err());
+        assert_eq!(target.len(), 6);
+    }
+
+    #[test]
+    fn detects_the_format_by_extension() {
+        let temp = directory(&[]);
+        let dir = temp.path();
+        let ips = dir.join("patch.IPS");
+
+        fs::write(&ips, b"PATCH\x00\x00\x01\x00\x01AEOF").unwrap();
+        fs::write(dir.join("patch.txt"), b"").unwrap();
+
+        assert_eq!(apply(&ips, &[0, 0]).unwrap(), [0, b'A']);
+        assert!(apply(&dir.join("patch.txt"), &[0]).is_err());
+    }
+}
@@ -0,0 +1,175 @@
+//! BPS patches (`BPS1`), see <https://www.romhacking.net/documents/746/>.
+
+use anyhow::{bail, ensure, Context, Result};
+
+use super::Reader;
+
+const MAGIC: &[u8] = b"BPS1";
+/// Source, target and patch CRC32.
+const FOOTER_LEN: usize = 12;
+
+const SOURCE_READ: u64 = 0;
+const TARGET_READ: u64 = 1;
+const SOURCE_COPY: u64 = 2;
+const TARGET_COPY: u64 = 3;
+
+pub fn apply(source: &[u8], patch: &[u8]) -> Result<Vec<u8>> {
+    ensure!(
+        patch.len() >= MAGIC.len() + FOOTER_LEN && patch.starts_with(MAGIC),
+        "not a BPS patch"
+    );
+
+    let (body, footer) = patch.split_at(patch.len() - FOOTER_LEN);
+    let footer_crc =
+        |index: usize| u32::from_le_bytes(footer[index * 4..index * 4 + 4].try_into().unwrap());
+
+    ensure!(
+        crc32fast::hash(&patch[..patch.len() - 4]) == footer_crc(2),
+        "patch checksum mismatch, the patch is corrupt"
+    );
+    ensure!(
+        crc32fast::hash(source) == footer_crc(0),
+        "source checksum mismatch, the patch is for a different ROM"
+    );
+
+    let mut reader = Reader {
+        data: body,
+        pos: MAGIC.len(),
+    };
+    let source_len = reader.number()?;
+    let target_len = usize::try_from(reader.number()?)?;
+    let metadata_len = usize::try_from(reader.number()?)?;
+
+    ensure!(
+        source_len == source.len() as u64,
+        "source size mismatch, the patch is for a different ROM"
+    );
+
+    reader.skip(metadata_len)?;
+
+    let mut target = Vec::new();
+
+    target
+        .try_reserve_exact(target_len)
+        .context("target size is too large")?;
+
+    let mut source_offset = 0i64;
+    let mut target_offset = 0i64;
+
+    while reader.pos < body.len() {
+        let action = reader.number()?;
+        let len = usize::try_from((action >> 2) + 1)?;
+
+        ensure!(
+            target
+                .len()
+                .checked_add(len)
+                .is_some_and(|end| end <= target_len),
+            "patch writes past the end of the target"
+        );
+
+        match action & 3 {
+            SOURCE_READ => {
+                let start = target.len();
+                let bytes = source
+                    .get(start..)
+                    .and_then(|source| source.get(..len))
+                    .context("source read out of bounds")?;
+
+                target.extend_from_slice(bytes);
+            }
+            TARGET_READ => target.extend_from_slice(reader.bytes(len)?),
+            SOURCE_COPY => {
+                source_offset = offset(source_offset, reader.signed_number()?)?;
+
+                let start = usize::try_from(source_offset).context("invalid source copy")?;
+                let bytes = source
+                    .get(start..)
+                    .and_then(|source| source.get(..len))
+                    .context("source copy out of bounds")?;
+
+                target.extend_from_slice(bytes);
+                source_offset = offset(source_offset, len as i64)?;
+            }
+            TARGET_COPY => {
+                target_offset = offset(target_offset, reader.signed_number()?)?;
+
+                let start = usize::try_from(target_offset).context("invalid target copy")?;
+                let end = start.checked_add(len).context("invalid target copy")?;
+
+                // copies may overlap the bytes they produce, so go byte by byte
+                for index in start..end {
+                    let byte = *target.get(index).context("target copy out of bounds")?;
+
+                    target.push(byte);
+                }
+
+                target_offset = offset(target_offset, len as i64)?;
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    if target.len() != target_len {
+        bail!(
+            "patch produced {} bytes instead of {target_len}",
+            target.len()
+        );
+    }
+
+    ensure!(
+        crc32fast::hash(&target) == footer_crc(1),
+        "target checksum mismatch"
+    );
+
+    Ok(target)
+}
+
+/// Moves a copy offset by `delta`, failing instead of overflowing.
+fn offset(offset: i64, delta: i64) -> Result<i64> {
+    offset.checked_add(delta).context("copy offset overflows")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &[u8] = include_bytes!("../../tests/fixtures/patch/source.bin");
+    const TARGET: &[u8] = include_bytes!("../../tests/fixtures/patch/target.bin");
+    const PATCH: &[u8] = include_bytes!("../../tests/fixtures/patch/target.bps");
+
+    #[test]
+    fn applies_all_actions() {
+        assert_eq!(apply(SOURCE, PATCH).unwrap(), TARGET);
+    }
+
+    #[test]
+    fn rejects_other_sources() {
+        let err = apply(TARGET, PATCH).unwrap_err();
+
+        assert!(err.to_string().contains("different ROM"), "{err}");
+    }
+
+    #[test]
+    fn rejects_corrupt_patches() {
+        let mut patch = PATCH.to_vec();
+
+        patch[8] ^= 1;
+
+        assert!(apply(SOURCE, &patch).is_err());
+    }
+
+    #[test]
+    fn rejects_oversized_targets() {
+        // checksums are valid, but the target would be larger than memory
+        let mut patch = b"BPS1\xC0\x7F\x7F\x7F\x7F\x7F\x7F\x7F\xBF\x80".to_vec();
+
+        patch.extend_from_slice(&crc32fast::hash(SOURCE).to_le_bytes());
+        patch.extend_from_slice(&0u32.to_le_bytes());
+        patch.extend_from_slice(&crc32fast::hash(&patch).to_le_bytes());
+
+        let err = apply(SOURCE, &patch).unwrap_err();
+
+        assert!(err.to_string().contains("too large"), "{err}");
+    }
+}
@@ -0,0 +1,151 @@
+//! bsdiff 4 (`BSDIFF40`) patches, as produced by Python's `bsdiff4`.
+
+use std::io::Read;
+
+use anyhow::{bail, ensure, Context, Result};
+use bzip2_rs::DecoderReader;
+
+const MAGIC: &[u8] = b"BSDIFF40";
+const HEADER_LEN: usize = 32;
+
+pub fn apply(source: &[u8], patch: &[u8]) -> Result<Vec<u8>> {
+    ensure!(
+        patch.len() >= HEADER_LEN && patch.starts_with(MAGIC),
+        "not a bsdiff4 patch"
+    );
+
+    let control_len = usize::try_from(read_offset(&patch[8..16])).context("invalid header")?;
+    let diff_len = usize::try_from(read_offset(&patch[16..24])).context("invalid header")?;
+    let target_len = usize::try_from(read_offset(&patch[24..32])).context("invalid header")?;
+
+    let control_start = HEADER_LEN;
+    let diff_start = control_start
+        .checked_add(control_len)
+        .context("invalid header")?;
+    let extra_start = diff_start.checked_add(diff_len).context("invalid header")?;
+
+    ensure!(extra_start <= patch.len(), "patch is truncated");
+
+    let control = decompress(&patch[control_start..diff_start]).context("invalid control block")?;
+    let diff = decompress(&patch[diff_start..extra_start]).context("invalid diff block")?;
+    let extra = decompress(&patch[extra_start..]).context("invalid extra block")?;
+
+    // the header isn't checksummed, so don't trust its size with an infallible allocation
+    let mut target = Vec::new();
+
+    target
+        .try_reserve_exact(target_len)
+        .context("target size is too large")?;
+
+    let mut source_pos = 0i64;
+    let mut diff = diff.iter();
+    let mut extra = extra.iter();
+
+    for control in control.chunks_exact(24) {
+        let add_len = read_len(&control[0..8])?;
+        let copy_len = read_len(&control[8..16])?;
+        let seek = read_offset(&control[16..24]);
+
+        let end = target
+            .len()
+            .checked_add(add_len)
+            .and_then(|end| end.checked_add(copy_len));
+
+        ensure!(
+            end.is_some_and(|end| end <= target_len),
+            "patch writes past the end of the target"
+        );
+
+        for _ in 0..add_len {
+            let diff = *diff.next().context("diff block is truncated")?;
+            let source = usize::try_from(source_pos)
+                .ok()
+                .and_then(|pos| source.get(pos))
+                .copied()
+                .unwrap_or(0);
+
+            target.push(source.wrapping_add(diff));
+            source_pos += 1;
+        }
+
+        for _ in 0..copy_len {
+            target.push(*extra.next().context("extra block is truncated")?);
+        }
+
+        source_pos = source_pos
+            .checked_add(seek)
+            .context("invalid seek in control block")?;
+    }
+
+    if target.len() != target_len {
+        bail!(
+            "patch produced {} bytes instead of {target_len}",
+            target.len()
+        );
+    }
+
+    Ok(target)
+}
+
+fn decompress(data: &[u8]) -> Result<Vec<u8>> {
+    let mut decompressed = Vec::new();
+
+    DecoderReader::new(data).read_to_end(&mut decompressed)?;
+
+    Ok(decompressed)
+}
+
+/// Reads bsdiff's sign-magnitude 64 bit integer.
+fn read_offset(bytes: &[u8]) -> i64 {
+    let mut bytes: [u8; 8] = bytes.try_into().expect("offsets are 8 bytes");
+    let is_negative = bytes[7] & 0x80 != 0;
+
+    bytes[7] &= 0x7F;
+
+    let magnitude = i64::from_le_bytes(bytes);
+
+    if is_negative {
+        -magnitude
+    } else {
+        magnitude
+    }
+}
+
+fn read_len(bytes: &[u8]) -> Result<usize> {
+    usize::try_from(read_offset(bytes)).context("negative length in control block")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &[u8] = include_bytes!("../../tests/fixtures/patch/source.bin");
+    const TARGET: &[u8] = include_bytes!("../../tests/fixtures/patch/target.bin");
+    const PATCH: &[u8] = include_bytes!("../../tests/fixtures/patch/target.bsdiff4");
+
+    #[test]
+    fn applies_diff_and_extra_blocks() {
+        assert_eq!(apply(SOURCE, PATCH).unwrap(), TARGET);
+    }
+
+    #[test]
+    fn reads_sign_magnitude_offsets() {
+        assert_eq!(read_offset(&[5, 0, 0, 0, 0, 0, 0, 0]), 5);
+        assert_eq!(read_offset(&[5, 0, 0, 0, 0, 0, 0, 0x80]), -5);
+    }
+
+    #[test]
+    fn rejects_oversized_targets() {
+        let mut patch = PATCH.to_vec();
+
+        patch[24..32].copy_from_slice(&i64::MAX.to_le_bytes());
+
+        assert!(apply(SOURCE, &patch).is_err());
+    }
+
+    #[test]
+    fn rejects_truncated_patches() {
+        assert!(apply(SOURCE, &PATCH[..40]).is_err());
+        assert!(apply(SOURCE, b"BSDIFF40").is_err());
+    }
+}
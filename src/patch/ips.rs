@@ -0,0 +1,82 @@
+//! IPS patches (`PATCH`), including run length encoded records and the
+//! truncation extension.
+
+use anyhow::{ensure, Result};
+
+use super::{fill, write, Reader};
+
+const MAGIC: &[u8] = b"PATCH";
+const EOF: &[u8] = b"EOF";
+
+pub fn apply(source: &[u8], patch: &[u8]) -> Result<Vec<u8>> {
+    ensure!(patch.starts_with(MAGIC), "not an IPS patch");
+
+    let mut reader = Reader {
+        data: patch,
+        pos: MAGIC.len(),
+    };
+    let mut target = source.to_vec();
+
+    loop {
+        let offset = reader.bytes(3)?;
+
+        if offset == EOF {
+            break;
+        }
+
+        let offset = be_number(offset);
+        let len = be_number(reader.bytes(2)?);
+
+        if len == 0 {
+            let len = be_number(reader.bytes(2)?);
+            let value = reader.bytes(1)?[0];
+
+            fill(&mut target, offset, len, value)?;
+        } else {
+            write(&mut target, offset, reader.bytes(len)?)?;
+        }
+    }
+
+    // some patchers append the size to truncate the target to
+    if let Ok(len) = reader.bytes(3) {
+        target.truncate(be_number(len));
+    }
+
+    Ok(target)
+}
+
+fn be_number(bytes: &[u8]) -> usize {
+    bytes
+        .iter()
+        .fold(0, |number, &byte| number << 8 | usize::from(byte))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &[u8] = include_bytes!("../../tests/fixtures/patch/source.bin");
+    const TARGET: &[u8] = include_bytes!("../../tests/fixtures/patch/target.bin");
+    const PATCH: &[u8] = include_bytes!("../../tests/fixtures/patch/target.ips");
+
+    #[test]
+    fn applies_records_rle_and_truncation() {
+        assert_eq!(apply(SOURCE, PATCH).unwrap(), TARGET);
+    }
+
+    #[test]
+    fn grows_the_target() {
+        let patch = b"PATCH\x00\x00\x42\x00\x02ABEOF";
+        let target = apply(&[1, 2], patch).unwrap();
+
+        assert_eq!(target.len(), 0x44);
+        assert_eq!(target[..2], [1, 2]);
+        assert_eq!(target[0x42..], *b"AB");
+    }
+
+    #[test]
+    fn rejects_invalid_patches() {
+        assert!(apply(SOURCE, b"PACH").is_err());
+        assert!(apply(SOURCE, &PATCH[..12]).is_err());
+    }
+}
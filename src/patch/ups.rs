@@ -0,0 +1,111 @@
+//! UPS patches (`UPS1`), XOR deltas between the source and the target.
+
+use anyhow::{ensure, Context, Result};
+
+use super::Reader;
+
+const MAGIC: &[u8] = b"UPS1";
+/// Source, target and patch CRC32.
+const FOOTER_LEN: usize = 12;
+
+pub fn apply(source: &[u8], patch: &[u8]) -> Result<Vec<u8>> {
+    ensure!(
+        patch.len() >= MAGIC.len() + FOOTER_LEN && patch.starts_with(MAGIC),
+        "not a UPS patch"
+    );
+
+    let (body, footer) = patch.split_at(patch.len() - FOOTER_LEN);
+    let footer_crc =
+        |index: usize| u32::from_le_bytes(footer[index * 4..index * 4 + 4].try_into().unwrap());
+
+    ensure!(
+        crc32fast::hash(&patch[..patch.len() - 4]) == footer_crc(2),
+        "patch checksum mismatch, the patch is corrupt"
+    );
+    ensure!(
+        crc32fast::hash(source) == footer_crc(0),
+        "source checksum mismatch, the patch is for a different ROM"
+    );
+
+    let mut reader = Reader {
+        data: body,
+        pos: MAGIC.len(),
+    };
+    let source_len = reader.number()?;
+    let target_len = usize::try_from(reader.number()?)?;
+
+    ensure!(
+        source_len == source.len() as u64,
+        "source size mismatch, the patch is for a different ROM"
+    );
+
+    // bytes past the end of the source read as zero
+    let mut target = source.to_vec();
+
+    // the header isn't verified until the end, so don't trust its size with an infallible allocation
+    target
+        .try_reserve_exact(target_len.saturating_sub(target.len()))
+        .context("target size is too large")?;
+    target.resize(target_len, 0);
+
+    let mut pos = 0usize;
+
+    while reader.pos < body.len() {
+        pos = pos
+            .checked_add(usize::try_from(reader.number()?)?)
+            .context("patch skips past the end of the target")?;
+
+        // XOR until a zero byte, which ends the run and skips its own position
+        loop {
+            let byte = reader.bytes(1)?[0];
+
+            if byte == 0 {
+                pos = pos.saturating_add(1);
+                break;
+            }
+
+            if let Some(target_byte) = target.get_mut(pos) {
+                *target_byte ^= byte;
+            }
+
+            pos = pos.saturating_add(1);
+        }
+    }
+
+    ensure!(
+        crc32fast::hash(&target) == footer_crc(1),
+        "target checksum mismatch"
+    );
+
+    Ok(target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &[u8] = include_bytes!("../../tests/fixtures/patch/source.bin");
+    const TARGET: &[u8] = include_bytes!("../../tests/fixtures/patch/target.bin");
+    const PATCH: &[u8] = include_bytes!("../../tests/fixtures/patch/target.ups");
+
+    #[test]
+    fn applies_xor_runs() {
+        assert_eq!(apply(SOURCE, PATCH).unwrap(), TARGET);
+    }
+
+    #[test]
+    fn rejects_other_sources() {
+        let err = apply(TARGET, PATCH).unwrap_err();
+
+        assert!(err.to_string().contains("different ROM"), "{err}");
+    }
+
+    #[test]
+    fn rejects_corrupt_patches() {
+        let mut patch = PATCH.to_vec();
+
+        patch[6] ^= 1;
+
+        assert!(apply(SOURCE, &patch).is_err());
+    }
+}
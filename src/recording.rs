@@ -0,0 +1,272 @@
+//! Recording video and audio to uncompressed Y4M and WAV files.
+//!
+//! Frames and samples are recorded as the core produces them, so recordings
+//! play back at emulated speed regardless of fast-forward or slowdown. Every
+//! `retro_run` produces exactly one video frame, duplicated frames included,
+//! which keeps video in sync with the audio the core generates for it.
+
+use std::fs::{self, File};
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+use crate::video::Frame;
+use crate::{config, util};
+
+/// `ape` in the user's videos directory, if it is known.
+pub fn default_directory() -> PathBuf {
+    dirs::video_dir()
+        .map(|videos| videos.join("ape"))
+        .unwrap_or_else(|| config::data_directory().join("recordings"))
+}
+
+/// A recording in progress.
+///
+/// Write errors stop the recording and are reported by [`Recording::finish`].
+pub struct Recording {
+    video: Y4mWriter,
+    audio: WavWriter,
+    error: Option<anyhow::Error>,
+}
+
+/// The files a finished recording was written to.
+pub struct RecordingFiles {
+    pub video: PathBuf,
+    pub audio: PathBuf,
+}
+
+impl Recording {
+    /// Starts recording to `directory`, into files named after the ROM and the current time.
+    ///
+    /// WAV only supports integer sample rates, so `sample_rate` is rounded.
+    /// The resulting drift is well below a frame per minute for all common rates.
+    ///
+    /// `fps` and `sample_rate` are fixed for the whole recording. ape rejects
+    /// `SET_SYSTEM_AV_INFO`, so cores can't change them while running.
+    pub fn start(
+        directory: &Path,
+        rom_path: Option<&Path>,
+        fps: f64,
+        sample_rate: f64,
+    ) -> Result<Self> {
+        fs::create_dir_all(directory)
+            .with_context(|| format!("failed to create recording directory {directory:?}"))?;
+
+        let name = rom_path
+            .and_then(Path::file_stem)
+            .map(|name| name.to_string_lossy())
+            .unwrap_or("recording".into());
+        let timestamp = util::timestamp();
+        let video_path = directory.join(format!("{name}-{timestamp}.y4m"));
+        let audio_path = directory.join(format!("{name}-{timestamp}.wav"));
+
+        Ok(Self {
+            video: Y4mWriter::create(video_path, fps)?,
+            audio: WavWriter::create(audio_path, sample_rate.round() as u32)?,
+            error: None,
+        })
+    }
+
+    /// Records the frame of one `retro_run`, `None` repeats the previous frame.
+    pub fn video_frame(&mut self, frame: Option<&Frame>) {
+        if self.error.is_none() {
+            self.error = self.video.write_frame(frame).err();
+        }
+    }
+
+    /// Records interleaved stereo samples.
+    pub fn audio_samples(&mut self, samples: &[i16]) {
+        if self.error.is_none() {
+            self.error = self.audio.write_samples(samples).err();
+        }
+    }
+
+    pub fn finish(self) -> Result<RecordingFiles> {
+        if let Some(err) = self.error {
+            return Err(err.context("recording failed"));
+        }
+
+        Ok(RecordingFiles {
+            video: self.video.finish()?,
+            audio: self.audio.finish()?,
+        })
+    }
+}
+
+/// Writes YUV4MPEG2 with 4:4:4 BT.601 limited range frames.
+///
+/// Y4M streams have a fixed size, the size of the first frame. Frames of a
+/// different size, e.g. after a core switched resolution, are scaled to it.
+struct Y4mWriter {
+    path: PathBuf,
+    file: BufWriter<File>,
+    fps: f64,
+    size: Option<(usize, usize)>,
+    /// The previous frame converted to planar YUV, repeated for duplicated frames.
+    last_frame: Vec<u8>,
+}
+
+impl Y4mWriter {
+    fn create(path: PathBuf, fps: f64) -> Result<Self> {
+        let file = File::create(&path).with_context(|| format!("failed to create {path:?}"))?;
+
+        Ok(Self {
+            path,
+            file: BufWriter::new(file),
+            fps,
+            size: None,
+            last_frame: Vec::new(),
+        })
+    }
+
+    fn write_frame(&mut self, frame: Option<&Frame>) -> Result<()> {
+        if let Some(frame) = frame {
+            let (width, height) = match self.size {
+                Some(size) => size,
+                None => self.write_header(frame.width, frame.height)?,
+            };
+
+            self.last_frame = to_yuv444(frame, width, height);
+        }
+
+        // nothing to repeat before the first frame
+        if self.last_frame.is_empty() {
+            return Ok(());
+        }
+
+        self.file.write_all(b"FRAME\n")?;
+        self.file.write_all(&self.last_frame)?;
+
+        Ok(())
+    }
+
+    fn write_header(&mut self, width: usize, height: usize) -> Result<(usize, usize)> {
+        // Y4M wants a rational frame rate, millihertz are precise enough
+        let fps_numerator = (self.fps * 1000.).round() as u64;
+
+        writeln!(
+            self.file,
+            "YUV4MPEG2 W{width} H{height} F{fps_numerator}:1000 Ip A1:1 C444"
+        )?;
+
+        self.size = Some((width, height));
+
+        Ok((width, height))
+    }
+
+    fn finish(mut self) -> Result<PathBuf> {
+        self.file
+            .flush()
+            .with_context(|| format!("failed to write {:?}", self.path))?;
+
+        Ok(self.path)
+    }
+}
+
+/// Converts `frame` to planar 4:4:4 YUV of the given size, scaling with nearest neighbour.
+fn to_yuv444(frame: &Frame, width: usize, height: usize) -> Vec<u8> {
+    let pixels = frame.buffer_to_packed_rgb888();
+    let plane_len = width * height;
+    let mut yuv = vec![0; plane_len * 3];
+
+    if frame.width == 0 || frame.height == 0 {
+        return yuv;
+    }
+
+    for y in 0..height {
+        let source_y = y * frame.height / height;
+
+        for x in 0..width {
+            let source_x = x * frame.width / width;
+            let offset = (source_y * frame.width + source_x) * 3;
+            let [r, g, b] = [0, 1, 2].map(|i| f32::from(pixels[offset + i]));
+            let index = y * width + x;
+
+            yuv[index] = (16. + 0.257 * r + 0.504 * g + 0.098 * b).round() as u8;
+            yuv[plane_len + index] = (128. - 0.148 * r - 0.291 * g + 0.439 * b).round() as u8;
+            yuv[plane_len * 2 + index] = (128. + 0.439 * r - 0.368 * g - 0.071 * b).round() as u8;
+        }
+    }
+
+    yuv
+}
+
+/// Writes 16 bit stereo PCM WAV, the format libretro cores produce.
+struct WavWriter {
+    path: PathBuf,
+    file: BufWriter<File>,
+    data_len: u32,
+}
+
+impl WavWriter {
+    const NUM_CHANNELS: u16 = 2;
+    const BITS_PER_SAMPLE: u16 = 16;
+    const HEADER_LEN: u32 = 44;
+
+    fn create(path: PathBuf, sample_rate: u32) -> Result<Self> {
+        let file = File::create(&path).with_context(|| format!("failed to create {path:?}"))?;
+        let mut writer = Self {
+            path,
+            file: BufWriter::new(file),
+            data_len: 0,
+        };
+
+        writer.write_header(sample_rate)?;
+
+        Ok(writer)
+    }
+
+    fn write_header(&mut self, sample_rate: u32) -> Result<()> {
+        let block_align = Self::NUM_CHANNELS * Self::BITS_PER_SAMPLE / 8;
+        let byte_rate = sample_rate * u32::from(block_align);
+        let file = &mut self.file;
+
+        file.write_all(b"RIFF")?;
+        // RIFF and data chunk lengths are filled in by `finish`
+        file.write_all(&0u32.to_le_bytes())?;
+        file.write_all(b"WAVEfmt ")?;
+        file.write_all(&16u32.to_le_bytes())?;
+        // PCM
+        file.write_all(&1u16.to_le_bytes())?;
+        file.write_all(&Self::NUM_CHANNELS.to_le_bytes())?;
+        file.write_all(&sample_rate.to_le_bytes())?;
+        file.write_all(&byte_rate.to_le_bytes())?;
+        file.write_all(&block_align.to_le_bytes())?;
+        file.write_all(&Self::BITS_PER_SAMPLE.to_le_bytes())?;
+        file.write_all(b"data")?;
+        file.write_all(&0u32.to_le_bytes())?;
+
+        Ok(())
+    }
+
+    fn write_samples(&mut self, samples: &[i16]) -> Result<()> {
+        for sample in samples {
+            self.file.write_all(&sample.to_le_bytes())?;
+        }
+
+        self.data_len = self
+            .data_len
+            .saturating_add((samples.len() * 2).try_into().unwrap_or(u32::MAX));
+
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<PathBuf> {
+        let riff_len = (Self::HEADER_LEN - 8).saturating_add(self.data_len);
+        let res = (|| -> Result<()> {
+            self.file.seek(SeekFrom::Start(4))?;
+            self.file.write_all(&riff_len.to_le_bytes())?;
+            self.file
+                .seek(SeekFrom::Start(u64::from(Self::HEADER_LEN) - 4))?;
+            self.file.write_all(&self.data_len.to_le_bytes())?;
+            self.file.flush()?;
+
+            Ok(())
+        })();
+
+        res.with_context(|| format!("failed to write {:?}", self.path))?;
+
+        Ok(self.path)
+    }
+}
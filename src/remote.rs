@@ -1,25 +1,100 @@
 use std::fmt::Write;
-use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::path::{Path, PathBuf};
 use std::{str, thread};
 
-use anyhow::{anyhow, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 
 use itertools::Itertools;
 
 use crate::core;
+use crate::memory_domain;
+use crate::osd::Notifications;
+use crate::replay;
+use crate::screenshot;
 
-pub fn start(core_handle: core::Handle) {
+pub const DEFAULT_PORT: u16 = 55355;
+
+/// Largest payload that fits into a single UDP datagram.
+const MAX_DATAGRAM_LEN: usize = 65507;
+
+pub struct Config {
+    pub address: SocketAddr,
+    /// Allows binding to non-loopback addresses.
+    pub allow_lan: bool,
+    /// Non-loopback peers that are allowed to talk to the remote interface.
+    pub allowlist: Vec<IpAddr>,
+    /// Where `SCREENSHOT` saves screenshots.
+    pub screenshot_directory: PathBuf,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            address: SocketAddr::from((Ipv4Addr::LOCALHOST, DEFAULT_PORT)),
+            allow_lan: false,
+            allowlist: Vec::new(),
+            screenshot_directory: screenshot::default_directory(),
+        }
+    }
+}
+
+impl Config {
+    /// Rejects settings that would expose the interface, or silently not apply.
+    pub fn validate(&self) -> Result<()> {
+        if self.allow_lan {
+            return Ok(());
+        }
+
+        if !self.address.ip().to_canonical().is_loopback() {
+            bail!(
+                "refusing to listen on non-loopback address {} without enabling LAN access",
+                self.address
+            );
+        }
+
+        if !self.allowlist.is_empty() {
+            bail!("the remote allowlist only applies with LAN access enabled");
+        }
+
+        Ok(())
+    }
+
+    fn is_peer_allowed(&self, peer: IpAddr) -> bool {
+        let peer = peer.to_canonical();
+
+        if peer.is_loopback() {
+            return true;
+        }
+
+        self.allow_lan
+            && self
+                .allowlist
+                .iter()
+                .any(|allowed| allowed.to_canonical() == peer)
+    }
+}
+
+pub fn start(core_handle: core::Handle, config: Config, notifications: Notifications) {
     thread::spawn(move || {
-        if let Err(err) = try_start(core_handle) {
+        if let Err(err) = try_start(core_handle, config, notifications) {
             eprintln!("remote interface stopped with error: {err:#?}");
         }
     });
 }
 
-fn try_start(core_handle: core::Handle) -> Result<()> {
-    let socket =
-        UdpSocket::bind((Ipv4Addr::LOCALHOST, 55355)).context("failed to create socket")?;
-    let msg = &mut [0; 2048];
+fn try_start(
+    core_handle: core::Handle,
+    config: Config,
+    notifications: Notifications,
+) -> Result<()> {
+    config.validate()?;
+
+    let socket = UdpSocket::bind(config.address)
+        .with_context(|| format!("failed to create socket on {}", config.address))?;
+    let msg = &mut [0; MAX_DATAGRAM_LEN];
+
+    eprintln!("remote interface listening on {}", config.address);
 
     loop {
         let (len, sockaddr) = socket
@@ -27,7 +102,19 @@ fn try_start(core_handle: core::Handle) -> Result<()> {
             .context("remote: failed to recv message")?;
         let msg = &msg[..len];
 
-        if let Err(err) = handle_message(&core_handle, &socket, sockaddr, msg) {
+        if !config.is_peer_allowed(sockaddr.ip()) {
+            eprintln!("remote: ignoring message from {sockaddr}: peer not allowed");
+            continue;
+        }
+
+        if let Err(err) = handle_message(
+            &core_handle,
+            &notifications,
+            &config,
+            &socket,
+            sockaddr,
+            msg,
+        ) {
             eprintln!("remote: failed to handle message: {err:?}")
         }
     }
@@ -35,6 +122,8 @@ fn try_start(core_handle: core::Handle) -> Result<()> {
 
 fn handle_message(
     core_handle: &core::Handle,
+    notifications: &Notifications,
+    config: &Config,
     socket: &UdpSocket,
     reply_addr: SocketAddr,
     msg: &[u8],
@@ -46,6 +135,8 @@ fn handle_message(
     let command = parts.next().context("received message without command")?;
     let context = CommandContext {
         core_handle,
+        notifications,
+        screenshot_directory: &config.screenshot_directory,
         socket,
         reply_addr,
         args: &mut parts,
@@ -58,6 +149,8 @@ fn handle_message(
 
 struct CommandContext<'a, I> {
     core_handle: &'a core::Handle,
+    notifications: &'a Notifications,
+    screenshot_directory: &'a Path,
     socket: &'a UdpSocket,
     reply_addr: SocketAddr,
     args: &'a mut I,
@@ -83,12 +176,42 @@ where
             "GET_STATUS" => self
                 .handle_get_status()
                 .context("failed to handle GET_STATUS command")?,
+            "SHOW_MSG" => self
+                .handle_show_msg()
+                .context("failed to handle SHOW_MSG command")?,
+            "PAUSE_TOGGLE" => self
+                .handle_pause_toggle()
+                .context("failed to handle PAUSE_TOGGLE command")?,
+            "SCREENSHOT" => self
+                .handle_screenshot()
+                .context("failed to handle SCREENSHOT command")?,
+            "SAVE_REPLAY" => self
+                .handle_save_replay()
+                .context("failed to handle SAVE_REPLAY command")?,
+            "RESET" => self
+                .handle_reset()
+                .context("failed to handle RESET command")?,
             "READ_CORE_MEMORY" => self
                 .handle_read_core_memory()
                 .context("failed to handle READ_CORE_MEMORY command")?,
             "WRITE_CORE_MEMORY" => self
                 .handle_write_core_memory()
                 .context("failed to handle WRITE_CORE_MEMORY command")?,
+            "MEMORY_MAP_SOURCE" => self
+                .handle_memory_map_source()
+                .context("failed to handle MEMORY_MAP_SOURCE command")?,
+            "MEMORY_DOMAINS" => self
+                .handle_memory_domains()
+                .context("failed to handle MEMORY_DOMAINS command")?,
+            "MEMORY_SIZE" => self
+                .handle_memory_size()
+                .context("failed to handle MEMORY_SIZE command")?,
+            "READ_DOMAIN_MEMORY" => self
+                .handle_read_domain_memory()
+                .context("failed to handle READ_DOMAIN_MEMORY command")?,
+            "WRITE_DOMAIN_MEMORY" => self
+                .handle_write_domain_memory()
+                .context("failed to handle WRITE_DOMAIN_MEMORY command")?,
             _ => {
                 eprintln!("unknown command `{command:?}`");
             }
@@ -102,58 +225,276 @@ where
     }
 
     fn handle_get_status(self) -> Result<()> {
-        let system_info = self
-            .core_handle
-            .run(|core| core.get_system_info().to_owned())?;
+        let (system_info, rom_path, crc32, paused) = self.core_handle.run(|core| {
+            (
+                core.get_system_info().to_owned(),
+                core.rom_path(),
+                core.get_crc32_romhash(),
+                core.is_paused(),
+            )
+        })?;
+
+        let Some(rom_path) = rom_path else {
+            return self.reply("GET_STATUS CONTENTLESS\n");
+        };
 
+        let status = if paused { "PAUSED" } else { "PLAYING" };
         let system_id = system_info.system_id.unwrap_or(&system_info.library_name);
+        let content_name = rom_path
+            .file_stem()
+            .map(|name| name.to_string_lossy())
+            .unwrap_or_default();
 
         self.reply(format!(
-            "GET_STATUS PLAYING {system_id},TODO_romname,TODO_hash\n"
+            "GET_STATUS {status} {system_id},{content_name},crc32={crc32:x}\n"
         ))
     }
 
+    fn handle_show_msg(self) -> Result<()> {
+        let message = self.args.join(" ");
+
+        self.notifications.info(message);
+
+        Ok(())
+    }
+
+    fn handle_pause_toggle(self) -> Result<()> {
+        self.core_handle.run(|core| {
+            let paused = core.is_paused();
+
+            core.set_paused(!paused);
+        })?;
+
+        Ok(())
+    }
+
+    fn handle_screenshot(self) -> Result<()> {
+        let (png, rom_path) = self
+            .core_handle
+            .run(|core| (core.screenshot(), core.rom_path()))?;
+        let path = screenshot::save(self.screenshot_directory, rom_path.as_deref(), &png?)?;
+
+        self.notifications
+            .info(format!("Screenshot saved to {}", path.display()));
+
+        Ok(())
+    }
+
+    /// `SAVE_REPLAY [SECONDS]` saves the instant replay buffer as an animated PNG.
+    fn handle_save_replay(self) -> Result<()> {
+        let seconds = self
+            .args
+            .next()
+            .map(str::parse::<f64>)
+            .transpose()
+            .context("invalid seconds format")?;
+        let (replay, rom_path) = self
+            .core_handle
+            .run(|core| (core.replay(), core.rom_path()))?;
+        let replay = replay.context("instant replay is disabled")?;
+        let png = replay.save(seconds)?;
+        let path = replay::save_to(self.screenshot_directory, rom_path.as_deref(), &png)?;
+
+        self.notifications
+            .info(format!("Replay saved to {}", path.display()));
+
+        Ok(())
+    }
+
+    fn handle_reset(self) -> Result<()> {
+        self.core_handle.run(|core| core.reset())?;
+
+        Ok(())
+    }
+
     fn handle_read_core_memory(self) -> Result<()> {
         let (address_str, len) = self.args.next_tuple().context("invalid number of args")?;
-        let address = address_str.strip_prefix("0x").unwrap_or(address_str);
-        let address = usize::from_str_radix(address, 16).context("invalid address format")?;
+        let address = parse_address(address_str)?;
+        let header = format!("READ_CORE_MEMORY {address_str}");
         let len = len.parse::<usize>().context("invalid len format")?;
-        let mut msg =
-            String::with_capacity("READ_CORE_MEMORY ".len() + address_str.len() + len * 3 + 1);
+        let len = len.min(max_read_len(&header));
 
-        let mem = self
-            .core_handle
-            .run(move |core| core.get_memory(address, len).to_vec())?;
+        let mem = self.core_handle.run(move |core| {
+            if !core.has_memory_map() {
+                return Err("no memory map defined");
+            }
 
-        msg.push_str("READ_CORE_MEMORY ");
-        msg.push_str(address_str);
+            let mem = core.get_memory(address, len);
 
-        for byte in mem {
-            write!(msg, " {byte:02X}").ok();
-        }
+            if mem.is_empty() && len > 0 {
+                return Err("no descriptor for address");
+            }
 
-        msg.push('\n');
+            Ok(mem)
+        })?;
 
-        self.reply(msg)
+        self.reply(format_read_reply(header, mem))
     }
 
     fn handle_write_core_memory(self) -> Result<()> {
         let address_str = self.args.next().context("invalid number of args")?;
-        let address = address_str.strip_prefix("0x").unwrap_or(address_str);
-        let address = usize::from_str_radix(address, 16).context("invalid address format")?;
-        let bytes = self
+        let address = parse_address(address_str)?;
+        let bytes = parse_bytes(&mut *self.args)?;
+
+        let bytes_written = self.core_handle.run(move |core| {
+            if !core.has_memory_map() {
+                return Err("no memory map defined");
+            }
+
+            let bytes_written = core.write_memory(address, &bytes);
+
+            if bytes_written == 0 && !bytes.is_empty() {
+                if core.is_memory_read_only(address) {
+                    return Err("descriptor data is readonly");
+                }
+
+                return Err("no descriptor for address");
+            }
+
+            Ok(bytes_written)
+        })?;
+
+        self.reply(format_write_reply(
+            format!("WRITE_CORE_MEMORY {address_str}"),
+            bytes_written,
+        ))
+    }
+
+    /// Replies with where system bus accesses are served from:
+    /// `core`, `regions` or `none`.
+    fn handle_memory_map_source(self) -> Result<()> {
+        let source = self.core_handle.run(|core| core.memory_map_source())?;
+        let source: &str = source.into();
+
+        self.reply(format!("MEMORY_MAP_SOURCE {source}\n"))
+    }
+
+    fn handle_memory_domains(self) -> Result<()> {
+        let domains = self.core_handle.run(|core| {
+            memory_domain::domains(core)
+                .iter()
+                .map(|domain| domain.name())
+                .join(",")
+        })?;
+
+        self.reply(format!("MEMORY_DOMAINS {domains}\n"))
+    }
+
+    fn handle_memory_size(self) -> Result<()> {
+        let domain_name = self
             .args
-            .map(|byte| {
-                let byte = byte.strip_prefix("0x").unwrap_or(byte);
-                u8::from_str_radix(byte, 16).map_err(|err| anyhow!("invalid byte `{byte}` {err:?}"))
-            })
-            .collect::<Result<Vec<_>>>()
-            .context("invalid byte format")?;
-
-        let bytes_written = self
-            .core_handle
-            .run(move |core| core.write_memory(address, &bytes))?;
+            .next()
+            .context("invalid number of args")?
+            .to_owned();
+        let header = format!("MEMORY_SIZE {domain_name}");
+
+        let size = self.core_handle.run(move |core| {
+            let domain = memory_domain::find(core, &domain_name).ok_or("unknown memory domain")?;
+
+            Ok::<_, &str>(domain.size(core))
+        })?;
+
+        match size {
+            Ok(size) => self.reply(format!("{header} {size}\n")),
+            Err(err) => self.reply(format!("{header} -1 {err}\n")),
+        }
+    }
+
+    fn handle_read_domain_memory(self) -> Result<()> {
+        let (domain_name, address_str, len) =
+            self.args.next_tuple().context("invalid number of args")?;
+        let domain_name = domain_name.to_owned();
+        let address = parse_address(address_str)?;
+        let header = format!("READ_DOMAIN_MEMORY {domain_name} {address_str}");
+        let len = len.parse::<usize>().context("invalid len format")?;
+        let len = len.min(max_read_len(&header));
+
+        let mem = self.core_handle.run(move |core| {
+            let domain = memory_domain::find(core, &domain_name).ok_or("unknown memory domain")?;
+            let mem = domain.read(core, address, len);
+
+            if mem.is_empty() && len > 0 {
+                return Err("address out of range");
+            }
+
+            Ok(mem)
+        })?;
+
+        self.reply(format_read_reply(header, mem))
+    }
+
+    fn handle_write_domain_memory(self) -> Result<()> {
+        let (domain_name, address_str) =
+            self.args.next_tuple().context("invalid number of args")?;
+        let domain_name = domain_name.to_owned();
+        let address = parse_address(address_str)?;
+        let header = format!("WRITE_DOMAIN_MEMORY {domain_name} {address_str}");
+        let bytes = parse_bytes(&mut *self.args)?;
+
+        let bytes_written = self.core_handle.run(move |core| {
+            let domain = memory_domain::find(core, &domain_name).ok_or("unknown memory domain")?;
+
+            if !domain.is_writable() {
+                return Err("memory domain is read-only");
+            }
+
+            let bytes_written = domain.write(core, address, &bytes);
+
+            if bytes_written == 0 && !bytes.is_empty() {
+                return Err("address out of range");
+            }
+
+            Ok(bytes_written)
+        })?;
+
+        self.reply(format_write_reply(header, bytes_written))
+    }
+}
+
+/// Reads are clamped so that the reply (`header` followed by ` XX` per byte)
+/// still fits into a single datagram.
+fn max_read_len(header: &str) -> usize {
+    MAX_DATAGRAM_LEN.saturating_sub(header.len() + 1) / 3
+}
+
+fn parse_address(address: &str) -> Result<usize> {
+    let address = address.strip_prefix("0x").unwrap_or(address);
+    let address = usize::from_str_radix(address, 16).context("invalid address format")?;
+
+    Ok(address)
+}
+
+fn parse_bytes<'a>(bytes: impl Iterator<Item = &'a str>) -> Result<Vec<u8>> {
+    bytes
+        .map(|byte| {
+            let byte = byte.strip_prefix("0x").unwrap_or(byte);
+            u8::from_str_radix(byte, 16).map_err(|err| anyhow!("invalid byte `{byte}` {err:?}"))
+        })
+        .collect::<Result<Vec<_>>>()
+        .context("invalid byte format")
+}
+
+fn format_read_reply(header: String, mem: Result<Vec<u8>, &str>) -> String {
+    let mem = match mem {
+        Ok(mem) => mem,
+        Err(err) => return format!("{header} -1 {err}\n"),
+    };
+
+    let mut msg = header;
+    msg.reserve(mem.len() * 3 + 1);
+
+    for byte in mem {
+        write!(msg, " {byte:02X}").ok();
+    }
+
+    msg.push('\n');
+
+    msg
+}
 
-        self.reply(format!("WRITE_CORE_MEMORY {address_str} {bytes_written}\n"))
+fn format_write_reply(header: String, bytes_written: Result<usize, &str>) -> String {
+    match bytes_written {
+        Ok(bytes_written) => format!("{header} {bytes_written}\n"),
+        Err(err) => format!("{header} -1 {err}\n"),
     }
 }
@@ -0,0 +1,310 @@
+//! Instant replay: a bounded buffer of recent frames that can be saved as an animated PNG.
+//!
+//! Frames are downscaled and buffered on a separate thread, and clips are
+//! encoded on yet another one, so the emulation thread only copies frames.
+
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::sync::Arc;
+use std::thread;
+
+use anyhow::{anyhow, bail, Context, Result};
+
+use crate::screenshot;
+use crate::video::Frame;
+
+/// Subdirectory of the screenshot directory replays are saved to, so they
+/// aren't mistaken for still screenshots.
+const REPLAY_DIR: &str = "replays";
+
+/// Frames queued for the buffer thread before new ones are dropped.
+const QUEUE_LEN: usize = 8;
+
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// How many seconds of frames to keep.
+    pub seconds: f64,
+    /// Upper bound for the memory used by buffered frames, in bytes.
+    /// Older frames are dropped first, which can shorten the buffer below `seconds`.
+    pub max_memory: usize,
+    /// Frames wider than this are downscaled by an integer factor.
+    pub max_width: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            seconds: 10.,
+            max_memory: 128 * 1024 * 1024,
+            max_width: 256,
+        }
+    }
+}
+
+/// A handle to a replay buffer, cheap to clone.
+///
+/// The buffer is dropped once all handles are.
+#[derive(Clone)]
+pub struct Replay {
+    tx: SyncSender<Message>,
+}
+
+enum Message {
+    Frame(Frame),
+    /// The core duplicated the previous frame.
+    Repeat,
+    Save {
+        seconds: Option<f64>,
+        reply_tx: SyncSender<Result<Vec<u8>>>,
+    },
+}
+
+impl Replay {
+    /// Starts a replay buffer for content running at `fps`.
+    pub fn start(config: Config, fps: f64) -> Self {
+        let (tx, rx) = sync_channel(QUEUE_LEN);
+        let buffer = Buffer {
+            config,
+            fps,
+            frames: VecDeque::new(),
+            num_bytes: 0,
+            duration: 0,
+        };
+
+        thread::spawn(move || buffer.run(rx));
+
+        Self { tx }
+    }
+
+    /// Adds the frame of one `retro_run`, `None` repeats the previous frame.
+    ///
+    /// Never blocks. If the buffer thread falls behind, frames are dropped.
+    pub fn push_frame(&self, frame: Option<&Frame>) {
+        let message = match frame {
+            Some(frame) => Message::Frame(frame.clone()),
+            None => Message::Repeat,
+        };
+
+        self.tx.try_send(message).ok();
+    }
+
+    /// Encodes the last `seconds` (or everything buffered) as an animated PNG.
+    pub fn save(&self, seconds: Option<f64>) -> Result<Vec<u8>> {
+        let (reply_tx, reply_rx) = sync_channel(1);
+
+        self.tx
+            .send(Message::Save { seconds, reply_tx })
+            .map_err(|_| anyhow!("replay buffer is gone"))?;
+
+        reply_rx
+            .recv()
+            .map_err(|_| anyhow!("replay buffer is gone"))?
+    }
+}
+
+/// Saves an encoded replay to the `replays` directory in `screenshot_directory`,
+/// named after the ROM and the current time.
+pub fn save_to(
+    screenshot_directory: &Path,
+    rom_path: Option<&Path>,
+    png: &[u8],
+) -> Result<PathBuf> {
+    screenshot::save(&screenshot_directory.join(REPLAY_DIR), rom_path, png)
+}
+
+#[derive(Clone)]
+struct ReplayFrame {
+    width: usize,
+    height: usize,
+    /// Packed RGB888
+    pixels: Vec<u8>,
+    /// How many emulated frames this frame was shown for.
+    duration: u32,
+}
+
+struct Buffer {
+    config: Config,
+    fps: f64,
+    frames: VecDeque<Arc<ReplayFrame>>,
+    num_bytes: usize,
+    /// Sum of the durations of all frames.
+    duration: u64,
+}
+
+impl Buffer {
+    fn run(mut self, rx: Receiver<Message>) {
+        for message in rx {
+            match message {
+                Message::Frame(frame) if frame.width == 0 || frame.height == 0 => {}
+                Message::Frame(frame) => self.push(downscale(&frame, self.config.max_width)),
+                Message::Repeat => {
+                    if let Some(last) = self.frames.back_mut() {
+                        Arc::make_mut(last).duration += 1;
+                        self.duration += 1;
+                    }
+                }
+                Message::Save { seconds, reply_tx } => {
+                    let frames = self.last_frames(seconds);
+                    let fps = self.fps;
+
+                    thread::spawn(move || {
+                        reply_tx.send(encode_apng(&frames, fps)).ok();
+                    });
+                }
+            }
+
+            self.evict();
+        }
+    }
+
+    fn push(&mut self, frame: ReplayFrame) {
+        self.num_bytes += frame.pixels.len();
+        self.duration += u64::from(frame.duration);
+        self.frames.push_back(Arc::new(frame));
+    }
+
+    fn evict(&mut self) {
+        let max_duration = (self.config.seconds * self.fps).ceil() as u64;
+
+        while self.num_bytes > self.config.max_memory || self.duration > max_duration {
+            let Some(frame) = self.frames.pop_front() else {
+                break;
+            };
+
+            self.num_bytes -= frame.pixels.len();
+            self.duration -= u64::from(frame.duration);
+        }
+    }
+
+    fn last_frames(&self, seconds: Option<f64>) -> Vec<Arc<ReplayFrame>> {
+        let max_duration = seconds.map_or(u64::MAX, |seconds| (seconds * self.fps).ceil() as u64);
+        let mut duration = 0;
+        let mut frames = self
+            .frames
+            .iter()
+            .rev()
+            .take_while(|frame| {
+                let is_included = duration < max_duration;
+
+                duration += u64::from(frame.duration);
+                is_included
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+
+        frames.reverse();
+        frames
+    }
+}
+
+/// Downscales by the smallest integer factor that makes the frame fit `max_width`.
+fn downscale(frame: &Frame, max_width: usize) -> ReplayFrame {
+    let factor = frame.width.div_ceil(max_width.max(1)).max(1);
+    let pixels = frame.buffer_to_packed_rgb888();
+    // very wide frames would otherwise lose their height entirely
+    let width = (frame.width / factor).max(1);
+    let height = (frame.height / factor).max(1);
+
+    ReplayFrame {
+        width,
+        height,
+        pixels: resize_nearest(&pixels, (frame.width, frame.height), (width, height)),
+        duration: 1,
+    }
+}
+
+/// Resizes packed RGB888 `pixels` with nearest neighbour sampling.
+fn resize_nearest(
+    pixels: &[u8],
+    (source_width, source_height): (usize, usize),
+    (width, height): (usize, usize),
+) -> Vec<u8> {
+    let mut resized = Vec::with_capacity(width * height * 3);
+
+    for y in 0..height {
+        let source_y = y * source_height / height;
+
+        for x in 0..width {
+            let source_x = x * source_width / width;
+            let offset = (source_y * source_width + source_x) * 3;
+
+            resized.extend_from_slice(&pixels[offset..offset + 3]);
+        }
+    }
+
+    resized
+}
+
+/// Encodes frames as an endlessly looping animated PNG the size of the first frame.
+fn encode_apng(frames: &[Arc<ReplayFrame>], fps: f64) -> Result<Vec<u8>> {
+    let Some(first) = frames.first() else {
+        bail!("no frames buffered yet");
+    };
+    let (width, height) = (first.width, first.height);
+
+    if width == 0 || height == 0 {
+        bail!("frames are empty");
+    }
+
+    let mut png = Vec::new();
+    let mut encoder = png::Encoder::new(&mut png, width as u32, height as u32);
+
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_animated(frames.len() as u32, 0)?;
+
+    let mut writer = encoder.write_header().context("failed to encode APNG")?;
+
+    for frame in frames {
+        let delay_ms = (f64::from(frame.duration) * 1000. / fps).round() as u16;
+
+        writer.set_frame_delay(delay_ms, 1000)?;
+
+        if (frame.width, frame.height) == (width, height) {
+            writer.write_image_data(&frame.pixels)?;
+        } else {
+            // the core changed resolution, nearest neighbour is good enough for a clip
+            let pixels =
+                resize_nearest(&frame.pixels, (frame.width, frame.height), (width, height));
+
+            writer.write_image_data(&pixels)?;
+        }
+    }
+
+    writer.finish().context("failed to encode APNG")?;
+
+    Ok(png)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libretro_sys::PixelFormat;
+
+    fn frame(width: usize, height: usize) -> Frame {
+        Frame {
+            buffer: vec![0xFF; width * height * 4],
+            width,
+            height,
+            pitch: width * 4,
+            pixel_format: PixelFormat::ARGB8888,
+        }
+    }
+
+    #[test]
+    fn downscales_to_fit_max_width() {
+        let replay_frame = downscale(&frame(640, 480), 256);
+
+        assert_eq!((replay_frame.width, replay_frame.height), (213, 160));
+        assert_eq!(replay_frame.pixels.len(), 213 * 160 * 3);
+    }
+
+    #[test]
+    fn downscaling_keeps_at_least_one_pixel() {
+        let replay_frame = downscale(&frame(4096, 2), 256);
+
+        assert_eq!((replay_frame.width, replay_frame.height), (256, 1));
+        assert_eq!(replay_frame.pixels.len(), 256 * 3);
+    }
+}
@@ -0,0 +1,52 @@
+//! Saving screenshots as PNG files.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+use crate::{config, util};
+
+/// `ape` in the user's pictures directory, if it is known.
+pub fn default_directory() -> PathBuf {
+    dirs::picture_dir()
+        .map(|pictures| pictures.join("ape"))
+        .unwrap_or_else(|| config::data_directory().join("screenshots"))
+}
+
+/// Encodes tightly packed 8 bit per channel pixels as PNG.
+pub fn encode_png(
+    width: usize,
+    height: usize,
+    color_type: png::ColorType,
+    pixels: &[u8],
+) -> Result<Vec<u8>> {
+    let mut png = Vec::new();
+    let mut encoder = png::Encoder::new(&mut png, width as u32, height as u32);
+
+    encoder.set_color(color_type);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(pixels))
+        .context("failed to encode PNG")?;
+
+    Ok(png)
+}
+
+/// Saves a PNG to `directory`, named after the ROM and the current time.
+pub fn save(directory: &Path, rom_path: Option<&Path>, png: &[u8]) -> Result<PathBuf> {
+    fs::create_dir_all(directory)
+        .with_context(|| format!("failed to create screenshot directory {directory:?}"))?;
+
+    let name = rom_path
+        .and_then(Path::file_stem)
+        .map(|name| name.to_string_lossy())
+        .unwrap_or("screenshot".into());
+    let path = directory.join(format!("{name}-{}.png", util::timestamp()));
+
+    fs::write(&path, png).with_context(|| format!("failed to write screenshot {path:?}"))?;
+
+    Ok(path)
+}
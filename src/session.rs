@@ -0,0 +1,50 @@
+use anyhow::Result;
+
+use crate::core::{Config, Core, Handle, Host};
+
+/// A core together with the host serving its [`Handle`]s.
+///
+/// Unlike [`Core::load`] a session is not tied to a closure scope: it can be
+/// stored, stepped frame by frame, queried and dropped, which unloads the core.
+/// Like [`Core`] it has to stay on the thread that created it. Requests from
+/// handles are served whenever the session is stepped or [`served`](Self::serve).
+pub struct Session {
+    core: Core,
+    host: Host,
+}
+
+impl Session {
+    pub fn new(config: Config) -> Result<Self> {
+        Ok(Self {
+            core: Core::new(config)?,
+            host: Host::new(),
+        })
+    }
+
+    /// Serves pending handle requests and runs a single frame, unless paused.
+    pub fn step(&mut self) {
+        self.serve();
+
+        if !self.core.is_paused() {
+            self.core.run();
+        }
+    }
+
+    /// Serves pending handle requests without running a frame.
+    pub fn serve(&mut self) {
+        self.host.run_pending(&mut self.core);
+    }
+
+    /// A handle for accessing the core from other threads.
+    pub fn handle(&self) -> Handle {
+        self.host.handle()
+    }
+
+    pub fn core(&self) -> &Core {
+        &self.core
+    }
+
+    pub fn core_mut(&mut self) -> &mut Core {
+        &mut self.core
+    }
+}
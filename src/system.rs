@@ -0,0 +1,206 @@
+use std::ffi::c_uint;
+use std::path::Path;
+
+use libretro_sys::{MEMORY_SAVE_RAM, MEMORY_SYSTEM_RAM, MEMORY_VIDEO_RAM};
+use strum::{EnumIter, IntoEnumIterator};
+
+use crate::core::Core;
+
+/// Offset of the Nintendo logo in a Game Boy (Color) cartridge header.
+const GB_LOGO_OFFSET: usize = 0x104;
+const GB_LOGO_START: [u8; 4] = [0xCE, 0xED, 0x66, 0x66];
+/// Offset of the CGB flag in a Game Boy (Color) cartridge header.
+const GB_CGB_FLAG_OFFSET: usize = 0x143;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, EnumIter)]
+pub enum System {
+    GameBoy,
+    GameBoyColor,
+    GameBoyAdvance,
+    Nes,
+    Snes,
+    N64,
+    Genesis,
+    Sms,
+    GameGear,
+    PlayStation,
+    Nds,
+    PcEngine,
+    Saturn,
+    VirtualBoy,
+    NeoGeoPocket,
+    WonderSwan,
+    Atari2600,
+    Atari7800,
+    Lynx,
+}
+
+pub struct DefaultCore {
+    /// Name of the core as used for its library file, e.g. `gambatte`.
+    pub name: &'static str,
+    /// Name the core reports in `retro_get_system_info`, e.g. `Gambatte`.
+    pub library_name: &'static str,
+}
+
+/// Where a `libretro_sys::MEMORY_*` region appears on the system bus.
+pub struct BusLocation {
+    pub start: usize,
+    /// Size of the address window, larger regions are truncated to it.
+    pub len: usize,
+}
+
+impl System {
+    /// Detects the system of the loaded content from the core and, where
+    /// cores emulate more than one system, the ROM header.
+    pub fn detect(core: &Core) -> Option<Self> {
+        let is_gb_rom = core.rom(|rom| {
+            rom.get(GB_LOGO_OFFSET..GB_LOGO_OFFSET + GB_LOGO_START.len()) == Some(&GB_LOGO_START)
+        });
+
+        if is_gb_rom {
+            // bit 7 is set for both CGB enhanced (0x80) and CGB only (0xC0) carts
+            let is_cgb = core.rom(|rom| {
+                rom.get(GB_CGB_FLAG_OFFSET)
+                    .is_some_and(|flag| flag & 0x80 != 0)
+            });
+
+            return Some(if is_cgb {
+                System::GameBoyColor
+            } else {
+                System::GameBoy
+            });
+        }
+
+        let system_id = core.get_system_info().system_id?;
+
+        Some(match system_id {
+            "game_boy" => System::GameBoy,
+            "game_boy_advance" => System::GameBoyAdvance,
+            "nes" => System::Nes,
+            "super_nes" => System::Snes,
+            "n64" => System::N64,
+            "mega_drive" => System::Genesis,
+            "master_system" => {
+                let is_game_gear = core
+                    .rom_path()
+                    .and_then(|rom_path| Self::from_path(&rom_path))
+                    == Some(System::GameGear);
+
+                if is_game_gear {
+                    System::GameGear
+                } else {
+                    System::Sms
+                }
+            }
+            "playstation" => System::PlayStation,
+            "nds" => System::Nds,
+            "pc_engine" => System::PcEngine,
+            "sega_saturn" => System::Saturn,
+            "virtual_boy" => System::VirtualBoy,
+            "neo_geo_pocket" => System::NeoGeoPocket,
+            "wonderswan" => System::WonderSwan,
+            "atari_2600" => System::Atari2600,
+            "atari_7800" => System::Atari7800,
+            "atari_lynx" => System::Lynx,
+            _ => return None,
+        })
+    }
+
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?;
+
+        Self::from_extension(extension)
+    }
+
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        let extension = extension.to_ascii_lowercase();
+
+        Some(match &*extension {
+            "gb" => System::GameBoy,
+            "gbc" => System::GameBoyColor,
+            "gba" => System::GameBoyAdvance,
+            "nes" => System::Nes,
+            "sfc" | "smc" => System::Snes,
+            "n64" | "z64" | "v64" => System::N64,
+            "md" | "gen" => System::Genesis,
+            "sms" => System::Sms,
+            "gg" => System::GameGear,
+            "nds" => System::Nds,
+            "pce" => System::PcEngine,
+            "vb" => System::VirtualBoy,
+            "ngp" | "ngc" => System::NeoGeoPocket,
+            "ws" | "wsc" => System::WonderSwan,
+            "a26" => System::Atari2600,
+            "a78" => System::Atari7800,
+            "lnx" => System::Lynx,
+            _ => return None,
+        })
+    }
+
+    /// The system id BizHawk reports for this system.
+    pub fn bizhawk_id(self) -> &'static str {
+        match self {
+            System::GameBoy => "GB",
+            System::GameBoyColor => "GBC",
+            System::GameBoyAdvance => "GBA",
+            System::Nes => "NES",
+            System::Snes => "SNES",
+            System::N64 => "N64",
+            System::Genesis => "GEN",
+            System::Sms => "SMS",
+            System::GameGear => "GG",
+            System::PlayStation => "PSX",
+            System::Nds => "NDS",
+            System::PcEngine => "PCE",
+            System::Saturn => "SAT",
+            System::VirtualBoy => "VB",
+            System::NeoGeoPocket => "NGP",
+            System::WonderSwan => "WSWAN",
+            System::Atari2600 => "A26",
+            System::Atari7800 => "A78",
+            System::Lynx => "Lynx",
+        }
+    }
+
+    /// The core ape picks for this system if none is specified.
+    pub fn default_core(self) -> Option<DefaultCore> {
+        Some(match self {
+            System::GameBoy | System::GameBoyColor => DefaultCore {
+                name: "gambatte",
+                library_name: "Gambatte",
+            },
+            System::GameBoyAdvance => DefaultCore {
+                name: "mgba",
+                library_name: "mGBA",
+            },
+            _ => return None,
+        })
+    }
+
+    /// The bus location of a memory region for systems whose cores commonly
+    /// don't provide a memory map.
+    pub fn region_location(self, region: c_uint) -> Option<BusLocation> {
+        let (start, len) = match (self, region) {
+            (System::GameBoy | System::GameBoyColor, MEMORY_SYSTEM_RAM) => (0xC000, 0x2000),
+            (System::GameBoy | System::GameBoyColor, MEMORY_SAVE_RAM) => (0xA000, 0x2000),
+            (System::GameBoy | System::GameBoyColor, MEMORY_VIDEO_RAM) => (0x8000, 0x2000),
+            (System::GameBoyAdvance, MEMORY_SYSTEM_RAM) => (0x0200_0000, 0x4_0000),
+            (System::GameBoyAdvance, MEMORY_SAVE_RAM) => (0x0E00_0000, 0x1_0000),
+            (System::GameBoyAdvance, MEMORY_VIDEO_RAM) => (0x0600_0000, 0x1_8000),
+            (System::Nes, MEMORY_SYSTEM_RAM) => (0x0000, 0x800),
+            (System::Nes, MEMORY_SAVE_RAM) => (0x6000, 0x2000),
+            (System::Snes, MEMORY_SYSTEM_RAM) => (0x7E_0000, 0x2_0000),
+            (System::N64, MEMORY_SYSTEM_RAM) => (0x8000_0000, 0x80_0000),
+            (System::Genesis, MEMORY_SYSTEM_RAM) => (0xFF_0000, 0x1_0000),
+            (System::Sms | System::GameGear, MEMORY_SYSTEM_RAM) => (0xC000, 0x2000),
+            (System::Atari2600, MEMORY_SYSTEM_RAM) => (0x80, 0x80),
+            _ => return None,
+        };
+
+        Some(BusLocation { start, len })
+    }
+
+    pub fn with_default_core() -> impl Iterator<Item = (System, DefaultCore)> {
+        System::iter().filter_map(|system| Some((system, system.default_core()?)))
+    }
+}
@@ -1,85 +1,216 @@
 use std::fs;
+use std::io::Write;
 use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use anyhow::{Context, Result};
-
-use crate::buildbot;
+use atomicwrites::{AtomicFile, OverwriteBehavior};
+use itertools::Itertools;
+
+use crate::archive;
+use crate::buildbot::Source;
+use crate::core_info;
+use crate::cores::CoreStore;
+use crate::system::System;
+
+/// The cores that can run content with `extension`: the default core of the
+/// extension's system first, then the supporting cores in the core database.
+fn core_names_for_extension(extension: &str) -> Vec<&'static str> {
+    let default_core = System::from_extension(extension)
+        .and_then(System::default_core)
+        .map(|default_core| default_core.name);
+
+    default_core
+        .into_iter()
+        .chain(
+            core_info::database()
+                .for_extension(extension)
+                .map(|core| core.name.as_str()),
+        )
+        .unique()
+        .collect()
+}
 
 fn guess_core_name_from_extension(extension: &str) -> Option<&'static str> {
-    let extension = extension.to_ascii_lowercase();
+    core_names_for_extension(extension).first().copied()
+}
+
+/// The path whose extension identifies the system: the ROM inside an archive,
+/// if there is exactly one the system of which is known, else `rom` itself.
+fn content_path(rom: &Path) -> Result<PathBuf> {
+    let Some((archive_path, entry)) = archive::split(rom) else {
+        return Ok(rom.to_owned());
+    };
 
-    Some(match &*extension {
-        "gb" | "gbc" => "gambatte",
-        "gba" => "mgba",
-        _ => return None,
+    if let Some(entry) = entry {
+        return Ok(PathBuf::from(entry));
+    }
+
+    let mut known_roms = archive::entry_names(archive_path)?
+        .into_iter()
+        .map(PathBuf::from)
+        .filter(|name| {
+            name.extension()
+                .and_then(|extension| extension.to_str())
+                .and_then(guess_core_name_from_extension)
+                .is_some()
+        })
+        .collect::<Vec<_>>();
+
+    Ok(if known_roms.len() == 1 {
+        known_roms.remove(0)
+    } else {
+        rom.to_owned()
     })
 }
 
-fn core_name_to_library_name(core_name: &str) -> String {
-    format!("{core_name}_libretro.{}", std::env::consts::DLL_EXTENSION)
+/// Finds the core for `rom` in `cores_dir`, downloading it from `source` if it isn't installed yet.
+pub fn find_and_potentially_fetch_core_for_rom(
+    rom: &Path,
+    cores_dir: &Path,
+    source: &Source,
+) -> Result<PathBuf> {
+    let core_name = core_name_for_rom(rom)?;
+
+    CoreStore::open(cores_dir, source.clone())?.find_or_install(core_name)
 }
 
-// TODO: use `dirs` crate or similar
-fn cores_directory() -> PathBuf {
-    PathBuf::from("./cores/")
+fn core_name_for_rom(rom: &Path) -> Result<&'static str> {
+    let extension = rom_extension(rom)?;
+
+    guess_core_name_from_extension(&extension)
+        .with_context(|| format!("no core known to handle `{extension}` roms"))
+}
+
+/// The cores that can run `rom`, most preferred first.
+pub fn core_names_for_rom(rom: &Path) -> Result<Vec<&'static str>> {
+    Ok(core_names_for_extension(&rom_extension(rom)?))
 }
 
-pub fn find_and_potentially_fetch_core_for_rom(rom: &Path) -> Result<PathBuf> {
-    let core_manager = CoreManager::from_rom(rom)?;
-    let library_path = core_manager.find_and_potentially_fetch()?;
+fn rom_extension(rom: &Path) -> Result<String> {
+    let rom = content_path(rom)?;
+    let extension = rom
+        .extension()
+        .context("rom has no extension: rename rom or explicitly specify a core")?
+        .to_str()
+        .context("rom extension is invalid utf-8")?;
 
-    Ok(library_path.to_owned())
+    Ok(extension.to_owned())
 }
 
-struct CoreManager {
-    core_name: String,
-    library_name: String,
-    library_dir: PathBuf,
-    library_path: PathBuf,
+/// The file in `directory` for `rom` with `extension`, e.g.
+/// `saves/Tetris (World).gb-1a2b3c4d.sram`.
+///
+/// The name keeps the ROM's extension, so `Tetris.gb` and `Tetris.gbc` don't
+/// share files, and adds a hash of the ROM's absolute path, so neither do ROMs
+/// of the same name in different directories or archive entries.
+pub fn file_for_rom(directory: &Path, rom: &Path, extension: &str) -> PathBuf {
+    let (file, entry) = archive::split(rom).unwrap_or((rom, None));
+    let absolute = fs::canonicalize(file).unwrap_or_else(|_| file.to_owned());
+    let mut hasher = crc32fast::Hasher::new();
+
+    hasher.update(absolute.as_os_str().as_encoded_bytes());
+    hasher.update(entry.unwrap_or_default().as_bytes());
+
+    let mut name = file.file_name().unwrap_or(file.as_os_str()).to_owned();
+
+    name.push(format!("-{:08x}.{extension}", hasher.finalize()));
+
+    directory.join(name)
 }
 
-impl CoreManager {
-    fn new(core_name: impl Into<String>) -> Self {
-        let core_name = core_name.into();
-        let library_name = core_name_to_library_name(&core_name);
-        let library_dir = cores_directory();
-        let library_path = library_dir.join(&library_name);
-
-        Self {
-            core_name,
-            library_name,
-            library_dir,
-            library_path,
-        }
-    }
+/// The current UTC time as `YYYY-MM-DD_HH-MM-SS-mmm`, sortable and safe for file names.
+pub fn timestamp() -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let secs = now.as_secs();
+    let (year, month, day) = civil_from_days((secs / 86400) as i64);
+    let secs_of_day = secs % 86400;
+
+    format!(
+        "{year:04}-{month:02}-{day:02}_{:02}-{:02}-{:02}-{:03}",
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60,
+        now.subsec_millis(),
+    )
+}
 
-    fn from_rom(rom: &Path) -> Result<Self> {
-        let extension = rom
-            .extension()
-            .context("rom has no extension: rename rom or explicitly specify a core")?
-            .to_str()
-            .context("rom extension is invalid utf-8")?;
+/// Converts days since the Unix epoch to a proleptic Gregorian date,
+/// see <https://howardhinnant.github.io/date_algorithms.html#civil_from_days>.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    (year, month, day)
+}
 
-        let core_name = guess_core_name_from_extension(extension)
-            .with_context(|| format!("no core known to handle `{extension}` roms"))?;
+/// Replaces the file at `path` with `data`, never leaving it partially written.
+pub fn write_atomically(path: &Path, data: &[u8]) -> Result<()> {
+    AtomicFile::new(path, OverwriteBehavior::AllowOverwrite)
+        .write(|file| file.write_all(data))
+        .with_context(|| format!("failed to write {path:?}"))
+}
 
-        let this = Self::new(core_name);
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
 
-        Ok(this)
-    }
+    use super::*;
 
-    fn find_and_potentially_fetch(&self) -> Result<&Path> {
-        if !self.library_path.exists() {
-            println!("Downloading `{}`…", self.library_name);
+    const ROMS_ZIP: &[u8] = include_bytes!("../tests/fixtures/archive/roms.zip");
 
-            fs::create_dir_all(&self.library_dir).context("failed to create core directory")?;
+    #[test]
+    fn names_files_uniquely_per_rom() {
+        let temp = TempDir::new().unwrap();
+        let dir = temp.path();
 
-            buildbot::download_core_to(&self.library_name, &self.library_path)
-                .context("failed to download core")?;
+        fs::create_dir_all(dir.join("other")).unwrap();
 
-            println!("Download successful!");
+        for rom in ["Tetris.gb", "Tetris.gbc", "other/Tetris.gb"] {
+            fs::write(dir.join(rom), [0]).unwrap();
         }
 
-        Ok(&self.library_path)
+        fs::write(dir.join("roms.zip"), ROMS_ZIP).unwrap();
+
+        let saves = Path::new("saves");
+        let roms = [
+            dir.join("Tetris.gb"),
+            dir.join("Tetris.gbc"),
+            dir.join("other/Tetris.gb"),
+            dir.join("roms.zip#Game (World).gb"),
+            dir.join("roms.zip#dir/Other (World).gbc"),
+        ];
+        let files = roms
+            .iter()
+            .map(|rom| file_for_rom(saves, rom, "sram"))
+            .collect::<Vec<_>>();
+
+        assert_eq!(files.iter().unique().count(), roms.len());
+        assert!(files.iter().all(|file| file.parent() == Some(saves)));
+
+        let name = files[1].file_name().unwrap().to_str().unwrap();
+
+        assert!(name.starts_with("Tetris.gbc-"), "{name}");
+        assert!(name.ends_with(".sram"), "{name}");
+        assert!(files[4].to_str().unwrap().contains("roms.zip-"));
+
+        // the same ROM by another path
+        let relative = dir.join("other/../Tetris.gbc");
+
+        assert_eq!(file_for_rom(saves, &relative, "sram"), files[1]);
     }
 }
@@ -1,14 +1,18 @@
 use std::ffi::{c_uint, c_void};
 use std::slice;
 
+use anyhow::Result;
 use itertools::Itertools;
 use libretro_sys::PixelFormat;
 
+use crate::screenshot;
+
 pub type R8 = u8;
 pub type G8 = u8;
 pub type B8 = u8;
 pub type A8 = u8;
 
+#[derive(Clone)]
 pub struct Frame {
     pub buffer: Vec<u8>,
     pub width: usize,
@@ -28,7 +32,7 @@ impl Frame {
         }
     }
 
-    pub unsafe fn from_raw(
+    pub(crate) unsafe fn from_raw(
         data: *const c_void,
         width: c_uint,
         height: c_uint,
@@ -53,6 +57,16 @@ impl Frame {
         })
     }
 
+    /// Encodes the frame at its native resolution as PNG.
+    pub fn to_png(&self) -> Result<Vec<u8>> {
+        screenshot::encode_png(
+            self.width,
+            self.height,
+            png::ColorType::Rgb,
+            &self.buffer_to_packed_rgb888(),
+        )
+    }
+
     pub fn buffer_to_packed_rgb888(&self) -> Vec<u8> {
         let len = self.width * self.height * 3;
         let mut pixels = Vec::with_capacity(len);